@@ -1,4 +1,4 @@
-use rustic::{Cookie, Request, Response, StatusCode};
+use rustic::{Cookie, Request, Response, StatusCode, WithCookies};
 
 async fn retrieve_cookie(req: Request) -> rustic::Result<String> {
     Ok(format!("hello cookies: {:?}", req.cookie("hello").unwrap()))
@@ -10,6 +10,19 @@ async fn insert_cookie(_req: Request) -> rustic::Result {
     Ok(res)
 }
 
+async fn retrieve_signed_cookie(req: Request) -> rustic::Result<String> {
+    Ok(format!(
+        "hello signed cookies: {:?}",
+        req.signed_cookie("signed")
+    ))
+}
+
+async fn insert_signed_cookie(_req: Request) -> rustic::Result {
+    let mut res = Response::new(StatusCode::Ok);
+    res.insert_signed_cookie(Cookie::new("signed", "world"));
+    Ok(res)
+}
+
 async fn remove_cookie(_req: Request) -> rustic::Result {
     let mut res = Response::new(StatusCode::Ok);
     res.remove_cookie(Cookie::named("hello"));
@@ -22,8 +35,12 @@ async fn main() -> Result<(), std::io::Error> {
 
     let mut app = rustic::new();
 
+    app.with_cookies(|cookies| cookies.key(b"an example key that is at least 32 bytes long"));
+
     app.at("/").get(retrieve_cookie);
     app.at("/set").post(insert_cookie);
+    app.at("/signed").get(retrieve_signed_cookie);
+    app.at("/signed/set").post(insert_signed_cookie);
     app.at("/remove").delete(remove_cookie);
 
     app.listen("127.0.0.1:8080").await?;
@@ -43,7 +43,10 @@ async fn main() -> Result<(), std::io::Error> {
 
     app.with(RequestCounterMiddleware::new(0));
 
-    app.at("/").get(|_| async { Ok("Hello, world!") });
+    app.at("/").get(|req: Request| async move {
+        let RequestCount(count) = req.ext().unwrap();
+        Ok(format!("Hello, request number {}!", count))
+    });
 
     app.listen("127.0.0.1:8080").await?;
     Ok(())
@@ -1,3 +1,5 @@
+use rustic::DirectoryListing;
+
 #[async_std::main]
 async fn main() -> Result<(), std::io::Error> {
     femme::start();
@@ -8,7 +10,7 @@ async fn main() -> Result<(), std::io::Error> {
         .serve_file("src/index.html")
         .expect("file could not be served!");
     app.at("/static/*")
-        .serve_dir("src/static/")
+        .serve_dir_with("src/static/", |dir| dir.listing(DirectoryListing::Html))
         .expect("directory could not be served");
 
     app.listen("127.0.0.1:8080").await?;
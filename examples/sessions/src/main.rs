@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use rustic::{MemoryStore, Request, WithSessions};
+
+async fn visit(mut req: Request) -> rustic::Result<String> {
+    let visits: usize = req.session().get("visits").unwrap_or_default();
+    req.session_mut().insert("visits", visits + 1)?;
+    Ok(format!("you have visited this page {} times", visits + 1))
+}
+
+async fn login(mut req: Request) -> rustic::Result<String> {
+    let mut session = req.session_mut();
+    session.regenerate();
+    session.insert("user", "admin")?;
+    Ok("logged in".to_owned())
+}
+
+async fn logout(mut req: Request) -> rustic::Result<String> {
+    req.session_mut().destroy();
+    Ok("logged out".to_owned())
+}
+
+#[async_std::main]
+async fn main() -> Result<(), std::io::Error> {
+    femme::start();
+
+    let mut app = rustic::new();
+
+    app.with_sessions(MemoryStore::new(), |sessions| {
+        sessions
+            .max_age(Duration::from_secs(24 * 60 * 60))
+            .idle_timeout(Duration::from_secs(30 * 60))
+    });
+
+    app.at("/").get(visit);
+    app.at("/login").post(login);
+    app.at("/logout").post(logout);
+
+    app.listen("127.0.0.1:8080").await?;
+
+    Ok(())
+}
@@ -156,9 +156,9 @@ mod todo {
         let query = "INSERT INTO todo (id, description, done) VALUES ($1, $2, $3)";
 
         sqlx::query(query)
-            .bind(&new_todo.id)
+            .bind(new_todo.id)
             .bind(&new_todo.value)
-            .bind(&new_todo.done)
+            .bind(new_todo.done)
             .execute(&pool)
             .await?;
 
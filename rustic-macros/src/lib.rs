@@ -0,0 +1,117 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use proc_macro::TokenStream;
+use quote::quote;
+use sha2::{Digest, Sha256};
+use syn::{parse_macro_input, LitStr};
+
+/// Include every file below a directory in the binary, as a `rustic::EmbeddedDir`.
+///
+/// The path is relative to the manifest directory of the crate using the macro. Changed
+/// files are picked up on the next build, added or removed files need the crate to be
+/// rebuilt (e.g. by touching the file using the macro).
+///
+/// Files and directories whose name starts with `.`, such as `.env` or `.git`, are left
+/// out, so they can't end up in a distributed binary by accident.
+#[proc_macro]
+pub fn embed_dir(input: TokenStream) -> TokenStream {
+    let path = parse_macro_input!(input as LitStr);
+
+    match expand(&path) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => syn::Error::new(path.span(), e).to_compile_error().into(),
+    }
+}
+
+fn expand(path: &LitStr) -> Result<proc_macro2::TokenStream, String> {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR")
+        .map_err(|_| "CARGO_MANIFEST_DIR is not set".to_owned())?;
+    let dir = Path::new(&manifest_dir).join(path.value());
+    let dir = dir
+        .canonicalize()
+        .map_err(|e| format!("cannot embed {}: {}", dir.display(), e))?;
+
+    let mut files = Vec::new();
+    walk(&dir, &mut files).map_err(|e| format!("cannot embed {}: {}", dir.display(), e))?;
+    files.sort();
+
+    let mut entries = Vec::with_capacity(files.len());
+    for file in files {
+        let contents =
+            fs::read(&file).map_err(|e| format!("cannot embed {}: {}", file.display(), e))?;
+        let hash: String = Sha256::digest(&contents)[..16]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        let relative = file
+            .strip_prefix(&dir)
+            .unwrap_or(&file)
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let absolute = file.to_string_lossy().into_owned();
+
+        entries.push(quote! {
+            ::rustic::EmbeddedFile::new(#relative, include_bytes!(#absolute), #hash)
+        });
+    }
+
+    // A `const` item so the slice is promoted to `'static` wherever the macro is used
+    Ok(quote! {
+        {
+            const FILES: &[::rustic::EmbeddedFile] = &[#(#entries),*];
+            ::rustic::EmbeddedDir::new(FILES)
+        }
+    })
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            walk(&entry.path(), files)?;
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hidden_files_and_directories_are_left_out() {
+        let dir = std::env::temp_dir().join(format!("rustic-macros-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for relative in [
+            "index.html",
+            ".env",
+            ".git/config",
+            "css/app.css",
+            "css/.app.css.swp",
+        ] {
+            let path = dir.join(relative);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let mut files = Vec::new();
+        walk(&dir, &mut files).unwrap();
+        files.sort();
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(files, [dir.join("css/app.css"), dir.join("index.html")]);
+    }
+}
@@ -0,0 +1,301 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    time::SystemTime,
+};
+
+use async_std::task;
+
+pub(crate) const DEFAULT_KEEP: usize = 7;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// When a `FileSink` moves on to a new file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    Never,
+    /// At the first line written on a new day, in UTC.
+    Daily,
+    /// Before a line would take the file past this many bytes.
+    Size(u64),
+}
+
+/// An access log file, rotated logrotate style: the current file is renamed with a `.1`
+/// suffix, the previous `.1` becomes `.2`, and so on, keeping the `keep` newest ones.
+///
+/// Lines are written and files rotated on the blocking thread pool, the executor never
+/// waits on the disk.
+pub struct FileSink {
+    path: PathBuf,
+    rotation: Rotation,
+    keep: usize,
+    state: Mutex<State>,
+}
+
+struct State {
+    file: File,
+    size: u64,
+    /// Days since the epoch of the last write.
+    day: u64,
+}
+
+impl FileSink {
+    /// Open `path` for appending, creating it if needed.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let file = open(&path)?;
+        let metadata = file.metadata()?;
+        let day = metadata.modified().map_or_else(|_| today(), day_of);
+
+        Ok(Self {
+            path,
+            rotation: Rotation::Never,
+            keep: DEFAULT_KEEP,
+            state: Mutex::new(State {
+                file,
+                size: metadata.len(),
+                day,
+            }),
+        })
+    }
+
+    #[must_use]
+    pub fn rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// How many rotated files to keep, older ones are deleted.
+    #[must_use]
+    pub fn keep(mut self, keep: usize) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    /// Append `line` from the blocking thread pool.
+    pub(crate) async fn write(self: Arc<Self>, line: String) -> io::Result<()> {
+        task::spawn_blocking(move || self.write_line(&line)).await
+    }
+
+    /// Append `line`, rotating first if it is due. A failed rotation doesn't lose the
+    /// line, it goes to the file still open and the rotation error is returned after.
+    fn write_line(&self, line: &str) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let line = format!("{}\n", line);
+        let today = today();
+
+        let due = match self.rotation {
+            Rotation::Never => false,
+            Rotation::Daily => state.day != today,
+            Rotation::Size(max_size) => state.size > 0 && state.size + line.len() as u64 > max_size,
+        };
+        let rotated = if due { self.rotate(&mut state) } else { Ok(()) };
+
+        state.day = today;
+        let written = state.file.write_all(line.as_bytes());
+        if written.is_ok() {
+            state.size += line.len() as u64;
+        }
+        rotated.and(written)
+    }
+
+    fn rotate(&self, state: &mut State) -> io::Result<()> {
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                match fs::rename(self.rotated(n), self.rotated(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+
+        state.file = open(&self.path)?;
+        state.size = 0;
+        Ok(())
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn today() -> u64 {
+    day_of(SystemTime::now())
+}
+
+fn day_of(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / SECONDS_PER_DAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::test_dir::TestDir;
+
+    fn read(path: impl AsRef<Path>) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    fn rotated(path: &Path, n: usize) -> PathBuf {
+        PathBuf::from(format!("{}.{}", path.display(), n))
+    }
+
+    #[test]
+    fn lines_are_appended_to_existing_files() {
+        let dir = TestDir::new();
+        let path = dir.file("access.log", "earlier\n");
+
+        let sink = FileSink::open(&path).unwrap();
+        sink.write_line("one").unwrap();
+        sink.write_line("two").unwrap();
+        assert_eq!(read(&path), "earlier\none\ntwo\n");
+    }
+
+    #[test]
+    fn missing_files_are_created() {
+        let dir = TestDir::new();
+        let path = dir.path().join("access.log");
+
+        FileSink::open(&path).unwrap().write_line("one").unwrap();
+        assert_eq!(read(&path), "one\n");
+        assert!(FileSink::open(dir.path().join("missing/access.log")).is_err());
+    }
+
+    #[test]
+    fn size_rotation_happens_before_a_line_would_overflow() {
+        let dir = TestDir::new();
+        let path = dir.path().join("access.log");
+        let sink = FileSink::open(&path).unwrap().rotation(Rotation::Size(8));
+
+        sink.write_line("one").unwrap();
+        sink.write_line("two").unwrap();
+        sink.write_line("three").unwrap();
+        assert_eq!(read(&path), "three\n");
+        assert_eq!(read(rotated(&path, 1)), "one\ntwo\n");
+
+        // a line longer than the limit still goes to an empty file whole
+        sink.write_line("a long line").unwrap();
+        assert_eq!(read(&path), "a long line\n");
+        assert_eq!(read(rotated(&path, 1)), "three\n");
+        assert_eq!(read(rotated(&path, 2)), "one\ntwo\n");
+    }
+
+    #[test]
+    fn size_rotation_counts_what_was_already_there() {
+        let dir = TestDir::new();
+        let path = dir.file("access.log", "earlier\n");
+        let sink = FileSink::open(&path).unwrap().rotation(Rotation::Size(10));
+
+        sink.write_line("one").unwrap();
+        assert_eq!(read(&path), "one\n");
+        assert_eq!(read(rotated(&path, 1)), "earlier\n");
+    }
+
+    #[test]
+    fn only_the_newest_rotated_files_are_kept() {
+        let dir = TestDir::new();
+        let path = dir.path().join("access.log");
+        let sink = FileSink::open(&path)
+            .unwrap()
+            .rotation(Rotation::Size(1))
+            .keep(2);
+
+        for line in ["1", "2", "3", "4"] {
+            sink.write_line(line).unwrap();
+        }
+        assert_eq!(read(&path), "4\n");
+        assert_eq!(read(rotated(&path, 1)), "3\n");
+        assert_eq!(read(rotated(&path, 2)), "2\n");
+        assert!(!rotated(&path, 3).exists());
+    }
+
+    #[test]
+    fn keeping_nothing_truncates() {
+        let dir = TestDir::new();
+        let path = dir.path().join("access.log");
+        let sink = FileSink::open(&path)
+            .unwrap()
+            .rotation(Rotation::Size(1))
+            .keep(0);
+
+        sink.write_line("1").unwrap();
+        sink.write_line("2").unwrap();
+        assert_eq!(read(&path), "2\n");
+        assert!(!rotated(&path, 1).exists());
+    }
+
+    #[test]
+    fn daily_rotation_happens_on_the_first_line_of_a_new_day() {
+        let dir = TestDir::new();
+        let path = dir.path().join("access.log");
+        let sink = FileSink::open(&path).unwrap().rotation(Rotation::Daily);
+
+        sink.write_line("today").unwrap();
+        sink.write_line("still today").unwrap();
+        assert!(!rotated(&path, 1).exists());
+
+        sink.state.lock().unwrap().day -= 1;
+        sink.write_line("tomorrow").unwrap();
+        assert_eq!(read(&path), "tomorrow\n");
+        assert_eq!(read(rotated(&path, 1)), "today\nstill today\n");
+    }
+
+    #[test]
+    fn never_rotating_keeps_one_file() {
+        let dir = TestDir::new();
+        let path = dir.path().join("access.log");
+        let sink = FileSink::open(&path).unwrap();
+
+        sink.state.lock().unwrap().day -= 1;
+        for line in ["1", "2", "3"] {
+            sink.write_line(line).unwrap();
+        }
+        assert_eq!(read(&path), "1\n2\n3\n");
+        assert!(!rotated(&path, 1).exists());
+    }
+
+    #[test]
+    fn failed_rotations_still_write_the_line() {
+        let dir = TestDir::new();
+        let path = dir.path().join("access.log");
+        let sink = FileSink::open(&path)
+            .unwrap()
+            .rotation(Rotation::Size(1))
+            .keep(1);
+
+        sink.write_line("1").unwrap();
+        // A directory in the way of the rotated file makes the rename fail
+        dir.file("access.log.1/blocker", "");
+
+        assert!(sink.write_line("2").is_err());
+        assert_eq!(read(&path), "1\n2\n");
+
+        fs::remove_dir_all(rotated(&path, 1)).unwrap();
+        sink.write_line("3").unwrap();
+        assert_eq!(read(&path), "3\n");
+        assert_eq!(read(rotated(&path, 1)), "1\n2\n");
+    }
+
+    #[async_std::test]
+    async fn lines_are_written_off_the_executor() {
+        let dir = TestDir::new();
+        let path = dir.path().join("access.log");
+        let sink = Arc::new(FileSink::open(&path).unwrap());
+
+        sink.clone().write("one".to_owned()).await.unwrap();
+        sink.write("two".to_owned()).await.unwrap();
+        assert_eq!(read(&path), "one\ntwo\n");
+    }
+}
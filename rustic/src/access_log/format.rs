@@ -0,0 +1,234 @@
+use std::{fmt::Write, net::IpAddr, time::Duration};
+
+use serde_json::json;
+use time::OffsetDateTime;
+
+/// The line written for each request by `LogMiddleware`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Common Log Format:
+    /// `203.0.113.7 - - [19/Oct/2026:13:55:36 +0000] "GET /a?b=c HTTP/1.1" 200 2326`
+    Common,
+    /// Common Log Format followed by the quoted `Referer` and `User-Agent`, as Apache and
+    /// nginx write by default.
+    Combined,
+    /// One JSON object per line, with every field of the record and the latency in
+    /// milliseconds.
+    Json,
+}
+
+/// Everything logged about a request, gathered by `LogMiddleware` once it is answered.
+pub(crate) struct AccessRecord {
+    /// When the request was received.
+    pub(crate) time: OffsetDateTime,
+    pub(crate) remote_addr: Option<IpAddr>,
+    pub(crate) method: String,
+    /// The path and query.
+    pub(crate) target: String,
+    pub(crate) version: String,
+    pub(crate) status: u16,
+    /// `None` for bodies of unknown length.
+    pub(crate) bytes: Option<u64>,
+    pub(crate) referer: Option<String>,
+    pub(crate) user_agent: Option<String>,
+    pub(crate) request_id: Option<String>,
+    pub(crate) duration: Duration,
+}
+
+impl LogFormat {
+    pub(crate) fn format(&self, record: &AccessRecord) -> String {
+        match self {
+            LogFormat::Common => common(record),
+            LogFormat::Combined => format!(
+                "{} \"{}\" \"{}\"",
+                common(record),
+                quoted(record.referer.as_deref()),
+                quoted(record.user_agent.as_deref()),
+            ),
+            LogFormat::Json => {
+                let time = format!(
+                    "{}.{:03}Z",
+                    record.time.format("%Y-%m-%dT%H:%M:%S"),
+                    record.time.millisecond()
+                );
+                json!({
+                    "time": time,
+                    "remote_addr": record.remote_addr.map(|ip| ip.to_string()),
+                    "method": record.method,
+                    "target": record.target,
+                    "version": record.version,
+                    "status": record.status,
+                    "bytes": record.bytes,
+                    "referer": record.referer,
+                    "user_agent": record.user_agent,
+                    "request_id": record.request_id,
+                    "duration_ms": record.duration.as_secs_f64() * 1000.0,
+                })
+                .to_string()
+            }
+        }
+    }
+}
+
+fn common(record: &AccessRecord) -> String {
+    let request_line = format!("{} {} {}", record.method, record.target, record.version);
+    format!(
+        "{} - - [{}] \"{}\" {} {}",
+        record
+            .remote_addr
+            .map_or_else(|| "-".to_owned(), |ip| ip.to_string()),
+        record.time.format("%d/%b/%Y:%H:%M:%S %z"),
+        quoted(Some(&request_line)),
+        record.status,
+        // An empty body is written as `-` too
+        match record.bytes {
+            Some(bytes) if bytes > 0 => bytes.to_string(),
+            _ => "-".to_owned(),
+        },
+    )
+}
+
+/// A field for between double quotes, escaped the way Apache does so that clients can't
+/// forge fields or lines.
+fn quoted(value: Option<&str>) -> String {
+    let value = match value {
+        Some(value) => value,
+        None => return "-".to_owned(),
+    };
+
+    let mut escaped = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => {
+                let _ = write!(escaped, "\\x{:02x}", byte);
+            }
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    fn record() -> AccessRecord {
+        AccessRecord {
+            // 2026-10-19 13:55:36.250 UTC
+            time: OffsetDateTime::from_unix_timestamp_nanos(1_792_418_136_250_000_000),
+            remote_addr: Some("203.0.113.7".parse().unwrap()),
+            method: "GET".to_owned(),
+            target: "/a?b=c".to_owned(),
+            version: "HTTP/1.1".to_owned(),
+            status: 200,
+            bytes: Some(2326),
+            referer: Some("https://example.com/".to_owned()),
+            user_agent: Some("curl/8.0".to_owned()),
+            request_id: Some("req-1".to_owned()),
+            duration: Duration::from_micros(1500),
+        }
+    }
+
+    #[test]
+    fn common_lines_follow_the_clf() {
+        assert_eq!(
+            LogFormat::Common.format(&record()),
+            "203.0.113.7 - - [19/Oct/2026:13:55:36 +0000] \"GET /a?b=c HTTP/1.1\" 200 2326"
+        );
+    }
+
+    #[test]
+    fn combined_lines_add_referer_and_user_agent() {
+        assert_eq!(
+            LogFormat::Combined.format(&record()),
+            "203.0.113.7 - - [19/Oct/2026:13:55:36 +0000] \"GET /a?b=c HTTP/1.1\" 200 2326 \
+             \"https://example.com/\" \"curl/8.0\""
+        );
+    }
+
+    #[test]
+    fn missing_fields_are_dashes() {
+        let record = AccessRecord {
+            remote_addr: None,
+            bytes: None,
+            referer: None,
+            user_agent: None,
+            ..record()
+        };
+        assert_eq!(
+            LogFormat::Combined.format(&record),
+            "- - - [19/Oct/2026:13:55:36 +0000] \"GET /a?b=c HTTP/1.1\" 200 - \"-\" \"-\""
+        );
+
+        let record = AccessRecord {
+            bytes: Some(0),
+            ..self::record()
+        };
+        assert!(LogFormat::Common.format(&record).ends_with(" 200 -"));
+    }
+
+    #[test]
+    fn quoted_fields_cannot_forge_fields_or_lines() {
+        let record = AccessRecord {
+            target: "/\"x\" 404\n".to_owned(),
+            referer: Some("back\\slash".to_owned()),
+            user_agent: Some("tab\there \u{e9}".to_owned()),
+            ..record()
+        };
+        let line = LogFormat::Combined.format(&record);
+
+        assert!(!line.contains('\n'));
+        assert!(
+            line.contains(r#""GET /\"x\" 404\x0a HTTP/1.1""#),
+            "{}",
+            line
+        );
+        assert!(
+            line.ends_with(r#""back\\slash" "tab\x09here \xc3\xa9""#),
+            "{}",
+            line
+        );
+    }
+
+    #[test]
+    fn json_lines_carry_every_field() {
+        let line = LogFormat::Json.format(&record());
+        assert!(!line.contains('\n'));
+
+        let value: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["time"], "2026-10-19T13:55:36.250Z");
+        assert_eq!(value["remote_addr"], "203.0.113.7");
+        assert_eq!(value["method"], "GET");
+        assert_eq!(value["target"], "/a?b=c");
+        assert_eq!(value["version"], "HTTP/1.1");
+        assert_eq!(value["status"], 200);
+        assert_eq!(value["bytes"], 2326);
+        assert_eq!(value["referer"], "https://example.com/");
+        assert_eq!(value["user_agent"], "curl/8.0");
+        assert_eq!(value["request_id"], "req-1");
+        assert_eq!(value["duration_ms"], 1.5);
+    }
+
+    #[test]
+    fn json_escapes_and_nulls_missing_fields() {
+        let record = AccessRecord {
+            target: "/\"x\"\n".to_owned(),
+            remote_addr: None,
+            bytes: None,
+            request_id: None,
+            ..record()
+        };
+        let line = LogFormat::Json.format(&record);
+        assert!(!line.contains('\n'));
+
+        let value: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["target"], "/\"x\"\n");
+        assert!(value["remote_addr"].is_null());
+        assert!(value["bytes"].is_null());
+        assert!(value["request_id"].is_null());
+    }
+}
@@ -0,0 +1,6 @@
+mod file_sink;
+mod format;
+
+pub use file_sink::{FileSink, Rotation};
+pub(crate) use format::AccessRecord;
+pub use format::LogFormat;
@@ -0,0 +1,141 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use http_types::{
+    headers::{HeaderName, HeaderValues, AGE},
+    Body, Headers, StatusCode,
+};
+
+use crate::response::Response;
+
+/// A buffered response stored by the `ResponseCache`.
+#[derive(Clone)]
+pub(crate) struct CachedResponse {
+    status: StatusCode,
+    headers: Headers,
+    body: Arc<Vec<u8>>,
+    /// Request header values the response varies on.
+    vary: Vec<(HeaderName, Option<String>)>,
+    stored_at: Instant,
+    initial_age: Duration,
+    fresh_for: Duration,
+    stale_while_revalidate: Duration,
+    revalidating: Arc<AtomicBool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Freshness {
+    Fresh,
+    /// Stale, but may be served while another request refreshes it.
+    StaleWhileRevalidate,
+    Stale,
+}
+
+impl CachedResponse {
+    pub(crate) fn new(
+        status: StatusCode,
+        headers: Headers,
+        body: Vec<u8>,
+        vary: Vec<(HeaderName, Option<String>)>,
+        initial_age: Duration,
+        fresh_for: Duration,
+        stale_while_revalidate: Duration,
+    ) -> Self {
+        Self {
+            status,
+            headers,
+            body: Arc::new(body),
+            vary,
+            stored_at: Instant::now(),
+            initial_age,
+            fresh_for,
+            stale_while_revalidate,
+            revalidating: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|(name, values)| {
+                name.as_str().len() + values.iter().map(|v| v.as_str().len()).sum::<usize>()
+            })
+            .sum();
+        self.body.len() + headers
+    }
+
+    pub(crate) fn age(&self) -> Duration {
+        self.initial_age + self.stored_at.elapsed()
+    }
+
+    /// `max_age` is the limit the client asked for with `Cache-Control: max-age`.
+    pub(crate) fn freshness(&self, max_age: Option<Duration>) -> Freshness {
+        let age = self.age();
+        let fresh_for = match max_age {
+            Some(max_age) => self.fresh_for.min(max_age),
+            None => self.fresh_for,
+        };
+
+        if age < fresh_for {
+            Freshness::Fresh
+        } else if age < self.fresh_for + self.stale_while_revalidate {
+            Freshness::StaleWhileRevalidate
+        } else {
+            Freshness::Stale
+        }
+    }
+
+    /// Claim the refresh of a stale entry, `None` if another request already did. The
+    /// claim is given up when the returned guard is dropped, whether the refresh stored a
+    /// new response or not.
+    pub(crate) fn start_revalidation(&self) -> Option<Revalidation> {
+        let claimed = !self.revalidating.swap(true, Ordering::AcqRel);
+        claimed.then(|| Revalidation(self.revalidating.clone()))
+    }
+
+    pub(crate) fn matches(&self, request_headers: &Headers) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| header_string(request_headers.get(name)) == *value)
+    }
+
+    pub(crate) fn same_variant(&self, other: &CachedResponse) -> bool {
+        self.vary == other.vary
+    }
+
+    pub(crate) fn to_response(&self, x_cache: &'static str) -> Response {
+        let mut res = http_types::Response::new(self.status);
+        for (name, values) in self.headers.iter() {
+            res.insert_header(name, values);
+        }
+        res.set_body(Body::from_bytes(self.body.to_vec()));
+        res.insert_header(AGE, self.age().as_secs().to_string());
+        res.insert_header("X-Cache", x_cache);
+        res.into()
+    }
+}
+
+/// A claimed refresh of a `CachedResponse`, see `start_revalidation`.
+pub(crate) struct Revalidation(Arc<AtomicBool>);
+
+impl Drop for Revalidation {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+pub(crate) fn header_string(values: Option<&HeaderValues>) -> Option<String> {
+    values.map(|values| {
+        values
+            .iter()
+            .map(|value| value.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    })
+}
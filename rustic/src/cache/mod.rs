@@ -0,0 +1,179 @@
+mod entry;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use http_types::Headers;
+
+pub(crate) use entry::{header_string, CachedResponse, Freshness};
+
+pub(crate) const DEFAULT_MAX_SIZE: usize = 64 * 1024 * 1024;
+
+/// Shared in-memory storage of the `CacheMiddleware`.
+///
+/// Entries are keyed by request host, path and query. The total size of the stored responses is
+/// bounded, least recently used entries are evicted first. Clones share the same storage,
+/// so a clone can be kept around to purge entries.
+#[derive(Clone)]
+pub struct ResponseCache {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    slots: HashMap<String, Slot>,
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+    size: usize,
+    max_size: usize,
+}
+
+/// All variants (see `Vary`) stored for one key.
+struct Slot {
+    variants: Vec<CachedResponse>,
+    tick: u64,
+}
+
+impl Slot {
+    fn size(&self) -> usize {
+        self.variants.iter().map(CachedResponse::size).sum()
+    }
+}
+
+impl ResponseCache {
+    /// Create a cache holding at most `max_size` bytes of responses.
+    #[must_use]
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                slots: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+                size: 0,
+                max_size,
+            })),
+        }
+    }
+
+    /// Remove everything stored for `key`, e.g. `example.com/articles?page=2`.
+    pub fn purge(&self, key: &str) -> bool {
+        self.inner.lock().unwrap().remove(key)
+    }
+
+    /// Remove everything stored for keys starting with `prefix`, e.g. `example.com/articles`.
+    pub fn purge_prefix(&self, prefix: &str) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let keys: Vec<String> = inner
+            .slots
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect();
+
+        for key in &keys {
+            inner.remove(key);
+        }
+        keys.len()
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.slots.clear();
+        inner.recency.clear();
+        inner.size = 0;
+    }
+
+    /// Number of stored keys.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().slots.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Approximate number of bytes stored.
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.inner.lock().unwrap().size
+    }
+
+    /// Look up the variant of `key` matching the request headers.
+    pub(crate) fn get(&self, key: &str, request_headers: &Headers) -> Option<CachedResponse> {
+        let mut inner = self.inner.lock().unwrap();
+        let variant = inner
+            .slots
+            .get(key)?
+            .variants
+            .iter()
+            .find(|variant| variant.matches(request_headers))
+            .cloned()?;
+        inner.touch(key);
+        Some(variant)
+    }
+
+    pub(crate) fn insert(&self, key: String, response: CachedResponse) {
+        let mut inner = self.inner.lock().unwrap();
+        if response.size() > inner.max_size {
+            return;
+        }
+
+        let slot = inner.slots.entry(key.clone()).or_insert_with(|| Slot {
+            variants: Vec::new(),
+            tick: 0,
+        });
+        let before = slot.size();
+        slot.variants
+            .retain(|variant| !variant.same_variant(&response));
+        slot.variants.push(response);
+        let after = slot.size();
+
+        inner.size = inner.size + after - before;
+        inner.touch(&key);
+        inner.evict();
+    }
+}
+
+impl Default for ResponseCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_SIZE)
+    }
+}
+
+impl Inner {
+    fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        let tick = self.tick;
+
+        if let Some(slot) = self.slots.get_mut(key) {
+            self.recency.remove(&slot.tick);
+            slot.tick = tick;
+            self.recency.insert(tick, key.to_owned());
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> bool {
+        match self.slots.remove(key) {
+            Some(slot) => {
+                self.recency.remove(&slot.tick);
+                self.size -= slot.size();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drop least recently used keys until the cache fits its size bound.
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            let key = match self.recency.values().next() {
+                Some(key) => key.clone(),
+                None => break,
+            };
+            self.remove(&key);
+        }
+    }
+}
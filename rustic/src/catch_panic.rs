@@ -0,0 +1,215 @@
+use std::{
+    any::Any,
+    future::Future,
+    panic::{catch_unwind, AssertUnwindSafe},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use http_types::{Error, Method, StatusCode};
+use kv_log_macro::error;
+
+use crate::response::Response;
+
+pub(crate) type PanicHandler = Arc<dyn Fn(&PanicReport) + Send + Sync + 'static>;
+
+/// Details about a panic raised by an endpoint or middleware while handling a request.
+pub struct PanicReport {
+    method: Method,
+    path: String,
+    route: Option<String>,
+    payload: Box<dyn Any + Send + 'static>,
+}
+
+impl PanicReport {
+    #[must_use]
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The pattern of the route that matched the request, e.g. `/users/:id`, `None` when
+    /// no route matched.
+    pub fn route(&self) -> Option<&str> {
+        self.route.as_deref()
+    }
+
+    /// The value the handler panicked with.
+    pub fn payload(&self) -> &(dyn Any + Send + 'static) {
+        self.payload.as_ref()
+    }
+
+    /// The panic message, if the payload is a string (as with `panic!` and friends).
+    pub fn message(&self) -> Option<&str> {
+        if let Some(message) = self.payload.downcast_ref::<&'static str>() {
+            Some(message)
+        } else {
+            self.payload.downcast_ref::<String>().map(String::as_str)
+        }
+    }
+}
+
+/// Stored in the request extensions by `Server::respond` so that every level of the
+/// middleware chain knows where to report panics.
+#[derive(Clone)]
+pub(crate) struct PanicContext {
+    pub(crate) handler: PanicHandler,
+    pub(crate) method: Method,
+    pub(crate) path: Arc<str>,
+    pub(crate) route: Option<Arc<str>>,
+}
+
+impl PanicContext {
+    pub(crate) fn into_response(self, payload: Box<dyn Any + Send + 'static>) -> Response {
+        let report = PanicReport {
+            method: self.method,
+            path: self.path.to_string(),
+            route: self.route.map(|route| route.to_string()),
+            payload,
+        };
+        let message = report.message().unwrap_or("Box<dyn Any>").to_owned();
+        (self.handler)(&report);
+
+        Error::from_str(StatusCode::InternalServerError, message).into()
+    }
+}
+
+pub(crate) fn log_panic(report: &PanicReport) {
+    error!("Request handler panicked", {
+        message: report.message().unwrap_or("Box<dyn Any>"),
+        method: report.method().to_string(),
+        path: report.path(),
+        route: report.route().unwrap_or("-"),
+    });
+}
+
+/// Polls the inner future, turning a panic into an `Err` holding the panic payload.
+pub(crate) struct CatchUnwind<F> {
+    inner: F,
+}
+
+impl<F> CatchUnwind<F> {
+    pub(crate) fn new(inner: F) -> Self {
+        Self { inner }
+    }
+}
+
+impl<F> Future for CatchUnwind<F>
+where
+    F: Future + Unpin,
+{
+    type Output = Result<F::Output, Box<dyn Any + Send + 'static>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let inner = &mut self.inner;
+        match catch_unwind(AssertUnwindSafe(|| Pin::new(inner).poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+    use http_types::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    use super::*;
+    use crate::{Middleware, Next, Request};
+
+    struct Stamp;
+
+    #[async_trait]
+    impl Middleware for Stamp {
+        async fn handle(&self, request: Request, next: Next<'_>) -> crate::Result {
+            let mut res = next.run(request).await;
+            res.insert_header("X-Stamped", "yes");
+            Ok(res)
+        }
+    }
+
+    struct Panicking;
+
+    #[async_trait]
+    impl Middleware for Panicking {
+        async fn handle(&self, _request: Request, _next: Next<'_>) -> crate::Result {
+            panic!("middleware failed");
+        }
+    }
+
+    fn get(path: &str) -> HttpRequest {
+        HttpRequest::new(
+            Method::Get,
+            Url::parse("http://localhost").unwrap().join(path).unwrap(),
+        )
+    }
+
+    #[async_std::test]
+    async fn endpoint_panic_is_reported_and_answered_with_500() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let mut app = crate::new();
+        let seen = reports.clone();
+        app.with_panic_handler(move |report: &PanicReport| {
+            seen.lock().unwrap().push((
+                report.method(),
+                report.path().to_owned(),
+                report.route().map(str::to_owned),
+                report.message().map(str::to_owned),
+            ));
+        });
+        app.with(Stamp);
+        app.at("/users/:id")
+            .get(|_: Request| async move { panic!("boom {}", 42) as crate::Result<String> });
+
+        let res: HttpResponse = app.respond(get("/users/7")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::InternalServerError);
+        // The panic went back up through the middleware that already ran
+        assert_eq!(res.header("X-Stamped").unwrap().as_str(), "yes");
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(
+            reports.as_slice(),
+            [(
+                Method::Get,
+                "/users/7".to_owned(),
+                Some("/users/:id".to_owned()),
+                Some("boom 42".to_owned())
+            )]
+        );
+    }
+
+    #[async_std::test]
+    async fn middleware_panic_is_caught() {
+        let mut app = crate::new();
+        app.with_panic_handler(|_: &PanicReport| {});
+        app.with(Stamp);
+        app.with(Panicking);
+        app.at("/").get(|_: Request| async move { Ok("unreachable") });
+
+        let res: HttpResponse = app.respond(get("/")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::InternalServerError);
+        assert_eq!(res.header("X-Stamped").unwrap().as_str(), "yes");
+    }
+
+    #[async_std::test]
+    async fn report_has_no_route_when_nothing_matched() {
+        let routes = Arc::new(Mutex::new(Vec::new()));
+        let mut app = crate::new();
+        let seen = routes.clone();
+        app.with_panic_handler(move |report: &PanicReport| {
+            seen.lock().unwrap().push(report.route().map(str::to_owned));
+        });
+        app.with(Panicking);
+
+        let res: HttpResponse = app.respond(get("/missing")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::InternalServerError);
+        assert_eq!(routes.lock().unwrap().as_slice(), [None]);
+    }
+}
@@ -0,0 +1,345 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use http_types::{
+    conditional::{ETag, IfMatch, IfModifiedSince, IfNoneMatch, IfUnmodifiedSince, LastModified},
+    headers::{self, HeaderName, Headers},
+    Method, StatusCode,
+};
+
+use crate::Response;
+
+/// Headers a `304 Not Modified` repeats from the full response.
+const NOT_MODIFIED_HEADERS: [HeaderName; 6] = [
+    headers::CACHE_CONTROL,
+    headers::CONTENT_LOCATION,
+    headers::ETAG,
+    headers::EXPIRES,
+    headers::LAST_MODIFIED,
+    headers::VARY,
+];
+
+/// What the conditional headers of a request ask for, given the current validators.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Precondition {
+    Proceed,
+    NotModified,
+    Failed,
+}
+
+/// The validators of the selected representation.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Validators {
+    pub(crate) etag: Option<ETag>,
+    pub(crate) last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    /// Validators set by an endpoint, invalid headers are ignored.
+    pub(crate) fn from_headers(headers: impl AsRef<Headers>) -> Self {
+        let headers = headers.as_ref();
+        Self {
+            etag: ETag::from_headers(headers).ok().flatten(),
+            last_modified: LastModified::from_headers(headers)
+                .ok()
+                .flatten()
+                .map(|last_modified| last_modified.modified()),
+        }
+    }
+
+    pub(crate) fn apply(&self, mut headers: impl AsMut<Headers>) {
+        let headers = headers.as_mut();
+        if let Some(etag) = &self.etag {
+            etag.apply(&mut *headers);
+        }
+        if let Some(last_modified) = self.last_modified {
+            LastModified::new(last_modified).apply(headers);
+        }
+    }
+
+    /// Evaluate the preconditions of `req` in the order RFC 9110 section 13.2.2 defines.
+    pub(crate) fn evaluate(&self, req: &http_types::Request) -> Precondition {
+        let is_get = matches!(req.method(), Method::Get | Method::Head);
+
+        if let Some(if_match) = IfMatch::from_headers(req).ok().flatten() {
+            let matches = if_match.wildcard()
+                || if_match
+                    .iter()
+                    .any(|etag| strong_match(self.etag.as_ref(), etag));
+            if !matches {
+                return Precondition::Failed;
+            }
+        } else if let Some(since) = IfUnmodifiedSince::from_headers(req).ok().flatten() {
+            if let Some(last_modified) = self.last_modified {
+                if unix_secs(last_modified) > unix_secs(since.modified()) {
+                    return Precondition::Failed;
+                }
+            }
+        }
+
+        if let Some(if_none_match) = IfNoneMatch::from_headers(req).ok().flatten() {
+            let matches = if_none_match.wildcard()
+                || if_none_match
+                    .iter()
+                    .any(|etag| weak_match(self.etag.as_ref(), etag));
+            if matches {
+                return if is_get {
+                    Precondition::NotModified
+                } else {
+                    Precondition::Failed
+                };
+            }
+        } else if is_get {
+            if let Some(since) = IfModifiedSince::from_headers(req).ok().flatten() {
+                if let Some(last_modified) = self.last_modified {
+                    if unix_secs(last_modified) <= unix_secs(since.modified()) {
+                        return Precondition::NotModified;
+                    }
+                }
+            }
+        }
+
+        Precondition::Proceed
+    }
+}
+
+/// Evaluate the preconditions of `req`, with `current` being `None` while the resource
+/// doesn't exist.
+///
+/// Nothing matches a missing resource, not even `If-Match: *`, so it fails any `If-Match`
+/// and passes any `If-None-Match`, which is how `If-None-Match: *` makes a `PUT` create-only.
+pub(crate) fn evaluate(current: Option<&Validators>, req: &http_types::Request) -> Precondition {
+    match current {
+        Some(validators) => validators.evaluate(req),
+        None if req.header(headers::IF_MATCH).is_some() => Precondition::Failed,
+        None => Precondition::Proceed,
+    }
+}
+
+/// Whether `req` carries any precondition header at all.
+pub(crate) fn is_conditional(req: &http_types::Request) -> bool {
+    [
+        headers::IF_MATCH,
+        headers::IF_NONE_MATCH,
+        headers::IF_MODIFIED_SINCE,
+        headers::IF_UNMODIFIED_SINCE,
+    ]
+    .iter()
+    .any(|name| req.header(name).is_some())
+}
+
+/// The response to send instead of `res` for `precondition`, if any.
+pub(crate) fn respond(precondition: Precondition, res: &http_types::Response) -> Option<Response> {
+    match precondition {
+        Precondition::Proceed => None,
+        Precondition::NotModified => {
+            let mut not_modified = http_types::Response::new(StatusCode::NotModified);
+            for name in &NOT_MODIFIED_HEADERS {
+                if let Some(values) = res.header(name) {
+                    not_modified.insert_header(name, values);
+                }
+            }
+            Some(not_modified.into())
+        }
+        Precondition::Failed => Some(Response::new(StatusCode::PreconditionFailed)),
+    }
+}
+
+fn strong_match(current: Option<&ETag>, requested: &ETag) -> bool {
+    matches!(
+        (current, requested),
+        (Some(ETag::Strong(current)), ETag::Strong(requested)) if current == requested
+    )
+}
+
+fn weak_match(current: Option<&ETag>, requested: &ETag) -> bool {
+    current.is_some_and(|current| opaque_tag(current) == opaque_tag(requested))
+}
+
+fn opaque_tag(etag: &ETag) -> &str {
+    match etag {
+        ETag::Strong(tag) | ETag::Weak(tag) => tag,
+    }
+}
+
+/// HTTP dates only have second precision.
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use http_types::Url;
+
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn validators(etag: Option<ETag>, last_modified: Option<u64>) -> Validators {
+        Validators {
+            etag,
+            last_modified: last_modified.map(at),
+        }
+    }
+
+    fn request(method: Method, headers: &[(HeaderName, String)]) -> http_types::Request {
+        let mut req = http_types::Request::new(method, Url::parse("http://localhost/").unwrap());
+        for (name, value) in headers {
+            req.insert_header(name, value.as_str());
+        }
+        req
+    }
+
+    fn http_date(secs: u64) -> String {
+        let mut headers = http_types::Response::new(200);
+        LastModified::new(at(secs)).apply(&mut headers);
+        headers
+            .header(headers::LAST_MODIFIED)
+            .unwrap()
+            .as_str()
+            .to_owned()
+    }
+
+    #[test]
+    fn if_none_match_uses_weak_comparison() {
+        let current = validators(Some(ETag::new_weak("a".into())), None);
+        let get = |value: &str| request(Method::Get, &[(headers::IF_NONE_MATCH, value.into())]);
+
+        assert_eq!(current.evaluate(&get("\"a\"")), Precondition::NotModified);
+        assert_eq!(current.evaluate(&get("W/\"a\"")), Precondition::NotModified);
+        assert_eq!(
+            current.evaluate(&get("\"b\", \"a\"")),
+            Precondition::NotModified
+        );
+        assert_eq!(current.evaluate(&get("*")), Precondition::NotModified);
+        assert_eq!(current.evaluate(&get("\"b\"")), Precondition::Proceed);
+
+        // Other methods fail instead
+        let put = request(Method::Put, &[(headers::IF_NONE_MATCH, "*".into())]);
+        assert_eq!(current.evaluate(&put), Precondition::Failed);
+    }
+
+    #[test]
+    fn if_match_uses_strong_comparison() {
+        let if_match = |value: &str| request(Method::Put, &[(headers::IF_MATCH, value.into())]);
+
+        let strong = validators(Some(ETag::new("a".into())), None);
+        assert_eq!(strong.evaluate(&if_match("\"a\"")), Precondition::Proceed);
+        assert_eq!(strong.evaluate(&if_match("W/\"a\"")), Precondition::Failed);
+        assert_eq!(strong.evaluate(&if_match("\"b\"")), Precondition::Failed);
+
+        let weak = validators(Some(ETag::new_weak("a".into())), None);
+        assert_eq!(weak.evaluate(&if_match("\"a\"")), Precondition::Failed);
+
+        let missing = validators(None, None);
+        assert_eq!(missing.evaluate(&if_match("*")), Precondition::Proceed);
+        assert_eq!(missing.evaluate(&if_match("\"a\"")), Precondition::Failed);
+    }
+
+    #[test]
+    fn missing_resources_match_nothing() {
+        let put = |name: HeaderName, value: &str| request(Method::Put, &[(name, value.into())]);
+
+        assert_eq!(
+            evaluate(None, &put(headers::IF_MATCH, "*")),
+            Precondition::Failed
+        );
+        assert_eq!(
+            evaluate(None, &put(headers::IF_MATCH, "\"a\"")),
+            Precondition::Failed
+        );
+        assert_eq!(
+            evaluate(None, &put(headers::IF_NONE_MATCH, "*")),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            evaluate(None, &put(headers::IF_UNMODIFIED_SINCE, &http_date(0))),
+            Precondition::Proceed
+        );
+
+        // Once it exists, `*` matches it
+        let current = validators(None, None);
+        assert_eq!(
+            evaluate(Some(&current), &put(headers::IF_MATCH, "*")),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            evaluate(Some(&current), &put(headers::IF_NONE_MATCH, "*")),
+            Precondition::Failed
+        );
+    }
+
+    #[test]
+    fn dates_compare_in_whole_seconds() {
+        let current = Validators {
+            etag: None,
+            last_modified: Some(at(1_000) + Duration::from_millis(500)),
+        };
+        let since = |name: HeaderName, secs: u64| request(Method::Get, &[(name, http_date(secs))]);
+
+        assert_eq!(
+            current.evaluate(&since(headers::IF_MODIFIED_SINCE, 1_000)),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            current.evaluate(&since(headers::IF_MODIFIED_SINCE, 999)),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            current.evaluate(&since(headers::IF_UNMODIFIED_SINCE, 1_000)),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            current.evaluate(&since(headers::IF_UNMODIFIED_SINCE, 999)),
+            Precondition::Failed
+        );
+    }
+
+    #[test]
+    fn etags_take_precedence_over_dates() {
+        let current = validators(Some(ETag::new("a".into())), Some(1_000));
+
+        // A matching If-Match makes If-Unmodified-Since irrelevant
+        let req = request(
+            Method::Put,
+            &[
+                (headers::IF_MATCH, "\"a\"".into()),
+                (headers::IF_UNMODIFIED_SINCE, http_date(0)),
+            ],
+        );
+        assert_eq!(current.evaluate(&req), Precondition::Proceed);
+
+        // A changed ETag wins over an unchanged date
+        let req = request(
+            Method::Get,
+            &[
+                (headers::IF_NONE_MATCH, "\"b\"".into()),
+                (headers::IF_MODIFIED_SINCE, http_date(2_000)),
+            ],
+        );
+        assert_eq!(current.evaluate(&req), Precondition::Proceed);
+    }
+
+    #[test]
+    fn not_modified_keeps_the_validators() {
+        let mut res = http_types::Response::new(StatusCode::Ok);
+        res.insert_header(headers::ETAG, "\"a\"");
+        res.insert_header(headers::CACHE_CONTROL, "max-age=60");
+        res.insert_header(headers::CONTENT_TYPE, "text/plain");
+
+        let not_modified = respond(Precondition::NotModified, &res).unwrap();
+        assert_eq!(not_modified.status(), StatusCode::NotModified);
+        assert_eq!(not_modified.res.header(headers::ETAG).unwrap(), "\"a\"");
+        assert!(not_modified.res.header(headers::CACHE_CONTROL).is_some());
+        assert!(not_modified.res.header(headers::CONTENT_TYPE).is_none());
+
+        let failed = respond(Precondition::Failed, &res).unwrap();
+        assert_eq!(failed.status(), StatusCode::PreconditionFailed);
+        assert!(respond(Precondition::Proceed, &res).is_none());
+    }
+}
@@ -77,6 +77,13 @@ where
     }
 }
 
+#[async_trait]
+impl<E: Endpoint> Endpoint for Arc<E> {
+    async fn call(&self, request: Request) -> crate::Result {
+        self.as_ref().call(request).await
+    }
+}
+
 #[async_trait]
 impl Endpoint for Box<dyn Endpoint> {
     async fn call(&self, request: Request) -> crate::Result {
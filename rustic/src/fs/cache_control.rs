@@ -0,0 +1,126 @@
+use regex::Regex;
+
+/// `Cache-Control` for fingerprinted assets, whose content never changes under a name.
+pub(crate) const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// Selects the files a `Cache-Control` value applies to.
+#[derive(Clone, Debug)]
+pub enum FilePattern {
+    Any,
+    /// A file name, e.g. `index.html`, in any directory.
+    Name(String),
+    /// The end of a file name, e.g. `.js` for the glob `*.js`, in any directory.
+    Suffix(String),
+    /// Matched against the path relative to the served directory, with `/` separators.
+    Match(Regex),
+}
+
+impl FilePattern {
+    pub(crate) fn matches(&self, relative_path: &str) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Name(name) => relative_path.rsplit('/').next() == Some(name.as_str()),
+            Self::Suffix(suffix) => relative_path
+                .rsplit('/')
+                .next()
+                .is_some_and(|name| name.len() > suffix.len() && name.ends_with(suffix.as_str())),
+            Self::Match(regex) => regex.is_match(relative_path),
+        }
+    }
+}
+
+/// `"*"` matches any file and `"*.ext"` any file ending in `.ext`, other strings are file
+/// names.
+///
+/// Panics on any other `*`, e.g. `"app.*"` or `"assets/*.js"`, which would otherwise be
+/// taken as a literal name and silently never match; use a `Regex` for those.
+impl From<String> for FilePattern {
+    fn from(s: String) -> Self {
+        if s == "*" {
+            return Self::Any;
+        }
+        if let Some(suffix) = s.strip_prefix('*') {
+            if !suffix.is_empty() && !suffix.contains(['*', '/']) {
+                return Self::Suffix(suffix.to_string());
+            }
+        }
+        assert!(
+            !s.contains('*'),
+            "unsupported file pattern {:?}, only \"*\" and \"*.ext\" globs are, use a Regex",
+            s
+        );
+        Self::Name(s)
+    }
+}
+
+impl From<&str> for FilePattern {
+    fn from(s: &str) -> Self {
+        Self::from(s.to_string())
+    }
+}
+
+impl From<Regex> for FilePattern {
+    fn from(regex: Regex) -> Self {
+        Self::Match(regex)
+    }
+}
+
+/// `Cache-Control` values by file, the first matching pattern wins.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CacheRules(Vec<(FilePattern, String)>);
+
+impl CacheRules {
+    pub(crate) fn push(&mut self, pattern: FilePattern, value: String) {
+        self.0.push((pattern, value));
+    }
+
+    pub(crate) fn find(&self, relative_path: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(pattern, _)| pattern.matches(relative_path))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_match_relative_paths() {
+        assert!(FilePattern::from("*").matches("css/app.css"));
+
+        let name = FilePattern::from("index.html");
+        assert!(name.matches("index.html"));
+        assert!(name.matches("docs/index.html"));
+        assert!(!name.matches("index.html.bak"));
+        assert!(!name.matches("myindex.html"));
+
+        let suffix = FilePattern::from("*.js");
+        assert!(suffix.matches("app.js"));
+        assert!(suffix.matches("assets/app.min.js"));
+        assert!(!suffix.matches("app.json"));
+        assert!(!suffix.matches("assets.js/app.css"));
+
+        let regex = FilePattern::from(Regex::new(r"^assets/.*\.js$").unwrap());
+        assert!(regex.matches("assets/app.js"));
+        assert!(!regex.matches("vendor/assets/app.js"));
+    }
+
+    #[test]
+    #[should_panic(expected = "unsupported file pattern")]
+    fn other_globs_are_refused() {
+        let _ = FilePattern::from("assets/*.js");
+    }
+
+    #[test]
+    fn the_first_matching_rule_wins() {
+        let mut rules = CacheRules::default();
+        rules.push("index.html".into(), "no-cache".into());
+        rules.push("*".into(), "max-age=60".into());
+
+        assert_eq!(rules.find("index.html"), Some("no-cache"));
+        assert_eq!(rules.find("app.js"), Some("max-age=60"));
+        assert_eq!(CacheRules::default().find("app.js"), None);
+    }
+}
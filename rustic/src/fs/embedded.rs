@@ -0,0 +1,503 @@
+use async_std::io::Cursor;
+use async_trait::async_trait;
+use std::path::Path;
+
+use http_types::{conditional::ETag, content::Encoding, headers, Body, Mime, StatusCode};
+use kv_log_macro::warn;
+
+use super::{
+    cache_control::CacheRules,
+    file::{accepts_range, PRECOMPRESSED_VARIANTS},
+    path_policy::{PathError, PathPolicy},
+    range::{self, RangeRequest},
+    serve_dir::{is_navigation, DEFAULT_INDEX_FILE},
+    FilePattern, MimeTypes,
+};
+use crate::{
+    conditional::{self, Validators},
+    middlewares::negotiate_encoding,
+    Endpoint, Redirect, Request, Response,
+};
+
+/// A file included in the binary by `embed_dir!`.
+#[derive(Debug)]
+pub struct EmbeddedFile {
+    path: &'static str,
+    contents: &'static [u8],
+    hash: &'static str,
+}
+
+impl EmbeddedFile {
+    #[doc(hidden)]
+    pub const fn new(path: &'static str, contents: &'static [u8], hash: &'static str) -> Self {
+        Self {
+            path,
+            contents,
+            hash,
+        }
+    }
+
+    /// Path relative to the embedded directory, with `/` separators.
+    pub fn path(&self) -> &'static str {
+        self.path
+    }
+
+    pub fn contents(&self) -> &'static [u8] {
+        self.contents
+    }
+}
+
+/// A directory included in the binary by `embed_dir!`, served with `Route::serve_embedded`.
+#[derive(Clone, Copy, Debug)]
+pub struct EmbeddedDir {
+    files: &'static [EmbeddedFile],
+}
+
+impl EmbeddedDir {
+    #[doc(hidden)]
+    pub const fn new(files: &'static [EmbeddedFile]) -> Self {
+        Self { files }
+    }
+
+    pub fn get(&self, path: &str) -> Option<&'static EmbeddedFile> {
+        self.files.iter().find(|file| file.path == path)
+    }
+
+    pub fn files(&self) -> &'static [EmbeddedFile] {
+        self.files
+    }
+
+    fn is_dir(&self, path: &str) -> bool {
+        self.files.iter().any(|file| {
+            file.path
+                .strip_prefix(path)
+                .is_some_and(|rest| rest.starts_with('/'))
+        })
+    }
+}
+
+/// Serves an `EmbeddedDir` like `ServeDir` serves a directory on disk, configured through
+/// `Route::serve_embedded_with`.
+pub struct ServeEmbedded {
+    prefix: String,
+    dir: EmbeddedDir,
+    index_files: Vec<String>,
+    spa_fallback: Option<String>,
+    redirect_directories: bool,
+    precompressed: bool,
+    cache_rules: CacheRules,
+    mime_types: MimeTypes,
+    nosniff: bool,
+}
+
+impl ServeEmbedded {
+    pub(crate) fn new(prefix: String, dir: EmbeddedDir) -> Self {
+        Self {
+            prefix,
+            dir,
+            index_files: vec![DEFAULT_INDEX_FILE.to_owned()],
+            spa_fallback: None,
+            redirect_directories: true,
+            precompressed: false,
+            cache_rules: CacheRules::default(),
+            mime_types: MimeTypes::default(),
+            nosniff: true,
+        }
+    }
+
+    /// Files to look for when a directory is requested, tried in order. Replaces the
+    /// default of `index.html`, pass an empty list to disable index files.
+    #[must_use]
+    pub fn index_files<T: Into<String>>(
+        mut self,
+        index_files: impl IntoIterator<Item = T>,
+    ) -> Self {
+        self.index_files = index_files.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Serve `path` for paths that don't exist, see `ServeDir::spa_fallback`.
+    #[must_use]
+    pub fn spa_fallback(mut self, path: impl Into<String>) -> Self {
+        self.spa_fallback = Some(path.into());
+        self
+    }
+
+    /// Redirect `/dir` to `/dir/`, on by default.
+    #[must_use]
+    pub fn redirect_directories(mut self, redirect_directories: bool) -> Self {
+        self.redirect_directories = redirect_directories;
+        self
+    }
+
+    /// Send an embedded `.br`, `.zst` or `.gz` sibling of a file when the client accepts
+    /// that encoding.
+    #[must_use]
+    pub fn precompressed(mut self, precompressed: bool) -> Self {
+        self.precompressed = precompressed;
+        self
+    }
+
+    /// Send `value` as the `Cache-Control` header for files matching `pattern`. The first
+    /// matching pattern wins.
+    ///
+    /// String patterns are file names, `"*"` or `"*.ext"` suffix globs; any other `*`
+    /// panics, match those with a `Regex` instead.
+    #[must_use]
+    pub fn cache_control(
+        mut self,
+        pattern: impl Into<FilePattern>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.cache_rules.push(pattern.into(), value.into());
+        self
+    }
+
+    /// Pick content types from `mime_types` instead of the default table.
+    #[must_use]
+    pub fn mime_types(mut self, mime_types: MimeTypes) -> Self {
+        self.mime_types = mime_types;
+        self
+    }
+
+    /// Send `X-Content-Type-Options: nosniff`, on by default.
+    #[must_use]
+    pub fn nosniff(mut self, nosniff: bool) -> Self {
+        self.nosniff = nosniff;
+        self
+    }
+
+    /// The best embedded sibling of `file` the client accepts, or `file` itself.
+    fn select_variant(
+        &self,
+        req: &Request,
+        file: &'static EmbeddedFile,
+    ) -> (&'static EmbeddedFile, Option<Encoding>) {
+        let accept_encoding = match req.header(headers::ACCEPT_ENCODING) {
+            Some(values) if self.precompressed => values
+                .iter()
+                .map(|value| value.as_str())
+                .collect::<Vec<_>>()
+                .join(","),
+            _ => return (file, None),
+        };
+
+        let available: Vec<(Encoding, &'static EmbeddedFile)> = PRECOMPRESSED_VARIANTS
+            .iter()
+            .filter_map(|(encoding, extension)| {
+                let variant = self.dir.get(&format!("{}.{}", file.path, extension))?;
+                Some((*encoding, variant))
+            })
+            .collect();
+
+        let encodings: Vec<Encoding> = available.iter().map(|(encoding, _)| *encoding).collect();
+        negotiate_encoding(&accept_encoding, &encodings)
+            .and_then(|chosen| {
+                available
+                    .into_iter()
+                    .find(|(encoding, _)| *encoding == chosen)
+            })
+            .map(|(encoding, variant)| (variant, Some(encoding)))
+            .unwrap_or((file, None))
+    }
+
+    fn serve(
+        &self,
+        req: &Request,
+        file: &'static EmbeddedFile,
+        cache_control: Option<&str>,
+    ) -> Response {
+        let (variant, encoding) = self.select_variant(req, file);
+
+        let validators = Validators {
+            etag: Some(ETag::new(variant.hash.to_owned())),
+            last_modified: None,
+        };
+
+        let mut res = Response::new(StatusCode::Ok);
+        validators.apply(&mut res.res);
+        if let Some(cache_control) = cache_control.or_else(|| self.cache_rules.find(file.path)) {
+            res.insert_header(headers::CACHE_CONTROL, cache_control);
+        }
+        if self.nosniff {
+            res.insert_header("X-Content-Type-Options", "nosniff");
+        }
+        if self.precompressed {
+            res.append_header(headers::VARY, "Accept-Encoding");
+        }
+
+        if conditional::is_conditional(&req.req) {
+            if let Some(short) = conditional::respond(validators.evaluate(&req.req), &res.res) {
+                return short;
+            }
+        }
+
+        if let Some(encoding) = encoding {
+            res.insert_header(headers::CONTENT_ENCODING, encoding.to_string());
+        }
+        res.insert_header(headers::ACCEPT_RANGES, "bytes");
+
+        let contents = variant.contents;
+        let len = contents.len() as u64;
+        let range = match req.header("Range") {
+            Some(range) if accepts_range(req, &validators) => {
+                RangeRequest::parse(range.last().as_str(), len)
+            }
+            _ => RangeRequest::Full,
+        };
+
+        let extension = Path::new(file.path)
+            .extension()
+            .and_then(|extension| extension.to_str());
+        let mime = self.mime_types.resolve(extension, Some(file.contents));
+        match range {
+            RangeRequest::Full => res.set_body(static_body(contents, mime)),
+            RangeRequest::Unsatisfiable => {
+                res.res.set_status(StatusCode::RequestedRangeNotSatisfiable);
+                res.insert_header(headers::CONTENT_RANGE, format!("bytes */{}", len));
+            }
+            RangeRequest::Satisfiable(ranges) => {
+                res.res.set_status(StatusCode::PartialContent);
+                if let [range] = ranges.as_slice() {
+                    res.insert_header(headers::CONTENT_RANGE, range::content_range(range, len));
+                    let part = &contents[*range.start() as usize..=*range.end() as usize];
+                    res.set_body(static_body(part, mime));
+                } else {
+                    res.set_body(range::multipart_bytes(contents, &ranges, &mime));
+                }
+            }
+        }
+
+        res
+    }
+
+    fn serve_directory(&self, req: &Request, path: &str) -> Response {
+        for index_file in &self.index_files {
+            let index_path = if path.is_empty() {
+                index_file.clone()
+            } else {
+                format!("{}/{}", path, index_file)
+            };
+            if let Some(index) = self.dir.get(&index_path) {
+                return self.serve(req, index, None);
+            }
+        }
+
+        Response::new(StatusCode::NotFound)
+    }
+
+    fn serve_missing(&self, req: &Request, path: &str) -> Response {
+        let fallback = match &self.spa_fallback {
+            Some(fallback) if is_navigation(req) => self.dir.get(fallback),
+            _ => None,
+        };
+
+        match fallback {
+            // The fallback stands in for every unknown path, don't let caches mix them up
+            Some(fallback) => {
+                let cache_control = self.cache_rules.find(fallback.path).unwrap_or("no-cache");
+                self.serve(req, fallback, Some(cache_control))
+            }
+            None => {
+                warn!("Embedded file not found: {:?}", path);
+                Response::new(StatusCode::NotFound)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Endpoint for ServeEmbedded {
+    async fn call(&self, req: Request) -> crate::Result {
+        let url_path = req.url().path();
+        let encoded = url_path
+            .strip_prefix(self.prefix.trim_end_matches('*'))
+            .unwrap_or_default();
+
+        // Embedded names are decoded, and hidden files are never served
+        let path = match PathPolicy::default().segments(encoded) {
+            Ok(segments) => segments.join("/"),
+            Err(PathError::Malformed) => {
+                warn!("Malformed embedded file path: {:?}", encoded);
+                return Ok(Response::new(StatusCode::BadRequest));
+            }
+            Err(PathError::Hidden) => {
+                warn!("Hidden embedded file requested: {:?}", encoded);
+                return Ok(Response::new(StatusCode::NotFound));
+            }
+        };
+        let path = path.as_str();
+
+        if path.is_empty() || encoded.ends_with('/') {
+            return Ok(self.serve_directory(&req, path));
+        }
+
+        if let Some(file) = self.dir.get(path) {
+            return Ok(self.serve(&req, file, None));
+        }
+
+        if self.dir.is_dir(path) {
+            if !self.redirect_directories {
+                return Ok(Response::new(StatusCode::NotFound));
+            }
+
+            let location = match req.url().query() {
+                Some(query) => format!("{}/?{}", url_path, query),
+                None => format!("{}/", url_path),
+            };
+            return Ok(Redirect::permanent(location).into());
+        }
+
+        Ok(self.serve_missing(&req, path))
+    }
+}
+
+fn static_body(contents: &'static [u8], mime: Mime) -> Body {
+    let mut body = Body::from_reader(Cursor::new(contents), Some(contents.len()));
+    body.set_mime(mime);
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use http_types::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    use super::*;
+    use crate::Server;
+
+    static FILES: [EmbeddedFile; 7] = [
+        EmbeddedFile::new("index.html", b"home", "h1"),
+        EmbeddedFile::new("css/app.css", b"body {}", "h2"),
+        EmbeddedFile::new("css/app.css.br", b"brotli", "h3"),
+        EmbeddedFile::new("docs/index.html", b"docs", "h4"),
+        EmbeddedFile::new("my file.txt", b"spaces", "h5"),
+        EmbeddedFile::new(".env", b"SECRET=1", "h6"),
+        EmbeddedFile::new("app.html", b"shell", "h7"),
+    ];
+
+    fn app(configure: impl Fn(ServeEmbedded) -> ServeEmbedded) -> Server {
+        let mut app = crate::new();
+        app.at("/static/*")
+            .serve_embedded_with(EmbeddedDir::new(&FILES), configure);
+        app
+    }
+
+    async fn get(app: &Server, path: &str, accept_encoding: Option<&str>) -> HttpResponse {
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        let mut req = HttpRequest::new(Method::Get, url);
+        if let Some(accept_encoding) = accept_encoding {
+            req.insert_header(headers::ACCEPT_ENCODING, accept_encoding);
+        }
+        app.respond(req).await.unwrap()
+    }
+
+    async fn body(app: &Server, path: &str) -> (StatusCode, String) {
+        let mut res = get(app, path, None).await;
+        (res.status(), res.body_string().await.unwrap())
+    }
+
+    #[async_std::test]
+    async fn files_are_served_with_their_hash_as_etag() {
+        let app = app(|serve_embedded| serve_embedded);
+        let mut res = get(&app, "/static/css/app.css", None).await;
+
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.header(headers::ETAG).unwrap(), "\"h2\"");
+        assert_eq!(res.header("X-Content-Type-Options").unwrap(), "nosniff");
+        assert_eq!(res.content_type().unwrap().essence(), "text/css");
+        assert_eq!(res.body_string().await.unwrap(), "body {}");
+    }
+
+    #[async_std::test]
+    async fn paths_are_percent_decoded() {
+        let app = app(|serve_embedded| serve_embedded);
+
+        assert_eq!(
+            body(&app, "/static/my%20file.txt").await,
+            (StatusCode::Ok, "spaces".to_owned())
+        );
+        assert_eq!(
+            body(&app, "/static/css/app%2ecss").await,
+            (StatusCode::Ok, "body {}".to_owned())
+        );
+        assert_eq!(
+            body(&app, "/static/css//app.css").await,
+            (StatusCode::Ok, "body {}".to_owned())
+        );
+    }
+
+    #[async_std::test]
+    async fn encoded_separators_are_refused() {
+        // Dot segments, encoded or not, are already resolved by the URL parser
+        let app = app(|serve_embedded| serve_embedded);
+
+        for path in [
+            "/static/css%2Fapp.css",
+            "/static/css%2fapp.css",
+            "/static/css%5Capp.css",
+            "/static/index.html%00",
+            "/static/%ff",
+        ] {
+            assert_eq!(body(&app, path).await.0, StatusCode::BadRequest, "{}", path);
+        }
+    }
+
+    #[async_std::test]
+    async fn hidden_files_are_not_served() {
+        let app = app(|serve_embedded| serve_embedded.spa_fallback("app.html"));
+
+        assert_eq!(body(&app, "/static/.env").await.0, StatusCode::NotFound);
+        assert_eq!(body(&app, "/static/%2eenv").await.0, StatusCode::NotFound);
+    }
+
+    #[async_std::test]
+    async fn directories_serve_their_index() {
+        let app = app(|serve_embedded| serve_embedded);
+
+        assert_eq!(
+            body(&app, "/static/").await,
+            (StatusCode::Ok, "home".to_owned())
+        );
+        assert_eq!(
+            body(&app, "/static/docs/").await,
+            (StatusCode::Ok, "docs".to_owned())
+        );
+
+        let res = get(&app, "/static/docs?a=b", None).await;
+        assert_eq!(res.status(), StatusCode::PermanentRedirect);
+        assert_eq!(res.header(headers::LOCATION).unwrap(), "/static/docs/?a=b");
+    }
+
+    #[async_std::test]
+    async fn unknown_pages_fall_back_to_the_app_shell() {
+        let app = app(|serve_embedded| serve_embedded.spa_fallback("app.html"));
+
+        let mut res = get(&app, "/static/users/42", None).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.header(headers::CACHE_CONTROL).unwrap(), "no-cache");
+        assert_eq!(res.body_string().await.unwrap(), "shell");
+
+        assert_eq!(
+            body(&app, "/static/missing.js").await.0,
+            StatusCode::NotFound
+        );
+    }
+
+    #[async_std::test]
+    async fn precompressed_siblings_and_ranges() {
+        let app = app(|serve_embedded| serve_embedded.precompressed(true));
+
+        let mut res = get(&app, "/static/css/app.css", Some("br")).await;
+        assert_eq!(res.header(headers::CONTENT_ENCODING).unwrap(), "br");
+        assert_eq!(res.content_type().unwrap().essence(), "text/css");
+        assert_eq!(res.body_string().await.unwrap(), "brotli");
+
+        let url = Url::parse("http://localhost/static/index.html").unwrap();
+        let mut req = HttpRequest::new(Method::Get, url);
+        req.insert_header("Range", "bytes=1-2");
+        let mut res: HttpResponse = app.respond(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PartialContent);
+        assert_eq!(res.header(headers::CONTENT_RANGE).unwrap(), "bytes 1-2/4");
+        assert_eq!(res.body_string().await.unwrap(), "om");
+    }
+}
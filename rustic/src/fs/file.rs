@@ -0,0 +1,373 @@
+use std::{io, path::Path, time::UNIX_EPOCH};
+
+use async_std::{
+    fs::{File, Metadata},
+    io::BufReader,
+    path::{Path as AsyncPath, PathBuf as AsyncPathBuf},
+};
+use http_types::{conditional::ETag, content::Encoding, headers, Body, Method, Mime, StatusCode};
+use kv_log_macro::warn;
+
+use super::{
+    path_policy::PathPolicy,
+    range::{self, RangeRequest},
+    MimeTypes,
+};
+use crate::{
+    conditional::{self, Validators},
+    middlewares::negotiate_encoding,
+    Request, Response,
+};
+
+/// Encodings precompressed siblings can have and their file extensions, in order of
+/// preference.
+pub(crate) const PRECOMPRESSED_VARIANTS: [(Encoding, &str); 3] = [
+    (Encoding::Brotli, "br"),
+    (Encoding::Zstd, "zst"),
+    (Encoding::Gzip, "gz"),
+];
+
+/// How the fs endpoints answer with a file.
+#[derive(Clone, Debug)]
+pub(crate) struct FileOptions {
+    /// Derive `ETag` and `Last-Modified` from the file metadata, and answer conditional
+    /// requests before opening the file.
+    pub(crate) metadata_validators: bool,
+    /// Look for `.br`, `.zst` and `.gz` siblings to send instead of compressing.
+    pub(crate) precompressed: bool,
+    pub(crate) mime_types: MimeTypes,
+    /// Send `X-Content-Type-Options: nosniff`, so browsers stick to the `Content-Type`.
+    pub(crate) nosniff: bool,
+}
+
+impl Default for FileOptions {
+    fn default() -> Self {
+        Self {
+            metadata_validators: true,
+            precompressed: false,
+            mime_types: MimeTypes::default(),
+            nosniff: true,
+        }
+    }
+}
+
+/// The directory `ServeDir` keeps files to, so that the precompressed siblings of a file
+/// get the same symlink checks as the file itself.
+#[derive(Clone, Copy)]
+pub(crate) struct Confinement<'a> {
+    pub(crate) root: &'a Path,
+    pub(crate) policy: &'a PathPolicy,
+}
+
+/// The file chosen to represent the requested one.
+struct Variant {
+    path: AsyncPathBuf,
+    metadata: Metadata,
+    encoding: Option<Encoding>,
+}
+
+pub(crate) async fn serve_file(
+    req: &Request,
+    path: &AsyncPath,
+    options: &FileOptions,
+    cache_control: Option<&str>,
+    confinement: Option<Confinement<'_>>,
+) -> crate::Result {
+    let metadata = match path.metadata().await {
+        Ok(metadata) if metadata.is_file() => metadata,
+        Ok(_) => return Ok(not_found(path)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(not_found(path)),
+        Err(e) => return Err(e.into()),
+    };
+
+    let variant = if options.precompressed {
+        select_variant(req, path, metadata, confinement).await?
+    } else {
+        Variant {
+            path: path.to_owned(),
+            metadata,
+            encoding: None,
+        }
+    };
+
+    let mut validators = if options.metadata_validators {
+        metadata_validators(&variant.metadata)
+    } else {
+        Validators::default()
+    };
+
+    // Each encoding is its own representation, with its own tag
+    if let (Some(ETag::Strong(tag)), Some(encoding)) = (&mut validators.etag, variant.encoding) {
+        tag.push_str(&format!("-{}", encoding));
+    }
+
+    let mut res = Response::new(StatusCode::Ok);
+    validators.apply(&mut res.res);
+
+    if let Some(cache_control) = cache_control {
+        res.insert_header(headers::CACHE_CONTROL, cache_control);
+    }
+    if options.nosniff {
+        res.insert_header("X-Content-Type-Options", "nosniff");
+    }
+
+    // Which file is sent depends on Accept-Encoding, even when it is the original
+    if options.precompressed {
+        res.append_header(headers::VARY, "Accept-Encoding");
+    }
+
+    if conditional::is_conditional(&req.req) {
+        if let Some(short) = conditional::respond(validators.evaluate(&req.req), &res.res) {
+            return Ok(short);
+        }
+    }
+
+    res.insert_header(headers::ACCEPT_RANGES, "bytes");
+
+    let len = variant.metadata.len();
+    let range = match req.header("Range") {
+        Some(range) if accepts_range(req, &validators) => {
+            RangeRequest::parse(range.last().as_str(), len)
+        }
+        _ => RangeRequest::Full,
+    };
+
+    if let Some(encoding) = variant.encoding {
+        res.insert_header(headers::CONTENT_ENCODING, encoding.to_string());
+    }
+
+    // A compressed sibling is sent with the content type of the original
+    let mime = match options.mime_types.resolve_file(path).await {
+        Ok(mime) => mime,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(not_found(path)),
+        Err(e) => return Err(e.into()),
+    };
+
+    match respond_with_range(res, &variant.path, range, len, mime).await {
+        Ok(res) => Ok(res),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(not_found(path)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Ranges are only honoured for `GET`, and with `If-Range` only while the file is
+/// unchanged.
+pub(crate) fn accepts_range(req: &Request, validators: &Validators) -> bool {
+    if req.method() != Method::Get {
+        return false;
+    }
+
+    match req.header(headers::IF_RANGE) {
+        Some(if_range) => range::if_range_matches(if_range.last().as_str(), validators),
+        None => true,
+    }
+}
+
+async fn respond_with_range(
+    mut res: Response,
+    path: &AsyncPath,
+    range: RangeRequest,
+    len: u64,
+    mime: Mime,
+) -> io::Result<Response> {
+    match range {
+        RangeRequest::Full => {
+            let file = File::open(path).await?;
+            let mut body = Body::from_reader(BufReader::new(file), Some(len as usize));
+            body.set_mime(mime);
+            res.set_body(body);
+        }
+        RangeRequest::Unsatisfiable => {
+            res.res.set_status(StatusCode::RequestedRangeNotSatisfiable);
+            res.insert_header(headers::CONTENT_RANGE, format!("bytes */{}", len));
+        }
+        RangeRequest::Satisfiable(ranges) => {
+            res.res.set_status(StatusCode::PartialContent);
+            if let [range] = ranges.as_slice() {
+                res.insert_header(headers::CONTENT_RANGE, range::content_range(range, len));
+                res.set_body(range::single_range_body(path, range, mime).await?);
+            } else {
+                res.set_body(range::multipart_body(path, &ranges, len, &mime).await?);
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+/// The best precompressed sibling of `path` the client accepts, or `path` itself.
+async fn select_variant(
+    req: &Request,
+    path: &AsyncPath,
+    metadata: Metadata,
+    confinement: Option<Confinement<'_>>,
+) -> io::Result<Variant> {
+    let accept_encoding = req.header(headers::ACCEPT_ENCODING).map(|values| {
+        values
+            .iter()
+            .map(|value| value.as_str())
+            .collect::<Vec<_>>()
+            .join(",")
+    });
+
+    if let Some(accept_encoding) = accept_encoding {
+        let mut available = Vec::new();
+        for (encoding, extension) in &PRECOMPRESSED_VARIANTS {
+            let mut variant_path = path.as_os_str().to_owned();
+            variant_path.push(".");
+            variant_path.push(extension);
+            let variant_path = AsyncPathBuf::from(variant_path);
+
+            let metadata = match variant_path.metadata().await {
+                Ok(metadata) if metadata.is_file() => metadata,
+                Ok(_) => continue,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            if let Some(Confinement { root, policy }) = confinement {
+                if !policy.allows(root, &variant_path).await? {
+                    warn!("Unauthorized attempt to read: {:?}", variant_path);
+                    continue;
+                }
+            }
+
+            available.push((*encoding, variant_path, metadata));
+        }
+
+        let encodings: Vec<Encoding> = available.iter().map(|(encoding, ..)| *encoding).collect();
+        if let Some(chosen) = negotiate_encoding(&accept_encoding, &encodings) {
+            if let Some((encoding, path, metadata)) = available
+                .into_iter()
+                .find(|(encoding, ..)| *encoding == chosen)
+            {
+                return Ok(Variant {
+                    path,
+                    metadata,
+                    encoding: Some(encoding),
+                });
+            }
+        }
+    }
+
+    Ok(Variant {
+        path: path.to_owned(),
+        metadata,
+        encoding: None,
+    })
+}
+
+/// A strong `ETag` from modification time and size, and the modification time as
+/// `Last-Modified`, without reading the file.
+pub(crate) fn metadata_validators(metadata: &Metadata) -> Validators {
+    let modified = metadata.modified().ok();
+    let etag = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since_epoch| ETag::new(format!("{:x}-{:x}", since_epoch.as_nanos(), metadata.len())));
+
+    Validators {
+        etag,
+        last_modified: modified,
+    }
+}
+
+fn not_found(path: &AsyncPath) -> Response {
+    warn!("File not found: {:?}", path);
+    Response::new(StatusCode::NotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use http_types::{Request as HttpRequest, Response as HttpResponse, Url};
+
+    use super::{super::test_dir::TestDir, *};
+    use crate::Server;
+
+    /// `app.js` with a brotli and a gzip sibling, told apart by their contents.
+    fn assets() -> TestDir {
+        let dir = TestDir::new();
+        dir.file("app.js", "original");
+        dir.file("app.js.br", "brotli");
+        dir.file("app.js.gz", "gzip");
+        dir
+    }
+
+    fn app(dir: &TestDir, precompressed: bool) -> Server {
+        let mut app = crate::new();
+        app.at("/*")
+            .serve_dir_with(dir.path(), |serve_dir| {
+                serve_dir.precompressed(precompressed)
+            })
+            .unwrap();
+        app
+    }
+
+    async fn get(app: &Server, accept_encoding: Option<&str>) -> HttpResponse {
+        let url = Url::parse("http://localhost/app.js").unwrap();
+        let mut req = HttpRequest::new(Method::Get, url);
+        if let Some(accept_encoding) = accept_encoding {
+            req.insert_header(headers::ACCEPT_ENCODING, accept_encoding);
+        }
+        app.respond(req).await.unwrap()
+    }
+
+    fn header(res: &HttpResponse, name: headers::HeaderName) -> Option<String> {
+        res.header(name).map(|value| value.as_str().to_owned())
+    }
+
+    #[async_std::test]
+    async fn the_best_accepted_sibling_is_sent() {
+        let dir = assets();
+        let app = app(&dir, true);
+
+        for (accept_encoding, encoding, body) in [
+            (Some("gzip, br"), Some("br"), "brotli"),
+            (Some("gzip"), Some("gzip"), "gzip"),
+            (Some("br;q=0.5, gzip"), Some("gzip"), "gzip"),
+            (Some("zstd"), None, "original"),
+            (None, None, "original"),
+        ] {
+            let mut res = get(&app, accept_encoding).await;
+            assert_eq!(res.status(), StatusCode::Ok);
+            assert_eq!(header(&res, headers::CONTENT_ENCODING).as_deref(), encoding);
+            assert_eq!(
+                header(&res, headers::VARY).as_deref(),
+                Some("Accept-Encoding")
+            );
+            assert!(res
+                .content_type()
+                .unwrap()
+                .essence()
+                .ends_with("javascript"));
+            assert_eq!(res.body_string().await.unwrap(), body);
+        }
+    }
+
+    #[async_std::test]
+    async fn each_encoding_has_its_own_etag() {
+        let dir = assets();
+        let app = app(&dir, true);
+
+        let original = header(&get(&app, None).await, headers::ETAG).unwrap();
+        let brotli = header(&get(&app, Some("br")).await, headers::ETAG).unwrap();
+        assert!(brotli.ends_with("-br\""));
+        assert_ne!(original, brotli);
+
+        let mut req = HttpRequest::new(Method::Get, Url::parse("http://localhost/app.js").unwrap());
+        req.insert_header(headers::ACCEPT_ENCODING, "gzip");
+        req.insert_header(headers::IF_NONE_MATCH, brotli.as_str());
+        let res: HttpResponse = app.respond(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+    }
+
+    #[async_std::test]
+    async fn siblings_are_ignored_unless_asked_for() {
+        let dir = assets();
+        let app = app(&dir, false);
+
+        let mut res = get(&app, Some("br")).await;
+        assert_eq!(header(&res, headers::CONTENT_ENCODING), None);
+        assert_eq!(header(&res, headers::VARY), None);
+        assert_eq!(res.body_string().await.unwrap(), "original");
+    }
+}
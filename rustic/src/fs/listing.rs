@@ -0,0 +1,268 @@
+use std::{io, time::UNIX_EPOCH};
+
+use async_std::{path::Path as AsyncPath, stream::StreamExt};
+use http_types::{headers, mime, Body, StatusCode};
+use serde::Serialize;
+
+use super::Confinement;
+use crate::{Request, Response};
+
+/// How `ServeDir` lists directories without an index file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DirectoryListing {
+    Html,
+    Json,
+    /// JSON for clients that accept `application/json` but not `text/html`, HTML otherwise.
+    Negotiate,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    name: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    size: u64,
+    /// Seconds since the unix epoch.
+    modified: Option<u64>,
+}
+
+impl DirectoryListing {
+    pub(crate) async fn respond(
+        self,
+        req: &Request,
+        dir: &AsyncPath,
+        url_path: &str,
+        confinement: Confinement<'_>,
+    ) -> crate::Result {
+        let entries = read_entries(dir, confinement).await?;
+
+        let mut res = Response::new(StatusCode::Ok);
+        if self.wants_json(req) {
+            res.set_body(Body::from_json(&entries)?);
+        } else {
+            res.set_body(html(url_path, &entries));
+            res.set_content_type(mime::HTML);
+        }
+        res.insert_header(headers::VARY, "Accept");
+        Ok(res)
+    }
+
+    fn wants_json(self, req: &Request) -> bool {
+        match self {
+            Self::Html => false,
+            Self::Json => true,
+            Self::Negotiate => {
+                let accept = req
+                    .header(headers::ACCEPT)
+                    .map(|accept| accept.last().as_str().to_ascii_lowercase())
+                    .unwrap_or_default();
+                accept.contains("application/json") && !accept.contains("text/html")
+            }
+        }
+    }
+}
+
+/// Directories first, then files, each sorted by name.
+async fn read_entries(dir: &AsyncPath, confinement: Confinement<'_>) -> io::Result<Vec<Entry>> {
+    let Confinement { root, policy } = confinement;
+    let mut entries = Vec::new();
+    let mut read_dir = dir.read_dir().await?;
+
+    while let Some(entry) = read_dir.next().await {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !policy.lists(&name) {
+            continue;
+        }
+
+        // Describe what links point to, and leave out those leading nowhere or out of the
+        // directory
+        let entry_path = entry.path();
+        let metadata = match entry_path.metadata().await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if !policy.allows(root, &entry_path).await? {
+            continue;
+        }
+
+        entries.push(Entry {
+            name,
+            kind: if metadata.is_dir() {
+                "directory"
+            } else {
+                "file"
+            },
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|since_epoch| since_epoch.as_secs()),
+        });
+    }
+
+    entries.sort_by(|a, b| (a.kind, &a.name).cmp(&(b.kind, &b.name)));
+    Ok(entries)
+}
+
+fn html(url_path: &str, entries: &[Entry]) -> String {
+    let title = escape_html(url_path);
+    let mut rows = String::new();
+
+    if url_path != "/" {
+        rows.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+
+    for entry in entries {
+        let suffix = if entry.kind == "directory" { "/" } else { "" };
+        rows.push_str(&format!(
+            "<li><a href=\"{}{}\">{}{}</a></li>\n",
+            encode_path_segment(&entry.name),
+            suffix,
+            escape_html(&entry.name),
+            suffix
+        ));
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {}</title></head>\n<body>\n<h1>Index of {}</h1>\n<ul>\n{}</ul>\n</body>\n</html>\n",
+        title, title, rows
+    )
+}
+
+pub(crate) fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub(crate) fn encode_path_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::path::PathBuf as AsyncPathBuf;
+
+    use super::{
+        super::{path_policy::PathPolicy, test_dir::TestDir, SymlinkPolicy},
+        *,
+    };
+
+    #[async_std::test]
+    async fn directories_come_first_then_files_by_name() {
+        let dir = TestDir::new();
+        dir.file("b.txt", "bb");
+        dir.file("a.txt", "a");
+        dir.dir("z");
+        dir.file(".env", "SECRET=1");
+        let path = AsyncPathBuf::from(dir.path());
+
+        let confinement = Confinement {
+            root: dir.path(),
+            policy: &PathPolicy::default(),
+        };
+        let entries = read_entries(&path, confinement).await.unwrap();
+        let listed: Vec<(&str, &str, u64)> = entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.kind, entry.size))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                ("z", "directory", 0),
+                ("a.txt", "file", 1),
+                ("b.txt", "file", 2)
+            ]
+        );
+
+        let policy = PathPolicy {
+            hidden_files: true,
+            ..PathPolicy::default()
+        };
+        let confinement = Confinement {
+            root: dir.path(),
+            policy: &policy,
+        };
+        let entries = read_entries(&path, confinement).await.unwrap();
+        assert!(entries.iter().any(|entry| entry.name == ".env"));
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn links_out_of_the_directory_are_left_out() {
+        let dir = TestDir::new();
+        let root = dir.dir("site");
+        dir.file("site/index.html", "home");
+        dir.file("outside/secret.txt", "secret");
+        dir.symlink("index.html", "site/inside-link");
+        dir.symlink("../outside/secret.txt", "site/outside-link");
+        dir.symlink("../outside", "site/outside-dir");
+        dir.symlink("missing.txt", "site/dangling-link");
+        let path = AsyncPathBuf::from(&root);
+
+        let names = |entries: Vec<Entry>| -> Vec<String> {
+            entries.into_iter().map(|entry| entry.name).collect()
+        };
+
+        let confinement = Confinement {
+            root: &root,
+            policy: &PathPolicy::default(),
+        };
+        let entries = read_entries(&path, confinement).await.unwrap();
+        assert_eq!(entries[0].size, 4);
+        assert_eq!(names(entries), ["index.html", "inside-link"]);
+
+        let policy = PathPolicy {
+            symlinks: SymlinkPolicy::Follow,
+            ..PathPolicy::default()
+        };
+        let confinement = Confinement {
+            root: &root,
+            policy: &policy,
+        };
+        let entries = read_entries(&path, confinement).await.unwrap();
+        assert_eq!(
+            names(entries),
+            ["outside-dir", "index.html", "inside-link", "outside-link"]
+        );
+    }
+
+    #[test]
+    fn names_are_escaped_and_encoded() {
+        let entries = [Entry {
+            name: "<a href=\"x\">&'.txt".to_owned(),
+            kind: "file",
+            size: 0,
+            modified: None,
+        }];
+        let html = html("/<docs>/", &entries);
+
+        assert!(html.contains("<title>Index of /&lt;docs&gt;/</title>"));
+        assert!(html.contains(
+            "<a href=\"%3Ca%20href%3D%22x%22%3E%26%27.txt\">&lt;a href=&quot;x&quot;&gt;&amp;&#39;.txt</a>"
+        ));
+        assert!(html.contains("<a href=\"../\">"));
+        assert!(!super::html("/", &[]).contains("../"));
+    }
+}
@@ -0,0 +1,267 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use super::file::PRECOMPRESSED_VARIANTS;
+
+/// Hex characters of the content hash put into fingerprinted names.
+const FINGERPRINT_LEN: usize = 12;
+
+/// Maps logical asset names such as `css/app.css` to fingerprinted ones such as
+/// `css/app.3f2a1b9c04de.css`, so templates can link to assets that are cached forever.
+///
+/// Hand the manifest to `ServeDir::manifest` to serve the fingerprinted names, and keep a
+/// clone around to render links with `url`.
+#[derive(Clone, Debug, Default)]
+pub struct AssetManifest {
+    base_url: String,
+    assets: HashMap<String, String>,
+    fingerprinted: HashMap<String, String>,
+}
+
+impl AssetManifest {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a manifest written by a build tool, a JSON object mapping logical names either
+    /// to fingerprinted names or to objects with a `file` entry, as Vite writes them.
+    pub fn from_json_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let content = fs::read(path)?;
+        let entries: HashMap<String, serde_json::Value> = serde_json::from_slice(&content)?;
+
+        let mut manifest = Self::new();
+        for (logical, entry) in entries {
+            let fingerprinted = match &entry {
+                serde_json::Value::String(file) => Some(file.as_str()),
+                serde_json::Value::Object(object) => object.get("file").and_then(|f| f.as_str()),
+                _ => None,
+            };
+            if let Some(fingerprinted) = fingerprinted {
+                manifest.insert(logical, fingerprinted.to_owned());
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Fingerprint every file below `dir` by the hash of its content, without renaming
+    /// anything on disk: `ServeDir` maps the fingerprinted names back to the files.
+    pub fn fingerprint_dir(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        let mut manifest = Self::new();
+
+        for path in walk(dir)? {
+            let logical = relative_path(dir, &path);
+            let is_variant = PRECOMPRESSED_VARIANTS
+                .iter()
+                .any(|(_, extension)| logical.ends_with(&format!(".{}", extension)));
+            if is_variant {
+                continue;
+            }
+
+            let digest = Sha256::digest(fs::read(&path)?);
+            let hash: String = digest
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>()[..FINGERPRINT_LEN]
+                .to_owned();
+
+            let fingerprinted = fingerprint(&logical, &hash);
+            manifest.insert(logical, fingerprinted);
+        }
+
+        Ok(manifest)
+    }
+
+    /// Prefix for the links `url` builds, e.g. the route the directory is served at.
+    #[must_use]
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        let mut base_url = base_url.into();
+        if !base_url.ends_with('/') {
+            base_url.push('/');
+        }
+        self.base_url = base_url;
+        self
+    }
+
+    pub fn insert(&mut self, logical: impl Into<String>, fingerprinted: impl Into<String>) {
+        let logical = logical.into();
+        let fingerprinted = fingerprinted.into();
+        self.fingerprinted
+            .insert(fingerprinted.clone(), logical.clone());
+        self.assets.insert(logical, fingerprinted);
+    }
+
+    /// The fingerprinted name of `logical`.
+    pub fn path(&self, logical: &str) -> Option<&str> {
+        self.assets.get(logical).map(String::as_str)
+    }
+
+    /// The link to `logical`, falling back to the logical name for unknown assets so a
+    /// missing entry degrades to an uncached link rather than a broken one.
+    #[must_use]
+    pub fn url(&self, logical: &str) -> String {
+        format!("{}{}", self.base_url, self.path(logical).unwrap_or(logical))
+    }
+
+    pub fn len(&self) -> usize {
+        self.assets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.assets.is_empty()
+    }
+
+    /// The logical name of a fingerprinted one.
+    pub(crate) fn logical(&self, fingerprinted: &str) -> Option<&str> {
+        self.fingerprinted.get(fingerprinted).map(String::as_str)
+    }
+}
+
+/// `css/app.css` -> `css/app.<hash>.css`, `LICENSE` -> `LICENSE.<hash>`
+fn fingerprint(logical: &str, hash: &str) -> String {
+    let (dir, name) = match logical.rsplit_once('/') {
+        Some((dir, name)) => (format!("{}/", dir), name),
+        None => (String::new(), logical),
+    };
+
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            format!("{}{}.{}.{}", dir, stem, hash, extension)
+        }
+        _ => format!("{}{}.{}", dir, name, hash),
+    }
+}
+
+fn walk(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_owned()];
+
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                pending.push(entry.path());
+            } else if file_type.is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+pub(crate) fn relative_path(dir: &Path, path: &Path) -> String {
+    path.strip_prefix(dir)
+        .unwrap_or(path)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use http_types::{
+        headers, Method, Request as HttpRequest, Response as HttpResponse, StatusCode, Url,
+    };
+
+    use super::{super::test_dir::TestDir, *};
+
+    #[test]
+    fn names_get_the_hash_before_their_extension() {
+        assert_eq!(fingerprint("css/app.css", "abc"), "css/app.abc.css");
+        assert_eq!(fingerprint("app.min.js", "abc"), "app.min.abc.js");
+        assert_eq!(fingerprint("LICENSE", "abc"), "LICENSE.abc");
+        assert_eq!(fingerprint("a/.env", "abc"), "a/.env.abc");
+    }
+
+    #[test]
+    fn build_tool_manifests_are_read() {
+        let dir = TestDir::new();
+        let path = dir.file(
+            "manifest.json",
+            r#"{
+                "app.css": "app.1234.css",
+                "main.js": { "file": "assets/main.5678.js", "isEntry": true },
+                "broken": 42
+            }"#,
+        );
+
+        let manifest = AssetManifest::from_json_file(path)
+            .unwrap()
+            .base_url("/static");
+        assert_eq!(manifest.len(), 2);
+        assert_eq!(manifest.path("app.css"), Some("app.1234.css"));
+        assert_eq!(manifest.url("main.js"), "/static/assets/main.5678.js");
+        assert_eq!(manifest.url("missing.js"), "/static/missing.js");
+        assert_eq!(manifest.logical("assets/main.5678.js"), Some("main.js"));
+    }
+
+    #[test]
+    fn directories_are_fingerprinted_by_content() {
+        let dir = TestDir::new();
+        dir.file("css/app.css", "body {}");
+        dir.file("css/app.css.gz", "compressed");
+        dir.file("copy.css", "body {}");
+
+        let manifest = AssetManifest::fingerprint_dir(dir.path()).unwrap();
+        assert_eq!(manifest.len(), 2);
+
+        let hash = &format!("{:x}", Sha256::digest(b"body {}"))[..FINGERPRINT_LEN];
+        assert_eq!(
+            manifest.path("css/app.css"),
+            Some(format!("css/app.{}.css", hash).as_str())
+        );
+        assert_eq!(
+            manifest.path("copy.css"),
+            Some(format!("copy.{}.css", hash).as_str())
+        );
+    }
+
+    #[async_std::test]
+    async fn fingerprinted_names_are_served_as_immutable() {
+        let dir = TestDir::new();
+        dir.file("app.css", "body {}");
+        dir.file("index.html", "home");
+        let manifest = AssetManifest::fingerprint_dir(dir.path()).unwrap();
+        let fingerprinted = manifest.path("app.css").unwrap().to_owned();
+
+        let mut app = crate::new();
+        app.at("/*")
+            .serve_dir_with(dir.path(), |serve_dir| {
+                serve_dir
+                    .manifest(manifest.clone())
+                    .cache_control("index.html", "no-cache")
+            })
+            .unwrap();
+
+        let get = |path: &str| {
+            let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+            app.respond::<_, HttpResponse>(HttpRequest::new(Method::Get, url))
+        };
+
+        let mut res = get(&fingerprinted).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(
+            res.header(headers::CACHE_CONTROL).unwrap(),
+            super::super::cache_control::IMMUTABLE
+        );
+        assert_eq!(res.body_string().await.unwrap(), "body {}");
+
+        let res = get("/app.css").await.unwrap();
+        assert!(res.header(headers::CACHE_CONTROL).is_none());
+
+        let res = get("/index.html").await.unwrap();
+        assert_eq!(res.header(headers::CACHE_CONTROL).unwrap(), "no-cache");
+
+        let res = get("/app.000000000000.css").await.unwrap();
+        assert_eq!(res.status(), StatusCode::NotFound);
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+
+use async_std::{fs::File, io::ReadExt, path::Path as AsyncPath};
+use http_types::{mime, Mime};
+
+/// Bytes read from the start of a file to sniff its type, enough for formats such as tar.
+const SNIFF_LEN: u64 = 300;
+
+const DEFAULT_CHARSET: &str = "utf-8";
+
+/// Extensions the fs endpoints know without configuration.
+const DEFAULT_TYPES: &[(&str, &str)] = &[
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("webmanifest", "application/manifest+json"),
+    ("xml", "application/xml"),
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("ico", "image/x-icon"),
+    ("bmp", "image/bmp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+];
+
+/// Types outside of `text/*` that are text and get the default charset.
+const TEXT_TYPES: &[&str] = &[
+    "application/json",
+    "application/manifest+json",
+    "application/xml",
+    "image/svg+xml",
+];
+
+/// Maps file extensions to the `Content-Type` the fs endpoints send, configured per route
+/// through their `mime_types` builders.
+///
+/// Starts out with the common web formats, text types get a `utf-8` charset unless they
+/// name one, and files without a known extension are sniffed by their first bytes.
+#[derive(Clone, Debug)]
+pub struct MimeTypes {
+    types: HashMap<String, Mime>,
+    charset: Option<String>,
+    sniff: bool,
+}
+
+impl Default for MimeTypes {
+    fn default() -> Self {
+        Self {
+            types: DEFAULT_TYPES
+                .iter()
+                .map(|(extension, mime)| (extension.to_string(), Mime::from(*mime)))
+                .collect(),
+            charset: Some(DEFAULT_CHARSET.to_owned()),
+            sniff: true,
+        }
+    }
+}
+
+impl MimeTypes {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send `mime` for files ending in `.extension`, replacing the type it had.
+    #[must_use]
+    pub fn insert(mut self, extension: impl AsRef<str>, mime: impl Into<Mime>) -> Self {
+        self.types
+            .insert(normalize(extension.as_ref()), mime.into());
+        self
+    }
+
+    /// Charset added to text types that don't name one, `None` to send them as they are.
+    #[must_use]
+    pub fn default_charset(mut self, charset: Option<&str>) -> Self {
+        self.charset = charset.map(str::to_owned);
+        self
+    }
+
+    /// Sniff the type of files without a known extension from their first bytes, on by
+    /// default. Files that can't be sniffed are sent as `application/octet-stream`.
+    #[must_use]
+    pub fn sniff(mut self, sniff: bool) -> Self {
+        self.sniff = sniff;
+        self
+    }
+
+    /// The type configured for `extension`, without the default charset.
+    pub fn get(&self, extension: &str) -> Option<&Mime> {
+        self.types.get(&normalize(extension))
+    }
+
+    /// The type to send for a file with `extension`, sniffing `head` if the extension is
+    /// unknown.
+    pub(crate) fn resolve(&self, extension: Option<&str>, head: Option<&[u8]>) -> Mime {
+        let mime = extension
+            .and_then(|extension| self.get(extension).cloned())
+            .or_else(|| {
+                head.filter(|_| self.sniff)
+                    .and_then(|head| Mime::sniff(head).ok())
+            })
+            .unwrap_or(mime::BYTE_STREAM);
+
+        self.with_charset(mime)
+    }
+
+    /// The type to send for the file at `path`, only reading from it to sniff.
+    pub(crate) async fn resolve_file(&self, path: &AsyncPath) -> std::io::Result<Mime> {
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        if !self.sniff || extension.is_some_and(|extension| self.get(extension).is_some()) {
+            return Ok(self.resolve(extension, None));
+        }
+
+        let mut head = Vec::new();
+        File::open(path)
+            .await?
+            .take(SNIFF_LEN)
+            .read_to_end(&mut head)
+            .await?;
+        Ok(self.resolve(extension, Some(&head)))
+    }
+
+    fn with_charset(&self, mime: Mime) -> Mime {
+        let charset = match &self.charset {
+            Some(charset) if is_text(&mime) && mime.param("charset").is_none() => charset,
+            _ => return mime,
+        };
+
+        format!("{};charset={}", mime, charset)
+            .parse()
+            .unwrap_or(mime)
+    }
+}
+
+fn is_text(mime: &Mime) -> bool {
+    mime.basetype() == "text" || TEXT_TYPES.contains(&mime.essence())
+}
+
+fn normalize(extension: &str) -> String {
+    extension.trim_start_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::path::PathBuf as AsyncPathBuf;
+    use http_types::{headers, Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    use super::{super::test_dir::TestDir, *};
+
+    const PNG_HEAD: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    fn resolve(mime_types: &MimeTypes, extension: Option<&str>, head: Option<&[u8]>) -> String {
+        mime_types.resolve(extension, head).to_string()
+    }
+
+    #[test]
+    fn text_types_get_the_default_charset() {
+        let mime_types = MimeTypes::new();
+
+        assert_eq!(
+            resolve(&mime_types, Some("html"), None),
+            "text/html;charset=utf-8"
+        );
+        assert_eq!(
+            resolve(&mime_types, Some("HTML"), None),
+            "text/html;charset=utf-8"
+        );
+        assert_eq!(
+            resolve(&mime_types, Some("svg"), None),
+            "image/svg+xml;charset=utf-8"
+        );
+        assert_eq!(resolve(&mime_types, Some("png"), None), "image/png");
+
+        let mime_types = mime_types.default_charset(None);
+        assert_eq!(resolve(&mime_types, Some("html"), None), "text/html");
+
+        let mime_types = MimeTypes::new()
+            .default_charset(Some("iso-8859-1"))
+            .insert("tpl", Mime::from("text/plain;charset=utf-16"));
+        assert_eq!(
+            resolve(&mime_types, Some("css"), None),
+            "text/css;charset=iso-8859-1"
+        );
+        assert_eq!(
+            resolve(&mime_types, Some("tpl"), None),
+            "text/plain;charset=utf-16"
+        );
+    }
+
+    #[test]
+    fn extensions_can_be_added_and_replaced() {
+        let mime_types = MimeTypes::new()
+            .insert(".GLTF", Mime::from("model/gltf+json"))
+            .insert("js", Mime::from("application/javascript"));
+
+        assert_eq!(mime_types.get("gltf").unwrap().essence(), "model/gltf+json");
+        assert_eq!(
+            mime_types.get(".js").unwrap().essence(),
+            "application/javascript"
+        );
+        assert!(MimeTypes::new().get("gltf").is_none());
+    }
+
+    #[test]
+    fn unknown_extensions_are_sniffed() {
+        let mime_types = MimeTypes::new();
+
+        assert_eq!(resolve(&mime_types, None, Some(PNG_HEAD)), "image/png");
+        assert_eq!(
+            resolve(&mime_types, Some("bin"), Some(PNG_HEAD)),
+            "image/png"
+        );
+        // The extension wins over the contents
+        assert_eq!(
+            resolve(&mime_types, Some("txt"), Some(PNG_HEAD)),
+            "text/plain;charset=utf-8"
+        );
+        assert_eq!(
+            resolve(&mime_types, None, Some(b"\0\x01\x02")),
+            "application/octet-stream"
+        );
+
+        let mime_types = mime_types.sniff(false);
+        assert_eq!(
+            resolve(&mime_types, None, Some(PNG_HEAD)),
+            "application/octet-stream"
+        );
+    }
+
+    #[async_std::test]
+    async fn files_are_only_read_without_a_known_extension() {
+        let dir = TestDir::new();
+        let image = AsyncPathBuf::from(dir.file("image", PNG_HEAD));
+        let mime_types = MimeTypes::new();
+
+        assert_eq!(
+            mime_types.resolve_file(&image).await.unwrap().essence(),
+            "image/png"
+        );
+
+        // Known extensions don't need the file to exist
+        let missing = AsyncPathBuf::from(dir.path().join("missing.css"));
+        assert_eq!(
+            mime_types.resolve_file(&missing).await.unwrap().essence(),
+            "text/css"
+        );
+        assert!(mime_types
+            .resolve_file(&AsyncPathBuf::from(dir.path().join("missing")))
+            .await
+            .is_err());
+    }
+
+    #[async_std::test]
+    async fn files_are_sent_with_nosniff_and_their_type() {
+        let dir = TestDir::new();
+        dir.file("page.html", "<p>hi</p>");
+        let get = |app: crate::Server| async move {
+            let url = Url::parse("http://localhost/page.html").unwrap();
+            let res: HttpResponse = app
+                .respond(HttpRequest::new(Method::Get, url))
+                .await
+                .unwrap();
+            res
+        };
+
+        let mut app = crate::new();
+        app.at("/*").serve_dir(dir.path()).unwrap();
+        let res = get(app).await;
+        assert_eq!(
+            res.header(headers::CONTENT_TYPE).unwrap(),
+            "text/html;charset=utf-8"
+        );
+        assert_eq!(res.header("X-Content-Type-Options").unwrap(), "nosniff");
+
+        let mut app = crate::new();
+        app.at("/*")
+            .serve_dir_with(dir.path(), |serve_dir| {
+                serve_dir
+                    .nosniff(false)
+                    .mime_types(MimeTypes::new().insert("html", Mime::from("text/plain")))
+            })
+            .unwrap();
+        let res = get(app).await;
+        assert_eq!(
+            res.header(headers::CONTENT_TYPE).unwrap(),
+            "text/plain;charset=utf-8"
+        );
+        assert!(res.header("X-Content-Type-Options").is_none());
+    }
+}
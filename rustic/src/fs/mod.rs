@@ -1,5 +1,23 @@
+mod cache_control;
+mod embedded;
+mod file;
+mod listing;
+mod manifest;
+mod mime_types;
+mod path_policy;
+mod range;
 mod serve_dir;
 mod serve_file;
+#[cfg(test)]
+pub(crate) mod test_dir;
+mod writable;
 
-pub(crate) use serve_dir::ServeDir;
-pub(crate) use serve_file::ServeFile;
+pub use cache_control::FilePattern;
+pub use embedded::{EmbeddedDir, EmbeddedFile, ServeEmbedded};
+pub(crate) use file::{serve_file, Confinement, FileOptions};
+pub use listing::DirectoryListing;
+pub use manifest::AssetManifest;
+pub use mime_types::MimeTypes;
+pub use path_policy::SymlinkPolicy;
+pub use serve_dir::ServeDir;
+pub use serve_file::ServeFile;
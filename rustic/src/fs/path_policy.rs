@@ -0,0 +1,344 @@
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use async_std::path::Path as AsyncPath;
+use percent_encoding::percent_decode_str;
+
+/// What `ServeDir` does with symlinks that resolve to a path outside the served directory.
+/// Symlinks that stay inside it are always followed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    Follow,
+    /// Answer `403`, as if the request had tried to climb out of the directory.
+    #[default]
+    Deny,
+}
+
+/// Why a request path can't name a file below the served directory.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum PathError {
+    /// A segment that decodes to a separator, `.`, `..`, a NUL byte, a drive or invalid UTF-8.
+    Malformed,
+    /// A segment starting with `.`, while hidden files aren't served.
+    Hidden,
+}
+
+/// How `ServeDir` maps request paths to files.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct PathPolicy {
+    pub(crate) symlinks: SymlinkPolicy,
+    pub(crate) hidden_files: bool,
+}
+
+impl PathPolicy {
+    /// Join the percent-encoded `path` to `root` segment by segment, see `segments`.
+    pub(crate) fn join(&self, root: &Path, path: &str) -> Result<PathBuf, PathError> {
+        let mut joined = root.to_owned();
+        for segment in self.segments(path)? {
+            joined.push(segment);
+        }
+        Ok(joined)
+    }
+
+    /// Decode the segments of the percent-encoded `path`, refusing segments that could
+    /// leave the directory once decoded rather than normalizing them away. Empty segments
+    /// are skipped.
+    pub(crate) fn segments(&self, path: &str) -> Result<Vec<String>, PathError> {
+        let mut segments = Vec::new();
+
+        for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+            let segment = percent_decode_str(segment)
+                .decode_utf8()
+                .map_err(|_| PathError::Malformed)?;
+
+            if segment.contains(['/', '\\', '\0']) {
+                return Err(PathError::Malformed);
+            }
+
+            // Anything but a plain name, e.g. `..` or a `C:` prefix on windows
+            let mut components = Path::new(segment.as_ref()).components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(_)), None) => {}
+                _ => return Err(PathError::Malformed),
+            }
+
+            if segment.starts_with('.') && !self.hidden_files {
+                return Err(PathError::Hidden);
+            }
+
+            segments.push(segment.into_owned());
+        }
+
+        Ok(segments)
+    }
+
+    /// Whether `path`, which must exist, may be served from `root` after resolving every
+    /// symlink on the way. `root` has to be canonical.
+    pub(crate) async fn allows(&self, root: &Path, path: &AsyncPath) -> io::Result<bool> {
+        if self.symlinks == SymlinkPolicy::Follow {
+            return Ok(true);
+        }
+
+        let canonical = path.canonicalize().await?;
+        Ok(canonical.starts_with(root))
+    }
+
+    /// Whether a directory entry called `name` shows up in listings.
+    pub(crate) fn lists(&self, name: &str) -> bool {
+        self.hidden_files || !name.starts_with('.')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::path::PathBuf as AsyncPathBuf;
+
+    use super::{super::test_dir::TestDir, *};
+
+    fn hidden_files() -> PathPolicy {
+        PathPolicy {
+            hidden_files: true,
+            ..PathPolicy::default()
+        }
+    }
+
+    fn follow() -> PathPolicy {
+        PathPolicy {
+            symlinks: SymlinkPolicy::Follow,
+            ..PathPolicy::default()
+        }
+    }
+
+    /// A served `site` directory next to an `outside` one it must not leak.
+    fn fixture() -> TestDir {
+        let dir = TestDir::new();
+        dir.file("site/index.html", "home");
+        dir.file("site/docs/readme.txt", "readme");
+        dir.file("site/app.js", "original");
+        dir.file("site/.env", "SECRET=1");
+        dir.file("outside/secret.txt", "secret");
+        dir.file("outside/app.js.br", "leaked");
+        #[cfg(unix)]
+        {
+            dir.symlink("index.html", "site/inside-link");
+            dir.symlink("../index.html", "site/docs/up-link");
+            dir.symlink("../outside/secret.txt", "site/outside-link");
+            dir.symlink("../outside", "site/outside-dir");
+            dir.symlink("../outside/app.js.br", "site/app.js.br");
+        }
+        dir
+    }
+
+    #[test]
+    fn plain_paths_are_joined_decoded() {
+        let root = Path::new("/srv/site");
+        let policy = PathPolicy::default();
+
+        assert_eq!(policy.join(root, "/"), Ok(root.to_owned()));
+        assert_eq!(
+            policy.join(root, "/docs//read%20me.txt"),
+            Ok(root.join("docs").join("read me.txt"))
+        );
+        assert_eq!(policy.join(root, "/caf%C3%A9"), Ok(root.join("café")));
+        assert_eq!(
+            policy.segments("/a/b/"),
+            Ok(vec!["a".to_owned(), "b".to_owned()])
+        );
+    }
+
+    #[test]
+    fn segments_leaving_the_directory_are_malformed() {
+        let root = Path::new("/srv/site");
+        for path in [
+            "/..",
+            "/docs/../../etc/passwd",
+            "/%2e%2e/etc/passwd",
+            "/%2E%2E",
+            "/.",
+            "/%2e",
+            "/docs%2f..%2f..%2fetc",
+            "/docs%2F..",
+            "/..%5c..%5cwindows",
+            "/%5C",
+            "/index.html%00.txt",
+            "/%00",
+            "/%ff",
+        ] {
+            for policy in [PathPolicy::default(), hidden_files()] {
+                assert_eq!(
+                    policy.join(root, path),
+                    Err(PathError::Malformed),
+                    "{}",
+                    path
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dotfiles_are_hidden_unless_allowed() {
+        let root = Path::new("/srv/site");
+        for path in ["/.env", "/%2eenv", "/.git/config", "/docs/.htaccess"] {
+            assert_eq!(
+                PathPolicy::default().join(root, path),
+                Err(PathError::Hidden),
+                "{}",
+                path
+            );
+            assert!(hidden_files().join(root, path).is_ok(), "{}", path);
+        }
+
+        assert!(!PathPolicy::default().lists(".env"));
+        assert!(hidden_files().lists(".env"));
+        assert!(PathPolicy::default().lists("env"));
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn symlinks_may_not_leave_the_directory() {
+        let dir = fixture();
+        let root = dir.path().join("site");
+        let allows = |policy: PathPolicy, relative: &str| {
+            let path = AsyncPathBuf::from(root.join(relative));
+            let root = root.clone();
+            async move { policy.allows(&root, &path).await.unwrap() }
+        };
+
+        for relative in [
+            "index.html",
+            "docs/readme.txt",
+            "inside-link",
+            "docs/up-link",
+        ] {
+            assert!(
+                allows(PathPolicy::default(), relative).await,
+                "{}",
+                relative
+            );
+        }
+        for relative in [
+            "outside-link",
+            "outside-dir",
+            "outside-dir/secret.txt",
+            "app.js.br",
+        ] {
+            assert!(
+                !allows(PathPolicy::default(), relative).await,
+                "{}",
+                relative
+            );
+            assert!(allows(follow(), relative).await, "{}", relative);
+        }
+    }
+
+    mod serve_dir {
+        use http_types::{
+            headers, Method, Request as HttpRequest, Response as HttpResponse, StatusCode, Url,
+        };
+
+        use super::{super::super::SymlinkPolicy, fixture, TestDir};
+        use crate::Server;
+
+        fn app(dir: &TestDir, symlinks: SymlinkPolicy) -> Server {
+            let mut app = crate::new();
+            app.at("/static/*")
+                .serve_dir_with(dir.path().join("site"), |serve_dir| {
+                    serve_dir.symlinks(symlinks).precompressed(true)
+                })
+                .unwrap();
+            app
+        }
+
+        async fn get(app: &Server, path: &str) -> (StatusCode, String) {
+            let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+            let mut req = HttpRequest::new(Method::Get, url);
+            req.insert_header(headers::ACCEPT_ENCODING, "br");
+            let mut res: HttpResponse = app.respond(req).await.unwrap();
+            (res.status(), res.body_string().await.unwrap())
+        }
+
+        #[async_std::test]
+        async fn encoded_separators_and_nul_bytes_are_refused() {
+            let dir = fixture();
+            let app = app(&dir, SymlinkPolicy::Deny);
+
+            for path in [
+                "/static/docs%2freadme.txt",
+                "/static/docs%2Freadme.txt",
+                "/static/docs%5creadme.txt",
+                "/static/..%2f..%2foutside%2fsecret.txt",
+                "/static/index.html%00",
+            ] {
+                assert_eq!(get(&app, path).await.0, StatusCode::BadRequest, "{}", path);
+            }
+        }
+
+        #[async_std::test]
+        async fn dot_segments_never_reach_outside_files() {
+            let dir = fixture();
+            let app = app(&dir, SymlinkPolicy::Deny);
+
+            // The URL parser resolves them, leaving the route behind
+            for path in [
+                "/static/../outside/secret.txt",
+                "/static/%2e%2e/outside/secret.txt",
+                "/static/docs/%2E%2E/%2e%2e/%2e%2e/outside/secret.txt",
+            ] {
+                let (status, body) = get(&app, path).await;
+                assert_eq!(status, StatusCode::NotFound, "{}", path);
+                assert_ne!(body, "secret");
+            }
+
+            assert_eq!(
+                get(&app, "/static/docs/%2e%2e/index.html").await,
+                (StatusCode::Ok, "home".to_owned())
+            );
+        }
+
+        #[async_std::test]
+        async fn dotfiles_are_not_found() {
+            let dir = fixture();
+            let app = app(&dir, SymlinkPolicy::Deny);
+
+            assert_eq!(get(&app, "/static/.env").await.0, StatusCode::NotFound);
+            assert_eq!(get(&app, "/static/%2eenv").await.0, StatusCode::NotFound);
+        }
+
+        #[cfg(unix)]
+        #[async_std::test]
+        async fn symlinks_out_of_the_directory_are_forbidden() {
+            let dir = fixture();
+            let app = app(&dir, SymlinkPolicy::Deny);
+
+            assert_eq!(
+                get(&app, "/static/inside-link").await,
+                (StatusCode::Ok, "home".to_owned())
+            );
+            assert_eq!(
+                get(&app, "/static/outside-link").await.0,
+                StatusCode::Forbidden
+            );
+            assert_eq!(
+                get(&app, "/static/outside-dir/secret.txt").await.0,
+                StatusCode::Forbidden
+            );
+            // The escaping brotli sibling is passed over for the original
+            assert_eq!(
+                get(&app, "/static/app.js").await,
+                (StatusCode::Ok, "original".to_owned())
+            );
+
+            let app = self::app(&dir, SymlinkPolicy::Follow);
+            assert_eq!(
+                get(&app, "/static/outside-link").await,
+                (StatusCode::Ok, "secret".to_owned())
+            );
+            assert_eq!(
+                get(&app, "/static/app.js").await,
+                (StatusCode::Ok, "leaked".to_owned())
+            );
+        }
+    }
+}
@@ -0,0 +1,480 @@
+use std::{io::SeekFrom, ops::RangeInclusive};
+
+use async_std::{
+    fs::File,
+    io::{prelude::SeekExt, BufReader, Cursor, Read, ReadExt},
+    path::Path as AsyncPath,
+};
+use http_types::{
+    conditional::{ETag, LastModified},
+    Body, Mime,
+};
+use rand::RngCore;
+
+use crate::conditional::Validators;
+
+/// Requests asking for more ranges than this get the whole file instead.
+pub(crate) const MAX_RANGES: usize = 16;
+
+type PartReader = Box<dyn Read + Unpin + Send + Sync + 'static>;
+
+/// What a `Range` header asks for, resolved against the file length.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum RangeRequest {
+    /// No usable `Range` header, send the whole file.
+    Full,
+    Satisfiable(Vec<RangeInclusive<u64>>),
+    Unsatisfiable,
+}
+
+impl RangeRequest {
+    /// Parse `bytes=` ranges, ignoring headers that are malformed or use another unit.
+    pub(crate) fn parse(header: &str, len: u64) -> Self {
+        let specs = match header.trim().strip_prefix("bytes=") {
+            Some(specs) => specs,
+            None => return Self::Full,
+        };
+
+        let mut ranges = Vec::new();
+        let mut specified = 0;
+        for spec in specs
+            .split(',')
+            .map(str::trim)
+            .filter(|spec| !spec.is_empty())
+        {
+            let (start, end) = match spec.split_once('-') {
+                Some(bounds) => bounds,
+                None => return Self::Full,
+            };
+
+            let range = match (start.trim(), end.trim()) {
+                ("", "") => return Self::Full,
+                // Suffix range, the last `end` bytes
+                ("", suffix) => match position(suffix) {
+                    Some(0) => None,
+                    Some(suffix) if len > 0 => Some(len.saturating_sub(suffix)..=len - 1),
+                    Some(_) => None,
+                    None => return Self::Full,
+                },
+                (start, end) => {
+                    let start = match position(start) {
+                        Some(start) => start,
+                        None => return Self::Full,
+                    };
+                    let end = match end {
+                        "" => u64::MAX,
+                        end => match position(end) {
+                            Some(end) if end >= start => end,
+                            _ => return Self::Full,
+                        },
+                    };
+                    (start < len).then(|| start..=end.min(len - 1))
+                }
+            };
+
+            specified += 1;
+            ranges.extend(range);
+        }
+
+        // `bytes=` without a single range is malformed rather than unsatisfiable
+        if specified == 0 {
+            return Self::Full;
+        }
+        if ranges.is_empty() {
+            return Self::Unsatisfiable;
+        }
+        if ranges.len() > MAX_RANGES {
+            return Self::Full;
+        }
+
+        Self::Satisfiable(coalesce(ranges))
+    }
+}
+
+/// A byte position, digits only. Positions past `u64::MAX` are as good as `u64::MAX`, they
+/// are beyond the end of any file anyway.
+fn position(digits: &str) -> Option<u64> {
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    Some(digits.parse().unwrap_or(u64::MAX))
+}
+
+/// Merge overlapping and adjacent ranges, so no byte is sent twice.
+fn coalesce(mut ranges: Vec<RangeInclusive<u64>>) -> Vec<RangeInclusive<u64>> {
+    ranges.sort_by_key(|range| *range.start());
+
+    let mut merged: Vec<RangeInclusive<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if *range.start() <= last.end().saturating_add(1) => {
+                *last = *last.start()..=*last.end().max(range.end());
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// `If-Range` only lets the range through if the file is unchanged, judged by a strong
+/// comparison of the `ETag` or an exact `Last-Modified` date.
+pub(crate) fn if_range_matches(header: &str, validators: &Validators) -> bool {
+    let header = header.trim();
+
+    if header.starts_with("W/") {
+        return false;
+    }
+
+    if let Some(tag) = header
+        .strip_prefix('"')
+        .and_then(|tag| tag.strip_suffix('"'))
+    {
+        return matches!(&validators.etag, Some(ETag::Strong(current)) if current == tag);
+    }
+
+    validators
+        .last_modified
+        .is_some_and(|last_modified| LastModified::new(last_modified).value() == header)
+}
+
+pub(crate) fn content_range(range: &RangeInclusive<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{}", range.start(), range.end(), len)
+}
+
+/// The bytes of `range`, read from `path` after seeking to its start.
+async fn read_range(path: &AsyncPath, range: &RangeInclusive<u64>) -> std::io::Result<PartReader> {
+    let mut file = File::open(path).await?;
+    file.seek(SeekFrom::Start(*range.start())).await?;
+    Ok(Box::new(file.take(range.end() - range.start() + 1)))
+}
+
+pub(crate) async fn single_range_body(
+    path: &AsyncPath,
+    range: &RangeInclusive<u64>,
+    mime: Mime,
+) -> std::io::Result<Body> {
+    let reader = read_range(path, range).await?;
+    let len = range.end() - range.start() + 1;
+
+    let mut body = Body::from_reader(BufReader::new(reader), Some(len as usize));
+    body.set_mime(mime);
+    Ok(body)
+}
+
+/// A `multipart/byteranges` body with a part per range, streamed from the file.
+pub(crate) async fn multipart_body(
+    path: &AsyncPath,
+    ranges: &[RangeInclusive<u64>],
+    len: u64,
+    mime: &Mime,
+) -> std::io::Result<Body> {
+    let boundary = boundary();
+
+    let mut reader: PartReader = Box::new(Cursor::new(Vec::new()));
+    let mut total = 0;
+
+    for range in ranges {
+        let head = format!(
+            "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+            boundary,
+            mime,
+            content_range(range, len)
+        );
+        total += head.len() as u64 + range.end() - range.start() + 1;
+
+        let part = read_range(path, range).await?;
+        reader = Box::new(reader.chain(Cursor::new(head.into_bytes())).chain(part));
+    }
+
+    let tail = format!("\r\n--{}--\r\n", boundary);
+    total += tail.len() as u64;
+    reader = Box::new(reader.chain(Cursor::new(tail.into_bytes())));
+
+    let mut body = Body::from_reader(BufReader::new(reader), Some(total as usize));
+    body.set_mime(multipart_mime(&boundary));
+    Ok(body)
+}
+
+fn boundary() -> String {
+    let mut bytes = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// A `multipart/byteranges` body with a part per range of `contents`.
+pub(crate) fn multipart_bytes(
+    contents: &[u8],
+    ranges: &[RangeInclusive<u64>],
+    mime: &Mime,
+) -> Body {
+    let boundary = boundary();
+    let len = contents.len() as u64;
+    let mut bytes = Vec::new();
+
+    for range in ranges {
+        bytes.extend_from_slice(
+            format!(
+                "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                boundary,
+                mime,
+                content_range(range, len)
+            )
+            .as_bytes(),
+        );
+        bytes.extend_from_slice(&contents[*range.start() as usize..=*range.end() as usize]);
+    }
+    bytes.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    let mut body = Body::from_bytes(bytes);
+    body.set_mime(multipart_mime(&boundary));
+    body
+}
+
+fn multipart_mime(boundary: &str) -> Mime {
+    format!("multipart/byteranges; boundary={}", boundary)
+        .parse()
+        .expect("the boundary is a valid parameter")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use http_types::{
+        headers, Method, Request as HttpRequest, Response as HttpResponse, StatusCode, Url,
+    };
+
+    use super::{super::test_dir::TestDir, *};
+    use crate::Server;
+
+    fn satisfiable(ranges: &[RangeInclusive<u64>]) -> RangeRequest {
+        RangeRequest::Satisfiable(ranges.to_vec())
+    }
+
+    #[test]
+    fn ranges_are_clamped_to_the_file() {
+        assert_eq!(RangeRequest::parse("bytes=0-0", 10), satisfiable(&[0..=0]));
+        assert_eq!(RangeRequest::parse("bytes=2-", 10), satisfiable(&[2..=9]));
+        assert_eq!(
+            RangeRequest::parse("bytes=5-100", 10),
+            satisfiable(&[5..=9])
+        );
+        assert_eq!(RangeRequest::parse("bytes=-3", 10), satisfiable(&[7..=9]));
+        assert_eq!(RangeRequest::parse("bytes=-20", 10), satisfiable(&[0..=9]));
+        assert_eq!(
+            RangeRequest::parse("bytes=0-99999999999999999999999", 10),
+            satisfiable(&[0..=9])
+        );
+        assert_eq!(
+            RangeRequest::parse(" bytes=1-2 ", 10),
+            satisfiable(&[1..=2])
+        );
+    }
+
+    #[test]
+    fn ranges_outside_the_file_are_unsatisfiable() {
+        assert_eq!(
+            RangeRequest::parse("bytes=10-", 10),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(
+            RangeRequest::parse("bytes=10-20", 10),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(
+            RangeRequest::parse("bytes=-0", 10),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(
+            RangeRequest::parse("bytes=99999999999999999999999-", 10),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(
+            RangeRequest::parse("bytes=0-", 0),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(
+            RangeRequest::parse("bytes=-5", 0),
+            RangeRequest::Unsatisfiable
+        );
+
+        // One satisfiable range is enough
+        assert_eq!(
+            RangeRequest::parse("bytes=20-30, 0-1", 10),
+            satisfiable(&[0..=1])
+        );
+    }
+
+    #[test]
+    fn malformed_headers_are_ignored() {
+        for header in [
+            "",
+            "bytes",
+            "bytes=",
+            "bytes=,",
+            "bytes=-",
+            "bytes=5",
+            "bytes=5-2",
+            "bytes=a-b",
+            "bytes=+1-2",
+            "bytes=1-+2",
+            "bytes=--1",
+            "bytes=0-1,x",
+            "items=0-1",
+        ] {
+            assert_eq!(
+                RangeRequest::parse(header, 10),
+                RangeRequest::Full,
+                "{}",
+                header
+            );
+        }
+    }
+
+    #[test]
+    fn ranges_are_sorted_and_merged() {
+        assert_eq!(
+            RangeRequest::parse("bytes=6-7,0-1", 10),
+            satisfiable(&[0..=1, 6..=7])
+        );
+        assert_eq!(
+            RangeRequest::parse("bytes=0-1,2-3,,3-5", 10),
+            satisfiable(&[0..=5])
+        );
+        assert_eq!(
+            RangeRequest::parse("bytes=0-4,-3", 10),
+            satisfiable(&[0..=4, 7..=9])
+        );
+        assert_eq!(
+            RangeRequest::parse("bytes=-2,8-", 10),
+            satisfiable(&[8..=9])
+        );
+    }
+
+    #[test]
+    fn too_many_ranges_get_the_whole_file() {
+        let header = |count: u64| {
+            let specs: Vec<String> = (0..count).map(|i| format!("{}-{}", i * 2, i * 2)).collect();
+            format!("bytes={}", specs.join(","))
+        };
+
+        assert!(matches!(
+            RangeRequest::parse(&header(MAX_RANGES as u64), 100),
+            RangeRequest::Satisfiable(ranges) if ranges.len() == MAX_RANGES
+        ));
+        assert_eq!(
+            RangeRequest::parse(&header(MAX_RANGES as u64 + 1), 100),
+            RangeRequest::Full
+        );
+    }
+
+    #[test]
+    fn if_range_needs_an_exact_match() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let validators = Validators {
+            etag: Some(ETag::new("v1".into())),
+            last_modified: Some(modified),
+        };
+        let date = LastModified::new(modified).value().to_string();
+
+        assert!(if_range_matches("\"v1\"", &validators));
+        assert!(!if_range_matches("\"v2\"", &validators));
+        assert!(!if_range_matches("W/\"v1\"", &validators));
+        assert!(if_range_matches(&date, &validators));
+        assert!(!if_range_matches(
+            "Thu, 01 Jan 1970 00:00:00 GMT",
+            &validators
+        ));
+
+        let weak = Validators {
+            etag: Some(ETag::new_weak("v1".into())),
+            last_modified: None,
+        };
+        assert!(!if_range_matches("\"v1\"", &weak));
+    }
+
+    #[async_std::test]
+    async fn multipart_bodies_hold_every_range() {
+        let mime: Mime = "text/plain".parse().unwrap();
+        let body = multipart_bytes(b"0123456789", &[0..=1, 8..=9], &mime);
+        let boundary = body.mime().param("boundary").unwrap().as_str().to_owned();
+        let body = body.into_string().await.unwrap();
+
+        assert_eq!(
+            body,
+            format!(
+                "\r\n--{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+                 \r\n--{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+                 \r\n--{b}--\r\n",
+                b = boundary
+            )
+        );
+    }
+
+    fn app(dir: &TestDir) -> Server {
+        let mut app = crate::new();
+        let file = dir.file("digits.txt", "0123456789");
+        app.at("/digits.txt").serve_file(file).unwrap();
+        app
+    }
+
+    async fn get(
+        app: &Server,
+        method: Method,
+        range: &str,
+        if_range: Option<&str>,
+    ) -> HttpResponse {
+        let url = Url::parse("http://localhost/digits.txt").unwrap();
+        let mut req = HttpRequest::new(method, url);
+        req.insert_header("Range", range);
+        if let Some(if_range) = if_range {
+            req.insert_header(headers::IF_RANGE, if_range);
+        }
+        app.respond(req).await.unwrap()
+    }
+
+    #[async_std::test]
+    async fn files_are_served_in_ranges() {
+        let dir = TestDir::new();
+        let app = app(&dir);
+
+        let mut res = get(&app, Method::Get, "bytes=2-4", None).await;
+        assert_eq!(res.status(), StatusCode::PartialContent);
+        assert_eq!(res.header(headers::CONTENT_RANGE).unwrap(), "bytes 2-4/10");
+        assert_eq!(res.body_string().await.unwrap(), "234");
+
+        let mut res = get(&app, Method::Get, "bytes=0-0,-1", None).await;
+        assert_eq!(res.status(), StatusCode::PartialContent);
+        assert!(res.content_type().unwrap().essence() == "multipart/byteranges");
+        let body = res.body_string().await.unwrap();
+        assert!(body.contains("Content-Range: bytes 0-0/10\r\n\r\n0\r\n"));
+        assert!(body.contains("Content-Range: bytes 9-9/10\r\n\r\n9\r\n"));
+
+        let res = get(&app, Method::Get, "bytes=10-", None).await;
+        assert_eq!(res.status(), StatusCode::RequestedRangeNotSatisfiable);
+        assert_eq!(res.header(headers::CONTENT_RANGE).unwrap(), "bytes */10");
+
+        let mut res = get(&app, Method::Get, "bytes=", None).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.body_string().await.unwrap(), "0123456789");
+    }
+
+    #[async_std::test]
+    async fn ranges_are_ignored_for_changed_files_and_head() {
+        let dir = TestDir::new();
+        let app = app(&dir);
+
+        let res = get(&app, Method::Get, "bytes=0-0", None).await;
+        let etag = res.header(headers::ETAG).unwrap().as_str().to_owned();
+
+        let res = get(&app, Method::Get, "bytes=0-0", Some(&etag)).await;
+        assert_eq!(res.status(), StatusCode::PartialContent);
+
+        let mut res = get(&app, Method::Get, "bytes=0-0", Some("\"changed\"")).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.body_string().await.unwrap(), "0123456789");
+
+        let res = get(&app, Method::Head, "bytes=0-0", None).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+    }
+}
@@ -1,65 +1,500 @@
-use async_std::path::PathBuf as AsyncPathBuf;
+use async_std::path::{Path as AsyncPath, PathBuf as AsyncPathBuf};
 use async_trait::async_trait;
-use http_types::{Body, StatusCode};
+use http_types::{Method, StatusCode};
 use kv_log_macro::{info, warn};
 use std::{
-    ffi::OsStr,
     io,
     path::{Path, PathBuf},
 };
 
-use crate::{Endpoint, Request, Response};
+use super::{
+    cache_control::{CacheRules, IMMUTABLE},
+    manifest::relative_path,
+    path_policy::{PathError, PathPolicy},
+    serve_file,
+    writable::{self, Target, DEFAULT_MAX_UPLOAD_SIZE},
+    AssetManifest, Confinement, DirectoryListing, FileOptions, FilePattern, MimeTypes,
+    SymlinkPolicy,
+};
+use crate::{Endpoint, Redirect, Request, Response};
+
+pub(crate) const DEFAULT_INDEX_FILE: &str = "index.html";
 
-pub(crate) struct ServeDir {
+/// Serves the files below a directory, configured through `Route::serve_dir_with`.
+pub struct ServeDir {
     prefix: String,
     dir: PathBuf,
+    options: FileOptions,
+    index_files: Vec<String>,
+    listing: Option<DirectoryListing>,
+    spa_fallback: Option<PathBuf>,
+    redirect_directories: bool,
+    cache_rules: CacheRules,
+    manifest: Option<AssetManifest>,
+    policy: PathPolicy,
+    writable: bool,
+    max_upload_size: u64,
 }
 
 impl ServeDir {
     pub(crate) fn new(prefix: String, dir: PathBuf) -> Self {
-        Self { prefix, dir }
+        Self {
+            prefix,
+            dir,
+            options: FileOptions::default(),
+            index_files: vec![DEFAULT_INDEX_FILE.to_owned()],
+            listing: None,
+            spa_fallback: None,
+            redirect_directories: true,
+            cache_rules: CacheRules::default(),
+            manifest: None,
+            policy: PathPolicy::default(),
+            writable: false,
+            max_upload_size: DEFAULT_MAX_UPLOAD_SIZE,
+        }
+    }
+
+    /// Files to look for when a directory is requested, tried in order. Replaces the
+    /// default of `index.html`, pass an empty list to disable index files.
+    #[must_use]
+    pub fn index_files<T: Into<String>>(
+        mut self,
+        index_files: impl IntoIterator<Item = T>,
+    ) -> Self {
+        self.index_files = index_files.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// List the contents of directories without an index file, instead of answering `404`.
+    #[must_use]
+    pub fn listing(mut self, listing: DirectoryListing) -> Self {
+        self.listing = Some(listing);
+        self
+    }
+
+    /// Serve `file`, relative to the directory, for paths that don't exist, so a
+    /// single-page app can route them on the client. Paths whose last segment has an
+    /// extension look like missing assets and still get a `404`.
+    #[must_use]
+    pub fn spa_fallback(mut self, file: impl AsRef<Path>) -> Self {
+        self.spa_fallback = Some(file.as_ref().to_owned());
+        self
+    }
+
+    /// Redirect `/dir` to `/dir/`, so relative links in index files and listings resolve
+    /// inside the directory. On by default.
+    #[must_use]
+    pub fn redirect_directories(mut self, redirect_directories: bool) -> Self {
+        self.redirect_directories = redirect_directories;
+        self
+    }
+
+    /// Derive `ETag` and `Last-Modified` from file metadata, on by default.
+    #[must_use]
+    pub fn metadata_validators(mut self, metadata_validators: bool) -> Self {
+        self.options.metadata_validators = metadata_validators;
+        self
+    }
+
+    /// Send a `.br`, `.zst` or `.gz` sibling of a file when the client accepts that
+    /// encoding, e.g. as emitted by frontend build tools.
+    #[must_use]
+    pub fn precompressed(mut self, precompressed: bool) -> Self {
+        self.options.precompressed = precompressed;
+        self
+    }
+
+    /// Pick content types from `mime_types` instead of the default table.
+    #[must_use]
+    pub fn mime_types(mut self, mime_types: MimeTypes) -> Self {
+        self.options.mime_types = mime_types;
+        self
+    }
+
+    /// Send `X-Content-Type-Options: nosniff`, on by default.
+    #[must_use]
+    pub fn nosniff(mut self, nosniff: bool) -> Self {
+        self.options.nosniff = nosniff;
+        self
+    }
+
+    /// Send `value` as the `Cache-Control` header for files matching `pattern`, e.g.
+    /// `no-cache` for `index.html`. The first matching pattern wins.
+    ///
+    /// String patterns are file names, `"*"` or `"*.ext"` suffix globs; any other `*`
+    /// panics, match those with a `Regex` instead.
+    #[must_use]
+    pub fn cache_control(
+        mut self,
+        pattern: impl Into<FilePattern>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.cache_rules.push(pattern.into(), value.into());
+        self
+    }
+
+    /// Serve the fingerprinted names of `manifest` as well, mapped back to their files if
+    /// they don't exist on disk, and cache them as immutable unless a `cache_control`
+    /// pattern says otherwise.
+    #[must_use]
+    pub fn manifest(mut self, manifest: AssetManifest) -> Self {
+        self.manifest = Some(manifest);
+        self
+    }
+
+    /// What to do with symlinks that lead out of the directory, denied by default.
+    #[must_use]
+    pub fn symlinks(mut self, symlinks: SymlinkPolicy) -> Self {
+        self.policy.symlinks = symlinks;
+        self
+    }
+
+    /// Serve and list files and directories whose name starts with `.`, such as `.env` or
+    /// `.git`. Off by default, requests for them get a `404`.
+    #[must_use]
+    pub fn hidden_files(mut self, hidden_files: bool) -> Self {
+        self.policy.hidden_files = hidden_files;
+        self
+    }
+
+    /// Accept `PUT` uploads, `DELETE`, `MKCOL` and `PROPFIND` below the directory. Off by
+    /// default, guard the route with an auth middleware before turning it on.
+    #[must_use]
+    pub fn writable(mut self, writable: bool) -> Self {
+        self.writable = writable;
+        self
+    }
+
+    /// Refuse uploads larger than `max_upload_size` bytes with `413`, 100MB by default.
+    #[must_use]
+    pub fn max_upload_size(mut self, max_upload_size: u64) -> Self {
+        self.max_upload_size = max_upload_size;
+        self
+    }
+
+    pub(crate) fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    fn confinement(&self) -> Confinement<'_> {
+        Confinement {
+            root: &self.dir,
+            policy: &self.policy,
+        }
+    }
+
+    fn cache_control_for(&self, relative_path: &str, fingerprinted: bool) -> Option<&str> {
+        self.cache_rules
+            .find(relative_path)
+            .or_else(|| fingerprinted.then_some(IMMUTABLE))
+    }
+
+    /// Resolve a fingerprinted name that only exists in the manifest to its file.
+    async fn resolve_fingerprinted(&self, file_path: AsyncPathBuf) -> (AsyncPathBuf, bool) {
+        let manifest = match &self.manifest {
+            Some(manifest) => manifest,
+            None => return (file_path, false),
+        };
+
+        let relative = relative_path(&self.dir, file_path.as_ref());
+        match manifest.logical(&relative) {
+            Some(logical) if !file_path.exists().await => {
+                (AsyncPathBuf::from(self.dir.join(logical)), true)
+            }
+            Some(_) => (file_path, true),
+            None => (file_path, false),
+        }
+    }
+
+    async fn serve_directory(&self, req: &Request, dir: &AsyncPath) -> crate::Result {
+        let url_path = req.url().path();
+
+        if !url_path.ends_with('/') {
+            if !self.redirect_directories {
+                return Ok(Response::new(StatusCode::NotFound));
+            }
+
+            let location = match req.url().query() {
+                Some(query) => format!("{}/?{}", url_path, query),
+                None => format!("{}/", url_path),
+            };
+            return Ok(Redirect::permanent(location).into());
+        }
+
+        for index_file in &self.index_files {
+            let index_path = dir.join(index_file);
+            if index_path.is_file().await && self.policy.allows(&self.dir, &index_path).await? {
+                let relative = relative_path(&self.dir, index_path.as_ref());
+                let cache_control = self.cache_control_for(&relative, false);
+                return serve_file(
+                    req,
+                    &index_path,
+                    &self.options,
+                    cache_control,
+                    Some(self.confinement()),
+                )
+                .await;
+            }
+        }
+
+        match self.listing {
+            Some(listing) => {
+                let mut res = listing
+                    .respond(req, dir, url_path, self.confinement())
+                    .await?;
+                if self.options.nosniff {
+                    res.insert_header("X-Content-Type-Options", "nosniff");
+                }
+                Ok(res)
+            }
+            None => Ok(Response::new(StatusCode::NotFound)),
+        }
+    }
+
+    async fn serve_missing(&self, req: &Request, file_path: &AsyncPath) -> crate::Result {
+        let fallback = match &self.spa_fallback {
+            Some(fallback) if is_navigation(req) => fallback,
+            _ => {
+                warn!("File not found: {:?}", file_path);
+                return Ok(Response::new(StatusCode::NotFound));
+            }
+        };
+
+        // The fallback stands in for every unknown path, don't let caches mix them up
+        let fallback_path = AsyncPathBuf::from(self.dir.join(fallback));
+        if fallback_path.exists().await && !self.policy.allows(&self.dir, &fallback_path).await? {
+            warn!("Unauthorized attempt to read: {:?}", fallback_path);
+            return Ok(Response::new(StatusCode::Forbidden));
+        }
+        let cache_control = self
+            .cache_rules
+            .find(&relative_path(&self.dir, fallback_path.as_ref()))
+            .unwrap_or("no-cache");
+
+        serve_file(
+            req,
+            &fallback_path,
+            &self.options,
+            Some(cache_control),
+            Some(self.confinement()),
+        )
+        .await
     }
 }
 
 #[async_trait]
 impl Endpoint for ServeDir {
-    async fn call(&self, req: Request) -> crate::Result {
+    async fn call(&self, mut req: Request) -> crate::Result {
         let path = req.url().path();
         let path = path
-            .strip_prefix(&self.prefix.trim_end_matches('*'))
+            .strip_prefix(self.prefix.trim_end_matches('*'))
             .unwrap();
-        let path = path.trim_start_matches('/');
-        let mut file_path = self.dir.clone();
-        for p in Path::new(path) {
-            if p == OsStr::new(".") {
-                continue;
-            } else if p == OsStr::new("..") {
-                file_path.pop();
-            } else {
-                file_path.push(&p);
+        let file_path = match self.policy.join(&self.dir, path) {
+            Ok(file_path) => file_path,
+            Err(PathError::Malformed) => {
+                warn!("Malformed file path: {:?}", path);
+                return Ok(Response::new(StatusCode::BadRequest));
             }
-        }
+            Err(PathError::Hidden) => {
+                warn!("Hidden file requested: {:?}", path);
+                return Ok(Response::new(StatusCode::NotFound));
+            }
+        };
 
         info!("Requested file: {:?}", file_path);
 
-        let file_path = AsyncPathBuf::from(file_path);
-        if !file_path.starts_with(&self.dir) {
-            warn!("Unauthorized attempt to read: {:?}", file_path);
-            Ok(Response::new(StatusCode::Forbidden))
-        } else {
-            match Body::from_file(&file_path).await {
-                Ok(body) => {
-                    let mut res = Response::new(StatusCode::Ok);
-                    res.set_body(body);
-
-                    Ok(res)
-                }
-                Err(e) if e.kind() == io::ErrorKind::NotFound => {
-                    warn!("File not found: {:?}", &file_path);
-                    Ok(Response::new(StatusCode::NotFound))
+        if self.writable {
+            let file_path = AsyncPathBuf::from(&file_path);
+            let target = Target {
+                root: &self.dir,
+                path: &file_path,
+                policy: &self.policy,
+            };
+            match req.method() {
+                Method::Put => return writable::put(&mut req, target, self.max_upload_size).await,
+                Method::Delete => return writable::delete(target).await,
+                Method::MkCol => return writable::mkcol(&req, target).await,
+                Method::PropFind => {
+                    return writable::propfind(&req, target, &self.options.mime_types).await
                 }
-                Err(e) => Err(e.into()),
+                _ => {}
             }
         }
+
+        let (file_path, fingerprinted) = self
+            .resolve_fingerprinted(AsyncPathBuf::from(file_path))
+            .await;
+
+        let metadata = file_path.metadata().await;
+        if metadata.is_ok() && !self.policy.allows(&self.dir, &file_path).await? {
+            warn!("Unauthorized attempt to read: {:?}", file_path);
+            return Ok(Response::new(StatusCode::Forbidden));
+        }
+
+        match metadata {
+            Ok(metadata) if metadata.is_dir() => self.serve_directory(&req, &file_path).await,
+            Ok(_) => {
+                let relative = relative_path(&self.dir, file_path.as_ref());
+                let cache_control = self.cache_control_for(&relative, fingerprinted);
+                serve_file(
+                    &req,
+                    &file_path,
+                    &self.options,
+                    cache_control,
+                    Some(self.confinement()),
+                )
+                .await
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.serve_missing(&req, &file_path).await
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// A request for a page rather than an asset: the last segment has no extension.
+pub(crate) fn is_navigation(req: &Request) -> bool {
+    let last_segment = req.url().path().rsplit('/').next().unwrap_or_default();
+    matches!(req.method(), Method::Get | Method::Head) && !last_segment.contains('.')
+}
+
+#[cfg(test)]
+mod tests {
+    use http_types::{headers, Request as HttpRequest, Response as HttpResponse, Url};
+
+    use super::{super::test_dir::TestDir, *};
+    use crate::Server;
+
+    /// A site with an index at the root, a directory without one and an app shell.
+    fn site() -> TestDir {
+        let dir = TestDir::new();
+        dir.file("index.html", "home");
+        dir.file("docs/readme.txt", "readme");
+        dir.file("docs/default.htm", "default");
+        dir.file("app.html", "shell");
+        dir
+    }
+
+    fn app(dir: &TestDir, configure: impl Fn(ServeDir) -> ServeDir) -> Server {
+        let mut app = crate::new();
+        app.at("/static/*")
+            .serve_dir_with(dir.path(), configure)
+            .unwrap();
+        app
+    }
+
+    async fn get(app: &Server, path: &str, accept: Option<&str>) -> HttpResponse {
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        let mut req = HttpRequest::new(Method::Get, url);
+        if let Some(accept) = accept {
+            req.insert_header(headers::ACCEPT, accept);
+        }
+        app.respond(req).await.unwrap()
+    }
+
+    async fn body(app: &Server, path: &str) -> (StatusCode, String) {
+        let mut res = get(app, path, None).await;
+        (res.status(), res.body_string().await.unwrap())
+    }
+
+    #[async_std::test]
+    async fn directories_serve_their_index_file() {
+        let dir = site();
+        let app = app(&dir, |serve_dir| serve_dir);
+
+        assert_eq!(
+            body(&app, "/static/").await,
+            (StatusCode::Ok, "home".to_owned())
+        );
+        assert_eq!(body(&app, "/static/docs/").await.0, StatusCode::NotFound);
+
+        let app = self::app(&dir, |serve_dir| {
+            serve_dir.index_files(["index.html", "default.htm"])
+        });
+        assert_eq!(
+            body(&app, "/static/docs/").await,
+            (StatusCode::Ok, "default".to_owned())
+        );
+
+        let app = self::app(&dir, |serve_dir| {
+            serve_dir.index_files(Vec::<String>::new())
+        });
+        assert_eq!(body(&app, "/static/").await.0, StatusCode::NotFound);
+    }
+
+    #[async_std::test]
+    async fn directories_redirect_to_their_trailing_slash() {
+        let dir = site();
+        let app = app(&dir, |serve_dir| serve_dir);
+
+        let res = get(&app, "/static/docs?page=2", None).await;
+        assert_eq!(res.status(), StatusCode::PermanentRedirect);
+        assert_eq!(
+            res.header(headers::LOCATION).unwrap(),
+            "/static/docs/?page=2"
+        );
+
+        let app = self::app(&dir, |serve_dir| serve_dir.redirect_directories(false));
+        assert_eq!(body(&app, "/static/docs").await.0, StatusCode::NotFound);
+    }
+
+    #[async_std::test]
+    async fn directories_without_an_index_are_listed() {
+        let dir = site();
+        dir.file("docs/.secret", "hidden");
+        let app = app(&dir, |serve_dir| {
+            serve_dir.listing(DirectoryListing::Negotiate)
+        });
+
+        let mut res = get(&app, "/static/docs/", Some("text/html")).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.header("X-Content-Type-Options").unwrap(), "nosniff");
+        let html = res.body_string().await.unwrap();
+        assert!(html.contains("<a href=\"readme.txt\">readme.txt</a>"));
+        assert!(!html.contains(".secret"));
+
+        let mut res = get(&app, "/static/docs/", Some("application/json")).await;
+        let entries: serde_json::Value = res.body_json().await.unwrap();
+        let names: Vec<&str> = entries
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["default.htm", "readme.txt"]);
+    }
+
+    #[async_std::test]
+    async fn unknown_pages_fall_back_to_the_app_shell() {
+        let dir = site();
+        dir.file("app.js", "boot()");
+        let app = app(&dir, |serve_dir| {
+            serve_dir
+                .spa_fallback("app.html")
+                .cache_control("*.js", "max-age=60")
+        });
+
+        let res = get(&app, "/static/users/42", None).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.header(headers::CACHE_CONTROL).unwrap(), "no-cache");
+        assert_eq!(
+            body(&app, "/static/users/42").await,
+            (StatusCode::Ok, "shell".to_owned())
+        );
+
+        // Missing assets are not pages
+        assert_eq!(
+            body(&app, "/static/missing.js").await.0,
+            StatusCode::NotFound
+        );
+        // Existing files are served as they are
+        assert_eq!(
+            body(&app, "/static/docs/readme.txt").await,
+            (StatusCode::Ok, "readme".to_owned())
+        );
+        let res = get(&app, "/static/app.js", None).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.header(headers::CACHE_CONTROL).unwrap(), "max-age=60");
     }
 }
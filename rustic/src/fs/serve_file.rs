@@ -1,13 +1,17 @@
-use crate::{Body, Endpoint, Request, Response, Result, StatusCode};
+use crate::{Endpoint, Request, Result};
 use std::io;
 use std::path::Path;
 
 use async_std::path::PathBuf as AsyncPathBuf;
 use async_trait::async_trait;
-use kv_log_macro::warn;
 
-pub(crate) struct ServeFile {
+use super::{serve_file, FileOptions, MimeTypes};
+
+/// Serves a single file, configured through `Route::serve_file_with`.
+pub struct ServeFile {
     path: AsyncPathBuf,
+    options: FileOptions,
+    cache_control: Option<String>,
 }
 
 impl ServeFile {
@@ -15,25 +19,58 @@ impl ServeFile {
         let file = path.as_ref().to_owned().canonicalize()?;
         Ok(Self {
             path: AsyncPathBuf::from(file),
+            options: FileOptions::default(),
+            cache_control: None,
         })
     }
+
+    /// Derive `ETag` and `Last-Modified` from file metadata, on by default.
+    #[must_use]
+    pub fn metadata_validators(mut self, metadata_validators: bool) -> Self {
+        self.options.metadata_validators = metadata_validators;
+        self
+    }
+
+    /// Send `cache_control` as the `Cache-Control` header, e.g. `no-cache`.
+    #[must_use]
+    pub fn cache_control(mut self, cache_control: impl Into<String>) -> Self {
+        self.cache_control = Some(cache_control.into());
+        self
+    }
+
+    /// Send a `.br`, `.zst` or `.gz` sibling of the file when the client accepts that
+    /// encoding.
+    #[must_use]
+    pub fn precompressed(mut self, precompressed: bool) -> Self {
+        self.options.precompressed = precompressed;
+        self
+    }
+
+    /// Pick content types from `mime_types` instead of the default table.
+    #[must_use]
+    pub fn mime_types(mut self, mime_types: MimeTypes) -> Self {
+        self.options.mime_types = mime_types;
+        self
+    }
+
+    /// Send `X-Content-Type-Options: nosniff`, on by default.
+    #[must_use]
+    pub fn nosniff(mut self, nosniff: bool) -> Self {
+        self.options.nosniff = nosniff;
+        self
+    }
 }
 
 #[async_trait]
 impl Endpoint for ServeFile {
-    async fn call(&self, _: Request) -> Result {
-        match Body::from_file(&self.path).await {
-            Ok(body) => {
-                let mut res = Response::new(StatusCode::Ok);
-                res.set_body(body);
-
-                Ok(res)
-            }
-            Err(e) if e.kind() == io::ErrorKind::NotFound => {
-                warn!("File not found: {:?}", &self.path);
-                Ok(Response::new(StatusCode::NotFound))
-            }
-            Err(e) => Err(e.into()),
-        }
+    async fn call(&self, req: Request) -> Result {
+        serve_file(
+            &req,
+            &self.path,
+            &self.options,
+            self.cache_control.as_deref(),
+            None,
+        )
+        .await
     }
 }
@@ -0,0 +1,583 @@
+use std::path::Path;
+
+use async_std::{
+    fs::{self, OpenOptions},
+    io::{self, ReadExt},
+    path::{Path as AsyncPath, PathBuf as AsyncPathBuf},
+    stream::StreamExt,
+};
+use http_types::{conditional::LastModified, headers, Body, StatusCode};
+use kv_log_macro::{info, warn};
+use rand::Rng;
+
+use super::{
+    file::metadata_validators,
+    listing::{encode_path_segment, escape_html},
+    path_policy::PathPolicy,
+    MimeTypes,
+};
+use crate::{
+    conditional::{self, Precondition},
+    Request, Response,
+};
+
+/// Uploads larger than this are refused unless `ServeDir::max_upload_size` says otherwise.
+pub(crate) const DEFAULT_MAX_UPLOAD_SIZE: u64 = 100 * 1024 * 1024;
+
+/// What the write methods of a writable `ServeDir` work on, resolved from the request path.
+pub(crate) struct Target<'a> {
+    /// The canonical directory being served.
+    pub(crate) root: &'a Path,
+    pub(crate) path: &'a AsyncPath,
+    pub(crate) policy: &'a PathPolicy,
+}
+
+impl Target<'_> {
+    /// The parent of the target, if it is an existing directory that may be written to.
+    async fn parent(&self) -> io::Result<Result<&AsyncPath, Response>> {
+        let parent = match self.path.parent() {
+            Some(parent) if self.path.as_ref() as &Path != self.root => parent,
+            _ => return Ok(Err(Response::new(StatusCode::Forbidden))),
+        };
+
+        // RFC 4918 answers `409` for missing intermediate collections
+        if !parent.is_dir().await {
+            return Ok(Err(Response::new(StatusCode::Conflict)));
+        }
+
+        if !self.policy.allows(self.root, parent).await? {
+            warn!("Unauthorized attempt to write: {:?}", self.path);
+            return Ok(Err(Response::new(StatusCode::Forbidden)));
+        }
+
+        Ok(Ok(parent))
+    }
+}
+
+/// Store the request body at the target, streamed into a temporary file next to it that
+/// is renamed over the target once complete, so readers never see a partial upload.
+pub(crate) async fn put(req: &mut Request, target: Target<'_>, max_size: u64) -> crate::Result {
+    let parent = match target.parent().await? {
+        Ok(parent) => parent,
+        Err(res) => return Ok(res),
+    };
+
+    let existing = match fs::symlink_metadata(target.path).await {
+        Ok(metadata) if metadata.is_dir() => {
+            return Ok(Response::new(StatusCode::MethodNotAllowed));
+        }
+        Ok(metadata) => Some(metadata),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    // `If-Match` needs something to match and `If-None-Match: *` creates only
+    if conditional::is_conditional(&req.req) {
+        let current = existing.as_ref().map(metadata_validators);
+        let precondition = conditional::evaluate(current.as_ref(), &req.req);
+        if precondition != Precondition::Proceed {
+            return Ok(Response::new(StatusCode::PreconditionFailed));
+        }
+    }
+
+    let too_large = req
+        .header(headers::CONTENT_LENGTH)
+        .and_then(|length| length.last().as_str().parse::<u64>().ok())
+        .is_some_and(|length| length > max_size);
+    if too_large {
+        return Ok(Response::new(StatusCode::PayloadTooLarge));
+    }
+
+    let temp_path = temp_path(parent, target.path);
+    let written = match write_temp(req.req.take_body(), &temp_path, max_size).await {
+        Ok(written) => written,
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(e.into());
+        }
+    };
+
+    // Chunked bodies only show their size while being read
+    if written > max_size {
+        fs::remove_file(&temp_path).await?;
+        return Ok(Response::new(StatusCode::PayloadTooLarge));
+    }
+
+    if let Err(e) = fs::rename(&temp_path, target.path).await {
+        let _ = fs::remove_file(&temp_path).await;
+        return Err(e.into());
+    }
+
+    info!("Stored upload: {:?}", target.path);
+
+    Ok(Response::new(if existing.is_some() {
+        StatusCode::NoContent
+    } else {
+        StatusCode::Created
+    }))
+}
+
+/// A hidden sibling of `path`, so the upload isn't served while in progress.
+fn temp_path(parent: &AsyncPath, path: &AsyncPath) -> AsyncPathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let suffix: u64 = rand::thread_rng().gen();
+    parent.join(format!(".{}.{:016x}.upload", name, suffix))
+}
+
+/// Copy at most one byte more than `max_size`, enough to tell the body was too large.
+async fn write_temp(body: Body, temp_path: &AsyncPath, max_size: u64) -> io::Result<u64> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(temp_path)
+        .await?;
+
+    let written = io::copy(&mut body.take(max_size + 1), &mut file).await?;
+    file.sync_all().await?;
+    Ok(written)
+}
+
+/// Remove the target, directories with everything below them. Symlinks are removed
+/// rather than followed.
+pub(crate) async fn delete(target: Target<'_>) -> crate::Result {
+    if let Err(res) = target.parent().await? {
+        return Ok(res);
+    }
+
+    let result = match fs::symlink_metadata(target.path).await {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(target.path).await,
+        Ok(_) => fs::remove_file(target.path).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(()) => {
+            info!("Deleted: {:?}", target.path);
+            Ok(Response::new(StatusCode::NoContent))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Response::new(StatusCode::NotFound)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Create the target as a directory, its parent has to exist already.
+pub(crate) async fn mkcol(req: &Request, target: Target<'_>) -> crate::Result {
+    let has_body = req
+        .header(headers::CONTENT_LENGTH)
+        .is_some_and(|length| length.last().as_str() != "0")
+        || req.header(headers::TRANSFER_ENCODING).is_some();
+    if has_body {
+        return Ok(Response::new(StatusCode::UnsupportedMediaType));
+    }
+
+    if let Err(res) = target.parent().await? {
+        return Ok(res);
+    }
+
+    match fs::create_dir(target.path).await {
+        Ok(()) => {
+            info!("Created directory: {:?}", target.path);
+            Ok(Response::new(StatusCode::Created))
+        }
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            Ok(Response::new(StatusCode::MethodNotAllowed))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// A `207 Multi-Status` with the live properties of the target and, for `Depth: 1`, of
+/// the entries of a directory. `Depth: infinity`, the default, is refused.
+pub(crate) async fn propfind(
+    req: &Request,
+    target: Target<'_>,
+    mime_types: &MimeTypes,
+) -> crate::Result {
+    let depth = req
+        .header("Depth")
+        .map(|depth| depth.last().as_str().trim());
+    let depth_one = match depth {
+        Some("0") => false,
+        Some("1") => true,
+        _ => return Ok(Response::new(StatusCode::Forbidden)),
+    };
+
+    let metadata = match target.path.metadata().await {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(Response::new(StatusCode::NotFound));
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if !target.policy.allows(target.root, target.path).await? {
+        warn!("Unauthorized attempt to read: {:?}", target.path);
+        return Ok(Response::new(StatusCode::Forbidden));
+    }
+
+    let mut href = req.url().path().to_owned();
+    if metadata.is_dir() && !href.ends_with('/') {
+        href.push('/');
+    }
+
+    let mut responses = property_response(&href, target.path, &metadata, mime_types);
+
+    if depth_one && metadata.is_dir() {
+        let mut entries = target.path.read_dir().await?;
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !target.policy.lists(&name) {
+                continue;
+            }
+
+            // Report what links point to, and leave out those leading out of the directory
+            let entry_path = entry.path();
+            match target.policy.allows(target.root, &entry_path).await {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            }
+
+            let metadata = entry_path.metadata().await?;
+            let mut entry_href = format!("{}{}", href, encode_path_segment(&name));
+            if metadata.is_dir() {
+                entry_href.push('/');
+            }
+            responses.push_str(&property_response(
+                &entry_href,
+                &entry_path,
+                &metadata,
+                mime_types,
+            ));
+        }
+    }
+
+    let mut body = Body::from_string(format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n{}</D:multistatus>\n",
+        responses
+    ));
+    body.set_mime("application/xml;charset=utf-8");
+
+    let mut res = Response::new(StatusCode::MultiStatus);
+    res.set_body(body);
+    Ok(res)
+}
+
+fn property_response(
+    href: &str,
+    path: &AsyncPath,
+    metadata: &fs::Metadata,
+    mime_types: &MimeTypes,
+) -> String {
+    let mut props = String::new();
+
+    if metadata.is_dir() {
+        props.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+    } else {
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        props.push_str("<D:resourcetype/>");
+        props.push_str(&format!(
+            "<D:getcontentlength>{}</D:getcontentlength><D:getcontenttype>{}</D:getcontenttype>",
+            metadata.len(),
+            escape_html(&mime_types.resolve(extension, None).to_string())
+        ));
+    }
+
+    let validators = metadata_validators(metadata);
+    if let Some(etag) = validators.etag {
+        props.push_str(&format!(
+            "<D:getetag>{}</D:getetag>",
+            escape_html(&etag.to_string())
+        ));
+    }
+    if let Some(modified) = validators.last_modified {
+        props.push_str(&format!(
+            "<D:getlastmodified>{}</D:getlastmodified>",
+            LastModified::new(modified).value()
+        ));
+    }
+
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        escape_html(href),
+        props
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs as std_fs;
+
+    use async_std::io::Cursor;
+    use http_types::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    use super::{super::test_dir::TestDir, *};
+    use crate::Server;
+
+    fn app(dir: &TestDir, writable: bool) -> Server {
+        let mut app = crate::new();
+        app.at("/files/*")
+            .serve_dir_with(dir.path(), |serve_dir| {
+                serve_dir.writable(writable).max_upload_size(8)
+            })
+            .unwrap();
+        app
+    }
+
+    fn request(method: Method, path: &str) -> HttpRequest {
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        HttpRequest::new(method, url)
+    }
+
+    fn put(path: &str, body: impl Into<Body>) -> HttpRequest {
+        let mut req = request(Method::Put, path);
+        req.set_body(body);
+        req
+    }
+
+    async fn send(app: &Server, req: HttpRequest) -> HttpResponse {
+        app.respond(req).await.unwrap()
+    }
+
+    async fn status(app: &Server, req: HttpRequest) -> StatusCode {
+        send(app, req).await.status()
+    }
+
+    /// Names in `dir`, to check that no temporary upload was left behind.
+    fn names(dir: &TestDir) -> Vec<String> {
+        let mut names: Vec<String> = std_fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[async_std::test]
+    async fn uploads_create_and_replace_files() {
+        let dir = TestDir::new();
+        let app = app(&dir, true);
+
+        assert_eq!(
+            status(&app, put("/files/a.txt", "one")).await,
+            StatusCode::Created
+        );
+        assert_eq!(
+            std_fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "one"
+        );
+
+        assert_eq!(
+            status(&app, put("/files/a.txt", "two")).await,
+            StatusCode::NoContent
+        );
+        let mut res = send(&app, request(Method::Get, "/files/a.txt")).await;
+        assert_eq!(res.body_string().await.unwrap(), "two");
+        assert_eq!(names(&dir), ["a.txt"]);
+    }
+
+    #[async_std::test]
+    async fn uploads_need_an_existing_parent_directory() {
+        let dir = TestDir::new();
+        dir.dir("docs");
+        let app = app(&dir, true);
+
+        assert_eq!(
+            status(&app, put("/files/missing/a.txt", "a")).await,
+            StatusCode::Conflict
+        );
+        assert_eq!(
+            status(&app, put("/files/docs", "a")).await,
+            StatusCode::MethodNotAllowed
+        );
+        assert_eq!(
+            status(&app, put("/files/", "a")).await,
+            StatusCode::Forbidden
+        );
+        assert_eq!(
+            status(&app, put("/files/.env", "a")).await,
+            StatusCode::NotFound
+        );
+        assert_eq!(
+            status(&app, put("/files/a%2fb", "a")).await,
+            StatusCode::BadRequest
+        );
+    }
+
+    #[async_std::test]
+    async fn oversized_uploads_are_refused() {
+        let dir = TestDir::new();
+        let app = app(&dir, true);
+
+        assert_eq!(
+            status(&app, put("/files/a.txt", "123456789")).await,
+            StatusCode::PayloadTooLarge
+        );
+
+        // Without a length the body is cut off after the limit
+        let chunked = Body::from_reader(Cursor::new(b"123456789".to_vec()), None);
+        assert_eq!(
+            status(&app, put("/files/a.txt", chunked)).await,
+            StatusCode::PayloadTooLarge
+        );
+        let chunked = Body::from_reader(Cursor::new(b"12345678".to_vec()), None);
+        assert_eq!(
+            status(&app, put("/files/b.txt", chunked)).await,
+            StatusCode::Created
+        );
+        assert_eq!(names(&dir), ["b.txt"]);
+    }
+
+    #[async_std::test]
+    async fn uploads_honour_preconditions() {
+        let dir = TestDir::new();
+        dir.file("a.txt", "one");
+        let app = app(&dir, true);
+
+        let mut req = put("/files/a.txt", "two");
+        req.insert_header(headers::IF_NONE_MATCH, "*");
+        assert_eq!(status(&app, req).await, StatusCode::PreconditionFailed);
+
+        let mut req = put("/files/b.txt", "two");
+        req.insert_header(headers::IF_MATCH, "*");
+        assert_eq!(status(&app, req).await, StatusCode::PreconditionFailed);
+
+        let etag = send(&app, request(Method::Get, "/files/a.txt"))
+            .await
+            .header(headers::ETAG)
+            .unwrap()
+            .as_str()
+            .to_owned();
+        let mut req = put("/files/a.txt", "two");
+        req.insert_header(headers::IF_MATCH, etag);
+        assert_eq!(status(&app, req).await, StatusCode::NoContent);
+        assert_eq!(
+            std_fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "two"
+        );
+    }
+
+    #[async_std::test]
+    async fn files_and_directories_are_deleted_and_created() {
+        let dir = TestDir::new();
+        dir.file("a.txt", "a");
+        dir.file("docs/readme.txt", "readme");
+        let app = app(&dir, true);
+
+        assert_eq!(
+            status(&app, request(Method::Delete, "/files/a.txt")).await,
+            StatusCode::NoContent
+        );
+        assert_eq!(
+            status(&app, request(Method::Delete, "/files/a.txt")).await,
+            StatusCode::NotFound
+        );
+        assert_eq!(
+            status(&app, request(Method::Delete, "/files/docs")).await,
+            StatusCode::NoContent
+        );
+        assert!(names(&dir).is_empty());
+
+        assert_eq!(
+            status(&app, request(Method::MkCol, "/files/new")).await,
+            StatusCode::Created
+        );
+        assert!(dir.path().join("new").is_dir());
+        assert_eq!(
+            status(&app, request(Method::MkCol, "/files/new")).await,
+            StatusCode::MethodNotAllowed
+        );
+        let mut req = request(Method::MkCol, "/files/other");
+        req.insert_header(headers::CONTENT_LENGTH, "8");
+        req.set_body("<mkcol/>");
+        assert_eq!(status(&app, req).await, StatusCode::UnsupportedMediaType);
+    }
+
+    #[async_std::test]
+    async fn propfind_lists_one_level() {
+        let dir = TestDir::new();
+        dir.file("a&b.txt", "abc");
+        dir.file(".env", "SECRET=1");
+        dir.dir("docs");
+        let app = app(&dir, true);
+
+        let propfind = |depth: Option<&str>| {
+            let mut req = request(Method::PropFind, "/files/");
+            if let Some(depth) = depth {
+                req.insert_header("Depth", depth);
+            }
+            req
+        };
+
+        assert_eq!(status(&app, propfind(None)).await, StatusCode::Forbidden);
+        assert_eq!(
+            status(&app, propfind(Some("infinity"))).await,
+            StatusCode::Forbidden
+        );
+
+        let mut res = send(&app, propfind(Some("0"))).await;
+        assert_eq!(res.status(), StatusCode::MultiStatus);
+        let body = res.body_string().await.unwrap();
+        assert_eq!(body.matches("<D:response>").count(), 1);
+
+        let mut res = send(&app, propfind(Some("1"))).await;
+        let body = res.body_string().await.unwrap();
+        assert_eq!(body.matches("<D:response>").count(), 3);
+        assert!(body.contains("<D:href>/files/a%26b.txt</D:href>"));
+        assert!(body.contains("<D:getcontentlength>3</D:getcontentlength>"));
+        assert!(body.contains("<D:href>/files/docs/</D:href>"));
+        assert!(!body.contains(".env"));
+    }
+
+    #[async_std::test]
+    async fn read_only_directories_refuse_changes() {
+        let dir = TestDir::new();
+        dir.file("a.txt", "a");
+        let app = app(&dir, false);
+
+        assert_ne!(
+            status(&app, put("/files/a.txt", "b")).await,
+            StatusCode::NoContent
+        );
+        assert_ne!(
+            status(&app, request(Method::Delete, "/files/a.txt")).await,
+            StatusCode::NoContent
+        );
+        assert_eq!(
+            std_fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "a"
+        );
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn writes_through_links_out_of_the_directory_are_forbidden() {
+        let dir = TestDir::new();
+        let site = dir.dir("site");
+        dir.dir("outside");
+        dir.symlink("../outside", "site/outside-dir");
+
+        let mut app = crate::new();
+        app.at("/files/*")
+            .serve_dir_with(&site, |serve_dir| serve_dir.writable(true))
+            .unwrap();
+
+        assert_eq!(
+            status(&app, put("/files/outside-dir/a.txt", "a")).await,
+            StatusCode::Forbidden
+        );
+        assert_eq!(
+            status(&app, request(Method::MkCol, "/files/outside-dir/new")).await,
+            StatusCode::Forbidden
+        );
+        assert!(std_fs::read_dir(dir.path().join("outside"))
+            .unwrap()
+            .next()
+            .is_none());
+    }
+}
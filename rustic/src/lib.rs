@@ -1,28 +1,62 @@
 pub use http_types::{Body, Cookie, Error, Status, StatusCode};
 
+mod access_log;
+mod cache;
+mod catch_panic;
+mod conditional;
 mod endpoint;
 mod fs;
 mod listeners;
 mod middleware;
 mod middlewares;
+mod rate_limit;
 mod request;
 mod response;
 mod route;
 mod router;
 mod redirect;
 mod server;
+mod sessions;
+mod sse;
+mod stream_body;
+mod trusted_proxies;
+mod websocket;
 
+pub use access_log::{FileSink, LogFormat, Rotation};
+pub use cache::ResponseCache;
+pub use catch_panic::PanicReport;
 pub use endpoint::Endpoint;
+pub use listeners::TcpListener;
+pub use fs::{
+    AssetManifest, DirectoryListing, EmbeddedDir, EmbeddedFile, FilePattern, MimeTypes,
+    ServeDir, ServeEmbedded, ServeFile, SymlinkPolicy,
+};
 pub use middleware::{Middleware, Next};
 pub use middlewares::{
-    AuthMiddleware, BasicAuthScheme, BearerAuthScheme, CorsMiddleware, Origin, WithCors,
-    WithHttpAuth, WithLogging,
+    AuthMiddleware, BasicAuthScheme, BearerAuthScheme, BodyLimitMiddleware, CacheMiddleware,
+    CompressionLevel, CompressionMiddleware, ConditionalMiddleware, CookieMiddleware,
+    CookiePattern, CookiePolicy, CorsMiddleware, CsrfMiddleware, CsrfStorage,
+    ForwardedMiddleware, LogMiddleware, Origin, RateLimitKey, RateLimitMiddleware,
+    RequestIdFormat, RequestIdMiddleware, SessionMiddleware, WithBodyLimit, WithCache,
+    WithCompression, WithConditional, WithCookies, WithCors, WithCsrf, WithForwarded,
+    WithHttpAuth, WithLogging, WithRateLimit, WithRequestId, WithSessions,
 };
 pub use request::Request;
 pub use response::Response;
 pub use route::Route;
+pub use rate_limit::{
+    MemoryRateLimitStore, RateLimitAlgorithm, RateLimitDecision, RateLimitState, RateLimitStore,
+};
 pub use redirect::Redirect;
+pub use rustic_macros::embed_dir;
 pub use server::Server;
+pub use sessions::{CookieStore, MemoryStore, Session, SessionMut, SessionRef, SessionStore};
+pub use stream_body::BodyExt;
+pub use trusted_proxies::{Cidr, ParseCidrError, TrustedProxies};
+pub use sse::{sse, Disconnected, SseEndpoint, SseEvent, SseSender};
+pub use websocket::{
+    CloseFrame, WebSocketConnection, WebSocketEndpoint, WebSocketMessage, WebSocketSender,
+};
 
 pub use http_types;
 
@@ -0,0 +1,297 @@
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use async_std::{
+    io::{self, BufRead, Read, Write},
+    net::TcpStream,
+};
+use http_types::{headers, Body, Request, Response, StatusCode};
+
+/// What the client asked for with `Expect`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Expectation {
+    None,
+    /// Wait for `100 Continue` before sending the body.
+    Continue,
+    /// Something we can't promise, answered with `417 Expectation Failed`.
+    Unsupported,
+}
+
+impl Expectation {
+    pub(crate) fn of(req: &Request) -> Self {
+        match req.header(headers::EXPECT) {
+            None => Expectation::None,
+            // async-h1 only sends `100 Continue` for this exact value, any other spelling
+            // would leave the client waiting, so it gets a 417 to retry without `Expect`
+            Some(expect) if expect.as_str() == "100-continue" => Expectation::Continue,
+            Some(_) => Expectation::Unsupported,
+        }
+    }
+}
+
+/// A connection whose reads can be cut short, so that async-h1 doesn't wait for a body
+/// the client was told not to send before closing the connection.
+#[derive(Clone)]
+pub(crate) struct ContinueStream {
+    stream: TcpStream,
+    skip_body: Arc<AtomicBool>,
+}
+
+impl ContinueStream {
+    pub(crate) fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            skip_body: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Answer `req` without its body, if the client is still waiting to be asked for it,
+    /// which happens when nothing read the body before the response was ready.
+    ///
+    /// async-h1 sends `100 Continue` on the first read of the body, and otherwise reads
+    /// and discards the whole body after the response, which the client may never send.
+    pub(crate) async fn respond<F, Fut>(
+        &self,
+        mut req: Request,
+        respond: F,
+    ) -> crate::Result<Response>
+    where
+        F: FnOnce(Request) -> Fut,
+        Fut: std::future::Future<Output = crate::Result<Response>>,
+    {
+        match Expectation::of(&req) {
+            Expectation::None => return respond(req).await,
+            Expectation::Unsupported => {
+                return Ok(self.skip_body(Response::new(StatusCode::ExpectationFailed)));
+            }
+            Expectation::Continue if req.len() == Some(0) => return respond(req).await,
+            Expectation::Continue => {}
+        }
+
+        let read = watch_body(&mut req);
+        let res = respond(req).await?;
+
+        Ok(if read.load(Ordering::Relaxed) {
+            res
+        } else {
+            self.skip_body(res)
+        })
+    }
+
+    /// Close the connection after `res` rather than wait for the rest of the request.
+    fn skip_body(&self, mut res: Response) -> Response {
+        self.skip_body.store(true, Ordering::Relaxed);
+        res.insert_header(headers::CONNECTION, "close");
+        res
+    }
+}
+
+impl Read for ContinueStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.skip_body.load(Ordering::Relaxed) {
+            return Poll::Ready(Ok(0));
+        }
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl Write for ContinueStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_close(cx)
+    }
+}
+
+/// Replace the body of `req` with one noting whether anything tried to read it.
+fn watch_body(req: &mut Request) -> Arc<AtomicBool> {
+    let read = Arc::new(AtomicBool::new(false));
+    let had_content_type = req.header(headers::CONTENT_TYPE).is_some();
+
+    let body = req.take_body();
+    let len = body.len();
+    let mime = body.mime().clone();
+    let mut watched = Body::from_reader(
+        WatchedBody {
+            body,
+            read: read.clone(),
+        },
+        len,
+    );
+    watched.set_mime(mime);
+    req.set_body(watched);
+
+    // Setting a body fills in a missing `Content-Type`, the client didn't send one
+    if !had_content_type {
+        req.remove_header(headers::CONTENT_TYPE);
+    }
+    read
+}
+
+struct WatchedBody {
+    body: Body,
+    read: Arc<AtomicBool>,
+}
+
+impl BufRead for WatchedBody {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        this.read.store(true, Ordering::Relaxed);
+        Pin::new(&mut this.body).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut self.body).consume(amt);
+    }
+}
+
+impl Read for WatchedBody {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.read.store(true, Ordering::Relaxed);
+        Pin::new(&mut self.body).poll_read(cx, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::{
+        io::{ReadExt, WriteExt},
+        net::TcpListener,
+    };
+    use http_types::{Method, Url};
+
+    use super::*;
+
+    /// Both ends of a local connection, the server side wrapped.
+    async fn connection() -> (ContinueStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (ContinueStream::new(server), client)
+    }
+
+    fn request(expect: Option<&str>, body: &str) -> Request {
+        let mut req = Request::new(Method::Post, Url::parse("http://localhost/").unwrap());
+        req.set_body(body);
+        req.remove_header(headers::CONTENT_TYPE);
+        if let Some(expect) = expect {
+            req.insert_header(headers::EXPECT, expect);
+        }
+        req
+    }
+
+    /// Whether reads from `stream` still reach the connection, with `client` having
+    /// sent some bytes.
+    async fn reads_through(stream: &mut ContinueStream, client: &mut TcpStream) -> bool {
+        client.write_all(b"body").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read(&mut buf).await.unwrap() > 0
+    }
+
+    #[test]
+    fn only_100_continue_can_be_promised() {
+        assert_eq!(Expectation::of(&request(None, "")), Expectation::None);
+        assert_eq!(
+            Expectation::of(&request(Some("100-continue"), "")),
+            Expectation::Continue
+        );
+        assert_eq!(
+            Expectation::of(&request(Some("100-Continue"), "")),
+            Expectation::Unsupported
+        );
+        assert_eq!(
+            Expectation::of(&request(Some("200-ok"), "")),
+            Expectation::Unsupported
+        );
+    }
+
+    #[async_std::test]
+    async fn unsupported_expectations_fail_without_reading_the_body() {
+        let (mut stream, mut client) = connection().await;
+        let res = stream
+            .respond(request(Some("200-ok"), "body"), |_| async {
+                panic!("the request must not be handled")
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::ExpectationFailed);
+        assert_eq!(res.header(headers::CONNECTION).unwrap(), "close");
+        assert!(!reads_through(&mut stream, &mut client).await);
+    }
+
+    #[async_std::test]
+    async fn unread_bodies_close_the_connection() {
+        let (mut stream, mut client) = connection().await;
+        let res = stream
+            .respond(request(Some("100-continue"), "body"), |_| async {
+                Ok(Response::new(StatusCode::Unauthorized))
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::Unauthorized);
+        assert_eq!(res.header(headers::CONNECTION).unwrap(), "close");
+        assert!(!reads_through(&mut stream, &mut client).await);
+    }
+
+    #[async_std::test]
+    async fn read_bodies_keep_the_connection() {
+        let (mut stream, mut client) = connection().await;
+        let res = stream
+            .respond(
+                request(Some("100-continue"), "body"),
+                |mut req| async move {
+                    assert!(req.header(headers::CONTENT_TYPE).is_none());
+                    let mut res = Response::new(StatusCode::Ok);
+                    res.set_body(req.body_string().await?);
+                    Ok(res)
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert!(res.header(headers::CONNECTION).is_none());
+        assert!(reads_through(&mut stream, &mut client).await);
+    }
+
+    #[async_std::test]
+    async fn requests_without_a_body_to_wait_for_pass_through() {
+        let (mut stream, mut client) = connection().await;
+        for req in [request(None, "body"), request(Some("100-continue"), "")] {
+            let res = stream
+                .respond(req, |_| async { Ok(Response::new(StatusCode::NoContent)) })
+                .await
+                .unwrap();
+            assert!(res.header(headers::CONNECTION).is_none());
+        }
+        assert!(reads_through(&mut stream, &mut client).await);
+    }
+}
@@ -1,7 +1,11 @@
+mod expect_continue;
 mod parsed_listener;
+mod proxy_protocol;
 mod tcp_listener;
 pub mod to_listener;
 
+pub use tcp_listener::TcpListener;
+
 use async_std::io;
 use async_trait::async_trait;
 
@@ -0,0 +1,411 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use async_std::io::{self, Read, ReadExt};
+
+/// How long a proxy gets to send its header after connecting.
+pub(crate) const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// `PROXY UNKNOWN ffff:f...f:ffff ffff:f...f:ffff 65535 65535\r\n`
+const V1_MAX_LEN: usize = 107;
+
+/// The connection as the proxy received it. `None` when the proxy doesn't say, e.g. for
+/// its own health checks, in which case the connection is taken at face value.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct ProxyHeader {
+    pub(crate) source: Option<SocketAddr>,
+    pub(crate) destination: Option<SocketAddr>,
+}
+
+/// Read a PROXY protocol header of either version (HAProxy's `proxy-protocol.txt`) from
+/// the start of `stream`, consuming it and nothing after it.
+pub(crate) async fn read_header<R>(stream: &mut R) -> io::Result<ProxyHeader>
+where
+    R: Read + Unpin,
+{
+    io::timeout(HEADER_TIMEOUT, async {
+        let mut start = [0; 12];
+        stream.read_exact(&mut start).await?;
+
+        if start == V2_SIGNATURE {
+            read_v2(stream).await
+        } else if start.starts_with(b"PROXY ") {
+            read_v1(stream, &start).await
+        } else {
+            Err(invalid("missing PROXY protocol header"))
+        }
+    })
+    .await
+}
+
+async fn read_v1<R>(stream: &mut R, start: &[u8]) -> io::Result<ProxyHeader>
+where
+    R: Read + Unpin,
+{
+    let mut line = start.to_vec();
+    // Byte by byte, so that no part of the request is read along with the header
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LEN {
+            return Err(invalid("PROXY protocol v1 header too long"));
+        }
+        let mut byte = [0];
+        stream.read_exact(&mut byte).await?;
+        line.push(byte[0]);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid("PROXY protocol v1 header is not ASCII"))?;
+    parse_v1(line)
+}
+
+fn parse_v1(line: &str) -> io::Result<ProxyHeader> {
+    let fields: Vec<&str> = line.split(' ').collect();
+
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(ProxyHeader {
+            source: None,
+            destination: None,
+        }),
+        ["PROXY", family @ ("TCP4" | "TCP6"), source, destination, source_port, destination_port] =>
+        {
+            let ip = |addr: &str| -> io::Result<IpAddr> {
+                let ip = match *family {
+                    "TCP4" => addr.parse::<Ipv4Addr>().map(IpAddr::V4),
+                    _ => addr.parse::<Ipv6Addr>().map(IpAddr::V6),
+                };
+                ip.map_err(|_| invalid("invalid address in PROXY protocol v1 header"))
+            };
+            let port = |port: &str| -> io::Result<u16> {
+                // No leading zeroes or signs allowed
+                match port.parse::<u16>() {
+                    Ok(parsed) if parsed.to_string() == port => Ok(parsed),
+                    _ => Err(invalid("invalid port in PROXY protocol v1 header")),
+                }
+            };
+
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(ip(source)?, port(source_port)?)),
+                destination: Some(SocketAddr::new(ip(destination)?, port(destination_port)?)),
+            })
+        }
+        _ => Err(invalid("malformed PROXY protocol v1 header")),
+    }
+}
+
+async fn read_v2<R>(stream: &mut R) -> io::Result<ProxyHeader>
+where
+    R: Read + Unpin,
+{
+    let mut fixed = [0; 4];
+    stream.read_exact(&mut fixed).await?;
+    let [version_command, family, len_hi, len_lo] = fixed;
+
+    let mut payload = vec![0; u16::from_be_bytes([len_hi, len_lo]) as usize];
+    stream.read_exact(&mut payload).await?;
+
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+    match version_command & 0x0f {
+        // LOCAL, the proxy's own connection
+        0x0 => {
+            return Ok(ProxyHeader {
+                source: None,
+                destination: None,
+            })
+        }
+        0x1 => {}
+        _ => return Err(invalid("unsupported PROXY protocol v2 command")),
+    }
+
+    // TLVs after the addresses are skipped, they were read along with the payload
+    let addresses = match family {
+        // TCP over IPv4
+        0x11 if payload.len() >= 12 => {
+            let ip = |at: usize| {
+                IpAddr::V4(Ipv4Addr::new(
+                    payload[at],
+                    payload[at + 1],
+                    payload[at + 2],
+                    payload[at + 3],
+                ))
+            };
+            Some((ip(0), ip(4), &payload[8..12]))
+        }
+        // TCP over IPv6
+        0x21 if payload.len() >= 36 => {
+            let ip = |at: usize| {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&payload[at..at + 16]);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            Some((ip(0), ip(16), &payload[32..36]))
+        }
+        0x11 | 0x21 => return Err(invalid("truncated PROXY protocol v2 addresses")),
+        // UNSPEC, UDP or UNIX sockets, nothing that maps onto this connection
+        _ => None,
+    };
+
+    Ok(match addresses {
+        Some((source, destination, ports)) => ProxyHeader {
+            source: Some(SocketAddr::new(
+                source,
+                u16::from_be_bytes([ports[0], ports[1]]),
+            )),
+            destination: Some(SocketAddr::new(
+                destination,
+                u16::from_be_bytes([ports[2], ports[3]]),
+            )),
+        },
+        None => ProxyHeader {
+            source: None,
+            destination: None,
+        },
+    })
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::io::Cursor;
+
+    use super::*;
+
+    /// The header read from `bytes`, and what is left of them for the request.
+    async fn read(bytes: &[u8]) -> (io::Result<ProxyHeader>, Vec<u8>) {
+        let mut stream = Cursor::new(bytes.to_vec());
+        let header = read_header(&mut stream).await;
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).await.unwrap();
+        (header, rest)
+    }
+
+    fn addresses(source: &str, destination: &str) -> ProxyHeader {
+        ProxyHeader {
+            source: Some(source.parse().unwrap()),
+            destination: Some(destination.parse().unwrap()),
+        }
+    }
+
+    fn unknown() -> ProxyHeader {
+        ProxyHeader {
+            source: None,
+            destination: None,
+        }
+    }
+
+    fn error_kind(result: io::Result<ProxyHeader>) -> io::ErrorKind {
+        result.expect_err("the header must be refused").kind()
+    }
+
+    /// A v2 header with the given version and command, address family and payload.
+    fn v2(version_command: u8, family: u8, payload: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[version_command, family]);
+        header.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        header.extend_from_slice(payload);
+        header
+    }
+
+    fn v2_tcp4() -> Vec<u8> {
+        let mut payload = vec![192, 0, 2, 1, 198, 51, 100, 2];
+        payload.extend_from_slice(&56324u16.to_be_bytes());
+        payload.extend_from_slice(&443u16.to_be_bytes());
+        payload
+    }
+
+    #[test]
+    fn v1_lines_parse_into_addresses() {
+        assert_eq!(
+            parse_v1("PROXY TCP4 192.0.2.1 198.51.100.2 56324 443").unwrap(),
+            addresses("192.0.2.1:56324", "198.51.100.2:443")
+        );
+        assert_eq!(
+            parse_v1("PROXY TCP6 2001:db8::1 2001:db8::2 65535 0").unwrap(),
+            addresses("[2001:db8::1]:65535", "[2001:db8::2]:0")
+        );
+        assert_eq!(parse_v1("PROXY UNKNOWN").unwrap(), unknown());
+        assert_eq!(
+            parse_v1("PROXY UNKNOWN ignored whatever follows").unwrap(),
+            unknown()
+        );
+    }
+
+    #[test]
+    fn malformed_v1_lines_are_refused() {
+        for line in [
+            "PROXY",
+            "PROXY TCP4",
+            "PROXY TCP4 192.0.2.1 198.51.100.2 56324",
+            "PROXY TCP4 192.0.2.1 198.51.100.2 56324 443 extra",
+            "PROXY TCP4  192.0.2.1 198.51.100.2 56324 443",
+            "PROXY UDP4 192.0.2.1 198.51.100.2 56324 443",
+            "PROXY tcp4 192.0.2.1 198.51.100.2 56324 443",
+            "PROXY TCP4 2001:db8::1 198.51.100.2 56324 443",
+            "PROXY TCP6 192.0.2.1 2001:db8::2 56324 443",
+            "PROXY TCP4 192.0.2.256 198.51.100.2 56324 443",
+            "PROXY TCP4 192.0.2.1 198.51.100.2 65536 443",
+            "PROXY TCP4 192.0.2.1 198.51.100.2 056324 443",
+            "PROXY TCP4 192.0.2.1 198.51.100.2 +56324 443",
+            "PROXY TCP4 192.0.2.1 198.51.100.2 56324 -1",
+        ] {
+            assert_eq!(
+                parse_v1(line).unwrap_err().kind(),
+                io::ErrorKind::InvalidData,
+                "{:?}",
+                line
+            );
+        }
+    }
+
+    #[async_std::test]
+    async fn v1_headers_are_consumed_up_to_the_line_end() {
+        let (header, rest) =
+            read(b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 443\r\nGET / HTTP/1.1\r\n").await;
+        assert_eq!(
+            header.unwrap(),
+            addresses("192.0.2.1:56324", "198.51.100.2:443")
+        );
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+
+        let (header, rest) = read(b"PROXY UNKNOWN\r\nGET").await;
+        assert_eq!(header.unwrap(), unknown());
+        assert_eq!(rest, b"GET");
+    }
+
+    #[async_std::test]
+    async fn v1_headers_of_the_longest_form_are_read() {
+        let ip = "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff";
+        let longest = format!("PROXY UNKNOWN {0} {0} 65535 65535\r\nGET", ip);
+        assert_eq!(longest.len(), V1_MAX_LEN + 3);
+        let (header, rest) = read(longest.as_bytes()).await;
+        assert_eq!(header.unwrap(), unknown());
+        assert_eq!(rest, b"GET");
+
+        let (header, _) = read(format!("PROXY TCP6 {0} {0} 65535 65535\r\n", ip).as_bytes()).await;
+        assert_eq!(
+            header.unwrap(),
+            addresses(&format!("[{0}]:65535", ip), &format!("[{0}]:65535", ip))
+        );
+
+        let too_long = format!("PROXY UNKNOWN {0} {0} 65535 655350\r\n", ip);
+        let (header, _) = read(too_long.as_bytes()).await;
+        assert_eq!(error_kind(header), io::ErrorKind::InvalidData);
+    }
+
+    #[async_std::test]
+    async fn truncated_v1_headers_fail() {
+        let header = b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 443\r\n";
+        for len in 0..header.len() {
+            let (result, _) = read(&header[..len]).await;
+            assert_eq!(
+                error_kind(result),
+                io::ErrorKind::UnexpectedEof,
+                "length {}",
+                len
+            );
+        }
+
+        let (result, _) = read(b"PROXY TCP4 \xff\xfe 198.51.100.2 1 2\r\n").await;
+        assert_eq!(error_kind(result), io::ErrorKind::InvalidData);
+    }
+
+    #[async_std::test]
+    async fn connections_without_a_header_are_refused() {
+        let (result, _) = read(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n").await;
+        assert_eq!(error_kind(result), io::ErrorKind::InvalidData);
+    }
+
+    #[async_std::test]
+    async fn v2_headers_carry_binary_addresses() {
+        let mut bytes = v2(0x21, 0x11, &v2_tcp4());
+        bytes.extend_from_slice(b"GET");
+        let (header, rest) = read(&bytes).await;
+        assert_eq!(
+            header.unwrap(),
+            addresses("192.0.2.1:56324", "198.51.100.2:443")
+        );
+        assert_eq!(rest, b"GET");
+
+        let source: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let destination: Ipv6Addr = "2001:db8::2".parse().unwrap();
+        let mut payload = source.octets().to_vec();
+        payload.extend_from_slice(&destination.octets());
+        payload.extend_from_slice(&[0xDC, 0x04, 0x01, 0xBB]);
+        let (header, _) = read(&v2(0x21, 0x21, &payload)).await;
+        assert_eq!(
+            header.unwrap(),
+            addresses("[2001:db8::1]:56324", "[2001:db8::2]:443")
+        );
+    }
+
+    #[async_std::test]
+    async fn v2_tlvs_are_skipped() {
+        let mut payload = v2_tcp4();
+        // PP2_TYPE_AUTHORITY "example.com"
+        payload.extend_from_slice(&[0x02, 0x00, 0x0B]);
+        payload.extend_from_slice(b"example.com");
+        let mut bytes = v2(0x21, 0x11, &payload);
+        bytes.extend_from_slice(b"GET");
+
+        let (header, rest) = read(&bytes).await;
+        assert_eq!(
+            header.unwrap(),
+            addresses("192.0.2.1:56324", "198.51.100.2:443")
+        );
+        assert_eq!(rest, b"GET");
+    }
+
+    #[async_std::test]
+    async fn v2_local_and_unmapped_families_carry_no_addresses() {
+        // LOCAL, whatever the family says
+        let (header, rest) = read(&[v2(0x20, 0x11, &v2_tcp4()), b"GET".to_vec()].concat()).await;
+        assert_eq!(header.unwrap(), unknown());
+        assert_eq!(rest, b"GET");
+
+        // UNSPEC, UDP over IPv4 and UNIX stream sockets
+        for family in [0x00, 0x12, 0x31] {
+            let (header, _) = read(&v2(0x21, family, &[0; 216])).await;
+            assert_eq!(header.unwrap(), unknown(), "family {:#x}", family);
+        }
+    }
+
+    #[async_std::test]
+    async fn malformed_v2_headers_are_refused() {
+        for bytes in [
+            v2(0x11, 0x11, &v2_tcp4()),
+            v2(0x31, 0x11, &v2_tcp4()),
+            v2(0x22, 0x11, &v2_tcp4()),
+            v2(0x21, 0x11, &v2_tcp4()[..11]),
+            v2(0x21, 0x21, &[0; 35]),
+        ] {
+            let (result, _) = read(&bytes).await;
+            assert_eq!(
+                error_kind(result),
+                io::ErrorKind::InvalidData,
+                "{:?}",
+                bytes
+            );
+        }
+    }
+
+    #[async_std::test]
+    async fn truncated_v2_headers_fail() {
+        let header = v2(0x21, 0x11, &v2_tcp4());
+        for len in 0..header.len() {
+            let (result, _) = read(&header[..len]).await;
+            assert_eq!(
+                error_kind(result),
+                io::ErrorKind::UnexpectedEof,
+                "length {}",
+                len
+            );
+        }
+    }
+}
@@ -1,15 +1,19 @@
 use async_std::net::{self, SocketAddr, TcpStream};
 use async_std::stream::StreamExt;
 use async_std::{io, task};
-use kv_log_macro::error;
+use kv_log_macro::{error, warn};
 
-use super::{is_transient_error, Listener};
-use crate::server::Server;
+use super::{
+    expect_continue::ContinueStream, is_transient_error, proxy_protocol, to_listener::ToListener,
+    Listener,
+};
+use crate::{server::Server, trusted_proxies::TrustedProxies};
 
 pub struct TcpListener {
     addrs: Option<Vec<SocketAddr>>,
     listener: Option<net::TcpListener>,
     server: Option<Server>,
+    proxy_protocol: Option<TrustedProxies>,
 }
 
 impl TcpListener {
@@ -18,19 +22,51 @@ impl TcpListener {
             addrs: Some(addrs),
             listener: None,
             server: None,
+            proxy_protocol: None,
         }
     }
+
+    /// Expect a PROXY protocol header, v1 or v2, from connections made by `proxies`, and
+    /// report the client and the address it connected to from it rather than from the
+    /// socket. Connections from elsewhere are served as they are.
+    #[must_use]
+    pub fn proxy_protocol(mut self, proxies: TrustedProxies) -> Self {
+        self.proxy_protocol = Some(proxies);
+        self
+    }
 }
 
-fn handle_tcp(app: Server, stream: TcpStream) {
+fn handle_tcp(app: Server, mut stream: TcpStream, proxy_protocol: Option<TrustedProxies>) {
     task::spawn(async move {
-        let local_addr = stream.local_addr().ok();
-        let peer_addr = stream.peer_addr().ok();
+        let mut local_addr = stream.local_addr().ok();
+        let mut peer_addr = stream.peer_addr().ok();
+
+        let from_proxy = proxy_protocol
+            .zip(peer_addr)
+            .is_some_and(|(proxies, peer)| proxies.contains(peer.ip()));
+        if from_proxy {
+            match proxy_protocol::read_header(&mut stream).await {
+                Ok(header) => {
+                    if let (Some(source), Some(destination)) = (header.source, header.destination) {
+                        peer_addr = Some(source);
+                        local_addr = Some(destination);
+                    }
+                }
+                Err(e) => {
+                    warn!("invalid PROXY protocol header", {
+                        peer_addr: format!("{:?}", peer_addr),
+                        error: e.to_string(),
+                    });
+                    return;
+                }
+            }
+        }
 
-        let fut = async_h1::accept(stream, |mut req| async {
+        let stream = ContinueStream::new(stream);
+        let fut = async_h1::accept(stream.clone(), |mut req| async {
             req.set_local_addr(local_addr);
             req.set_peer_addr(peer_addr);
-            app.respond(req).await
+            stream.respond(req, |req| app.respond(req)).await
         });
 
         if let Err(error) = fut.await {
@@ -80,7 +116,7 @@ impl Listener for TcpListener {
                 }
 
                 Ok(stream) => {
-                    handle_tcp(server.clone(), stream);
+                    handle_tcp(server.clone(), stream, self.proxy_protocol.clone());
                 }
             };
         }
@@ -88,3 +124,11 @@ impl Listener for TcpListener {
         Ok(())
     }
 }
+
+impl ToListener for TcpListener {
+    type Listener = Self;
+
+    fn to_listener(self) -> io::Result<Self::Listener> {
+        Ok(self)
+    }
+}
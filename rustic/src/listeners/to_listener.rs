@@ -18,8 +18,7 @@ impl ToListener for Url {
                 self.socket_addrs(|| Some(80))?,
             ))),
 
-            "tls" | "ssl" | "https" => Err(io::Error::new(
-                io::ErrorKind::Other,
+            "tls" | "ssl" | "https" => Err(io::Error::other(
                 "parsing TLS listeners not supported yet",
             )),
 
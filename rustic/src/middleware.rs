@@ -1,8 +1,14 @@
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use http_types::StatusCode;
 
-use crate::{endpoint::DynEndpoint, request::Request, response::Response};
+use crate::{
+    catch_panic::{CatchUnwind, PanicContext},
+    endpoint::DynEndpoint,
+    request::Request,
+    response::Response,
+};
 
 #[async_trait]
 pub trait Middleware: Send + Sync + 'static {
@@ -20,18 +26,26 @@ pub struct Next<'a> {
 
 impl Next<'_> {
     /// Asynchronously execute the remaining middleware chain.
+    ///
+    /// A panic in the remaining chain is turned into a 500 response here, so it still
+    /// passes through the middleware that already ran.
     pub async fn run(mut self, req: Request) -> Response {
-        if let Some((current, next)) = self.next_middleware.split_first() {
+        let panic_context = req.ext::<PanicContext>().cloned();
+
+        let result = if let Some((current, next)) = self.next_middleware.split_first() {
             self.next_middleware = next;
-            match current.handle(req, self).await {
-                Ok(request) => request,
-                Err(err) => err.into(),
-            }
+            CatchUnwind::new(current.handle(req, self)).await
         } else {
-            match self.endpoint.call(req).await {
-                Ok(request) => request,
-                Err(err) => err.into(),
-            }
+            CatchUnwind::new(self.endpoint.call(req)).await
+        };
+
+        match result {
+            Ok(Ok(response)) => response,
+            Ok(Err(err)) => err.into(),
+            Err(payload) => match panic_context {
+                Some(panic_context) => panic_context.into_response(payload),
+                None => Response::new(StatusCode::InternalServerError),
+            },
         }
     }
 }
@@ -10,6 +10,11 @@ pub struct AuthMiddleware<ImplScheme: Scheme> {
     pub(crate) scheme: ImplScheme,
 }
 
+/// Marks requests whose credentials were verified by `AuthMiddleware`, read by
+/// `RateLimitKey::Authorization`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Authenticated;
+
 impl<ImplScheme: Scheme> AuthMiddleware<ImplScheme> {
     pub fn new(scheme: ImplScheme) -> Self {
         Self { scheme }
@@ -57,7 +62,7 @@ impl<ImplScheme> Middleware for AuthMiddleware<ImplScheme>
 where
     ImplScheme: Scheme + Send + Sync + 'static,
 {
-    async fn handle(&self, req: Request, next: Next<'_>) -> crate::Result {
+    async fn handle(&self, mut req: Request, next: Next<'_>) -> crate::Result {
         let auth_header = req.header(ImplScheme::header_name());
         if auth_header.is_none() {
             info!("no auth header, proceeding");
@@ -85,7 +90,10 @@ where
             info!("saw auth header, attempting to auth");
 
             return match self.scheme.authenticate(auth_param).await? {
-                Some(_bool) => Ok(next.run(req).await),
+                Some(_bool) => {
+                    req.set_ext(Authenticated);
+                    Ok(next.run(req).await)
+                }
                 _ => return Ok(get_basic_auth_forbiden_response()),
             };
         }
@@ -122,7 +130,7 @@ pub struct BasicAuthScheme {
 impl BasicAuthScheme {
     pub fn new(verify_password: fn(username: &str, password: &str) -> bool) -> Self {
         Self {
-            verify_password: verify_password,
+            verify_password,
         }
     }
 }
@@ -189,7 +197,7 @@ pub struct BearerAuthScheme {
 impl BearerAuthScheme {
     pub fn new(verify_token: fn(token: &str) -> bool) -> Self {
         Self {
-            verify_token: verify_token,
+            verify_token,
         }
     }
 }
@@ -224,7 +232,7 @@ impl Scheme for BearerAuthScheme {
         let as_utf8 = as_utf8.unwrap();
         let parts: Vec<_> = as_utf8.split(':').collect();
 
-        if parts.len() < 1 {
+        if parts.is_empty() {
             return Err(http_types::Error::from_str(
                 StatusCode::Unauthorized,
                 "Bearer auth must contain token",
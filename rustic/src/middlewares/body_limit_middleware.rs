@@ -0,0 +1,341 @@
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use async_std::io::{self, BufRead, Read};
+use async_trait::async_trait;
+use http_types::{headers, Body, StatusCode};
+
+use crate::{Middleware, Next, Request, Response, Server};
+
+pub(crate) const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Refuses request bodies that are too large or of an unexpected type, with `413 Payload
+/// Too Large` or `415 Unsupported Media Type`.
+///
+/// Both are decided from the headers before the body is read, so clients sending
+/// `Expect: 100-continue` are turned away before uploading anything. Bodies without a
+/// `Content-Length` are cut off once they grow past the limit while being read.
+pub struct BodyLimitMiddleware {
+    max_size: u64,
+    content_types: Vec<String>,
+}
+
+impl BodyLimitMiddleware {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            max_size: DEFAULT_MAX_SIZE,
+            content_types: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// The content types bodies may have, matched as prefixes, e.g. `"image/"`. Any type
+    /// is accepted while the list is empty.
+    #[must_use]
+    pub fn content_types<I, S>(mut self, content_types: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.content_types = content_types
+            .into_iter()
+            .map(|content_type| content_type.into().to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    fn allows_type(&self, request: &Request) -> bool {
+        if self.content_types.is_empty() {
+            return true;
+        }
+
+        let content_type = match request.header(headers::CONTENT_TYPE) {
+            Some(content_type) => content_type.last().as_str().to_ascii_lowercase(),
+            None => return false,
+        };
+        self.content_types
+            .iter()
+            .any(|allowed| content_type.starts_with(allowed.as_str()))
+    }
+}
+
+#[async_trait]
+impl Middleware for BodyLimitMiddleware {
+    async fn handle(&self, mut request: Request, next: Next<'_>) -> crate::Result {
+        let length = request
+            .header(headers::CONTENT_LENGTH)
+            .and_then(|length| length.last().as_str().parse::<u64>().ok());
+        let chunked = request.header(headers::TRANSFER_ENCODING).is_some();
+
+        if length.unwrap_or(0) == 0 && !chunked {
+            return Ok(next.run(request).await);
+        }
+
+        if length.is_some_and(|length| length > self.max_size) {
+            return Ok(Response::new(StatusCode::PayloadTooLarge));
+        }
+        if !self.allows_type(&request) {
+            return Ok(Response::new(StatusCode::UnsupportedMediaType));
+        }
+        if length.is_some() {
+            return Ok(next.run(request).await);
+        }
+
+        let exceeded = Arc::new(AtomicBool::new(false));
+        let body = request.req.take_body();
+        let mime = body.mime().clone();
+        let mut limited = Body::from_reader(
+            LimitedBody::new(body, self.max_size, exceeded.clone()),
+            None,
+        );
+        limited.set_mime(mime);
+        request.req.set_body(limited);
+
+        let res = next.run(request).await;
+
+        // Whatever the endpoint made of the failed read, the reason was the size
+        if exceeded.load(Ordering::Relaxed) {
+            return Ok(Response::new(StatusCode::PayloadTooLarge));
+        }
+        Ok(res)
+    }
+}
+
+impl Default for BodyLimitMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A body failing reads once more than `remaining` bytes came through.
+pub(crate) struct LimitedBody {
+    body: Body,
+    remaining: u64,
+    exceeded: Arc<AtomicBool>,
+}
+
+impl LimitedBody {
+    /// `exceeded` is set when a read fails for the size, so the caller can answer 413.
+    pub(crate) fn new(body: Body, max_size: u64, exceeded: Arc<AtomicBool>) -> Self {
+        Self {
+            body,
+            remaining: max_size,
+            exceeded,
+        }
+    }
+}
+
+impl BufRead for LimitedBody {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.body).poll_fill_buf(cx) {
+            Poll::Ready(Ok(buf)) if buf.len() as u64 > this.remaining => {
+                this.exceeded.store(true, Ordering::Relaxed);
+                Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "request body too large",
+                )))
+            }
+            poll => poll,
+        }
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        self.remaining -= amt as u64;
+        Pin::new(&mut self.body).consume(amt);
+    }
+}
+
+impl Read for LimitedBody {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let available = match self.as_mut().poll_fill_buf(cx) {
+            Poll::Ready(Ok(available)) => available,
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Poll::Ready(Ok(len))
+    }
+}
+
+pub trait WithBodyLimit {
+    fn with_body_limit(
+        &mut self,
+        configure: impl Fn(BodyLimitMiddleware) -> BodyLimitMiddleware,
+    ) -> &mut Self;
+}
+
+impl WithBodyLimit for Server {
+    fn with_body_limit(
+        &mut self,
+        configure: impl Fn(BodyLimitMiddleware) -> BodyLimitMiddleware,
+    ) -> &mut Self {
+        let body_limit = BodyLimitMiddleware::new();
+
+        self.with((configure)(body_limit));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use http_types::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    use super::*;
+
+    fn app(
+        calls: Arc<AtomicUsize>,
+        configure: impl Fn(BodyLimitMiddleware) -> BodyLimitMiddleware,
+    ) -> Server {
+        let mut app = crate::new();
+        app.with_body_limit(configure);
+        app.at("/upload").post(move |mut req: Request| {
+            let calls = calls.clone();
+            async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                req.req.body_string().await
+            }
+        });
+        app.at("/lenient").post(|mut req: Request| async move {
+            let _ = req.req.body_string().await;
+            Ok("ignored")
+        });
+        app
+    }
+
+    fn upload(path: &str, body: &str, content_type: Option<&str>) -> HttpRequest {
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        let mut req = HttpRequest::new(Method::Post, url);
+        req.set_body(body);
+        req.insert_header(headers::CONTENT_LENGTH, body.len().to_string());
+        match content_type {
+            Some(content_type) => req.insert_header(headers::CONTENT_TYPE, content_type),
+            None => req.remove_header(headers::CONTENT_TYPE),
+        };
+        req
+    }
+
+    /// A body of unknown length, as sent with chunked transfer encoding.
+    fn chunked(path: &str, body: &str) -> HttpRequest {
+        let mut req = upload(path, "", Some("text/plain"));
+        req.remove_header(headers::CONTENT_LENGTH);
+        req.insert_header(headers::TRANSFER_ENCODING, "chunked");
+        req.set_body(Body::from_reader(io::Cursor::new(body.to_owned()), None));
+        req
+    }
+
+    #[async_std::test]
+    async fn bodies_within_the_limit_pass() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = app(calls.clone(), |limit| limit.max_size(5));
+
+        let mut res: HttpResponse = app
+            .respond(upload("/upload", "12345", Some("text/plain")))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.body_string().await.unwrap(), "12345");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[async_std::test]
+    async fn declared_lengths_over_the_limit_are_refused_unread() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = app(calls.clone(), |limit| limit.max_size(5));
+
+        let res: HttpResponse = app
+            .respond(upload("/upload", "123456", Some("text/plain")))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::PayloadTooLarge);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[async_std::test]
+    async fn undeclared_lengths_are_cut_off_while_reading() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = app(calls.clone(), |limit| limit.max_size(5));
+
+        let mut res: HttpResponse = app.respond(chunked("/upload", "12345")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.body_string().await.unwrap(), "12345");
+
+        let res: HttpResponse = app.respond(chunked("/upload", "123456")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PayloadTooLarge);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[async_std::test]
+    async fn endpoints_ignoring_the_failed_read_still_answer_413() {
+        let app = app(Arc::default(), |limit| limit.max_size(5));
+
+        let res: HttpResponse = app
+            .respond(chunked("/lenient", "far too long"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::PayloadTooLarge);
+    }
+
+    #[async_std::test]
+    async fn content_types_are_matched_as_prefixes() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = app(calls.clone(), |limit| {
+            limit.content_types(["Image/", "text/plain"])
+        });
+
+        for content_type in ["image/png", "IMAGE/JPEG", "text/plain; charset=utf-8"] {
+            let res: HttpResponse = app
+                .respond(upload("/upload", "data", Some(content_type)))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::Ok, "{}", content_type);
+        }
+
+        for content_type in [Some("application/json"), Some("text/html"), None] {
+            let res: HttpResponse = app
+                .respond(upload("/upload", "data", content_type))
+                .await
+                .unwrap();
+            assert_eq!(
+                res.status(),
+                StatusCode::UnsupportedMediaType,
+                "{:?}",
+                content_type
+            );
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[async_std::test]
+    async fn empty_bodies_skip_the_checks() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = app(calls.clone(), |limit| {
+            limit.max_size(0).content_types(["image/"])
+        });
+
+        let res: HttpResponse = app.respond(upload("/upload", "", None)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
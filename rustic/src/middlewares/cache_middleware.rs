@@ -0,0 +1,609 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use async_std::sync::Mutex as AsyncMutex;
+use async_trait::async_trait;
+use http_types::{
+    cache::{CacheControl, CacheDirective, Expires},
+    headers::{self, HeaderName, Headers},
+    other::Date,
+    Method, StatusCode,
+};
+
+use crate::{
+    cache::{header_string, CachedResponse, Freshness, ResponseCache},
+    Middleware, Next, Request, Response, Server,
+};
+
+pub(crate) const DEFAULT_MAX_ENTRY_SIZE: usize = 1024 * 1024;
+
+/// Caches `GET` responses in memory, acting like a shared HTTP cache in front of the
+/// endpoints.
+///
+/// Only responses with explicit freshness (`s-maxage`, `max-age` or `Expires`) are
+/// stored, and responses that would need revalidation (`no-cache`) are not. Responses
+/// setting cookies are never stored. Successful unsafe requests purge the cached
+/// responses for their host, path and query.
+pub struct CacheMiddleware {
+    cache: ResponseCache,
+    max_entry_size: usize,
+    inflight: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+}
+
+impl CacheMiddleware {
+    #[must_use]
+    pub fn new(cache: ResponseCache) -> Self {
+        Self {
+            cache,
+            max_entry_size: DEFAULT_MAX_ENTRY_SIZE,
+            inflight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Don't store responses with a larger body, or without a known length.
+    #[must_use]
+    pub fn max_entry_size(mut self, max_entry_size: usize) -> Self {
+        self.max_entry_size = max_entry_size;
+        self
+    }
+
+    /// Handle to the underlying storage, e.g. to purge entries.
+    #[must_use]
+    pub fn cache(&self) -> &ResponseCache {
+        &self.cache
+    }
+
+    /// Let concurrent misses for `key` wait for a single backend call.
+    async fn fetch_coalesced(
+        &self,
+        key: String,
+        request: Request,
+        next: Next<'_>,
+        max_age: Option<Duration>,
+    ) -> crate::Result {
+        let lock = self
+            .inflight
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+
+        if let Some(guard) = lock.try_lock() {
+            let res = self.fetch(key.clone(), request, next).await;
+            self.inflight.lock().unwrap().remove(&key);
+            drop(guard);
+            return res;
+        }
+
+        // Another request is fetching the same key, wait for it and try the cache again
+        drop(lock.lock().await);
+
+        if let Some(cached) = self.cache.get(&key, request.req.as_ref()) {
+            if cached.freshness(max_age) == Freshness::Fresh {
+                return Ok(cached.to_response("HIT"));
+            }
+        }
+
+        self.fetch(key, request, next).await
+    }
+
+    async fn fetch(&self, key: String, request: Request, next: Next<'_>) -> crate::Result {
+        let method = request.method();
+        let request_headers: Headers = request.req.as_ref().clone();
+
+        let mut res = next.run(request).await;
+
+        if method == Method::Get {
+            if let Some((headers, policy)) = self.storable(&res, &request_headers) {
+                let body = res.res.take_body().into_bytes().await?;
+                res.set_body(body.clone());
+
+                let vary = policy
+                    .vary
+                    .iter()
+                    .map(|name| (name.clone(), header_string(request_headers.get(name))))
+                    .collect();
+
+                self.cache.insert(
+                    key,
+                    CachedResponse::new(
+                        res.status(),
+                        headers,
+                        body,
+                        vary,
+                        policy.initial_age,
+                        policy.fresh_for,
+                        policy.stale_while_revalidate,
+                    ),
+                );
+            }
+        }
+
+        res.insert_header("X-Cache", "MISS");
+        Ok(res)
+    }
+
+    /// Decide whether `res` may be stored, following the rules for shared caches.
+    fn storable(
+        &self,
+        res: &Response,
+        request_headers: &Headers,
+    ) -> Option<(Headers, StorePolicy)> {
+        if !is_cacheable_status(res.status())
+            || res.error().is_some()
+            || !res.cookie_events.is_empty()
+            || res.res.header(headers::SET_COOKIE).is_some()
+        {
+            return None;
+        }
+
+        match res.res.len() {
+            Some(len) if len <= self.max_entry_size => {}
+            _ => return None,
+        }
+
+        // Without `Cache-Control`, `Expires` alone can make the response storable
+        let cache_control = CacheControl::from_headers(&res.res).ok()?;
+        let directives: Vec<&CacheDirective> =
+            cache_control.iter().flat_map(|cc| cc.iter()).collect();
+        let has = |directive: &CacheDirective| directives.contains(&directive);
+
+        if has(&CacheDirective::NoStore)
+            || has(&CacheDirective::NoCache)
+            || has(&CacheDirective::Private)
+        {
+            return None;
+        }
+
+        let authorized = request_headers.get(headers::AUTHORIZATION).is_some();
+        let shared_ok = has(&CacheDirective::Public)
+            || has(&CacheDirective::MustRevalidate)
+            || directives
+                .iter()
+                .any(|d| matches!(d, CacheDirective::SMaxAge(_)));
+        if authorized && !shared_ok {
+            return None;
+        }
+
+        let vary = match res.res.header(headers::VARY) {
+            Some(vary) => {
+                let names: Vec<String> = vary
+                    .iter()
+                    .flat_map(|value| value.as_str().split(','))
+                    .map(|name| name.trim().to_ascii_lowercase())
+                    .filter(|name| !name.is_empty())
+                    .collect();
+                if names.iter().any(|name| name == "*") {
+                    return None;
+                }
+                names
+                    .into_iter()
+                    .filter_map(|name| HeaderName::from_string(name).ok())
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        let fresh_for = directives
+            .iter()
+            .find_map(|d| match d {
+                CacheDirective::SMaxAge(age) => Some(*age),
+                _ => None,
+            })
+            .or_else(|| {
+                directives.iter().find_map(|d| match d {
+                    CacheDirective::MaxAge(age) => Some(*age),
+                    _ => None,
+                })
+            })
+            .or_else(|| {
+                // Measured from the response's own `Date` (RFC 9111 4.2.1), `Expires - now`
+                // would count the `Age` it arrived with twice
+                let expires = Expires::from_headers(&res.res).ok()??;
+                let date = Date::from_headers(&res.res)
+                    .ok()
+                    .flatten()
+                    .map_or_else(SystemTime::now, SystemTime::from);
+                Some(
+                    expires
+                        .expiration()
+                        .duration_since(date)
+                        .unwrap_or_default(),
+                )
+            })?;
+
+        if fresh_for.is_zero() {
+            return None;
+        }
+
+        let stale_while_revalidate = directives
+            .iter()
+            .find_map(|d| match d {
+                CacheDirective::StaleWhileRevalidate(swr) => Some(*swr),
+                _ => None,
+            })
+            .unwrap_or_default();
+
+        let initial_age = res
+            .res
+            .header(headers::AGE)
+            .and_then(|age| age.last().as_str().parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or_default();
+
+        let headers: Headers = res.res.as_ref().clone();
+
+        Some((
+            headers,
+            StorePolicy {
+                vary,
+                initial_age,
+                fresh_for,
+                stale_while_revalidate,
+            },
+        ))
+    }
+}
+
+struct StorePolicy {
+    vary: Vec<HeaderName>,
+    initial_age: Duration,
+    fresh_for: Duration,
+    stale_while_revalidate: Duration,
+}
+
+#[async_trait]
+impl Middleware for CacheMiddleware {
+    async fn handle(&self, request: Request, next: Next<'_>) -> crate::Result {
+        let key = cache_key(&request);
+        let method = request.method();
+
+        if method != Method::Get && method != Method::Head {
+            let res = next.run(request).await;
+            if res.status().is_success() || res.status().is_redirection() {
+                self.cache.purge(&key);
+            }
+            return Ok(res);
+        }
+
+        let request_directives: Vec<CacheDirective> = CacheControl::from_headers(&request.req)
+            .ok()
+            .flatten()
+            .map(|cache_control| cache_control.into_iter().collect())
+            .unwrap_or_default();
+
+        if request_directives.contains(&CacheDirective::NoStore) {
+            let mut res = next.run(request).await;
+            res.insert_header("X-Cache", "BYPASS");
+            return Ok(res);
+        }
+
+        let max_age = request_directives.iter().find_map(|d| match d {
+            CacheDirective::MaxAge(age) => Some(*age),
+            _ => None,
+        });
+
+        // `no-cache` and `max-age=0` ask for a fresh response, which is stored again
+        let skip_lookup = request_directives.contains(&CacheDirective::NoCache)
+            || max_age == Some(Duration::ZERO);
+
+        if skip_lookup {
+            // Not coalesced, waiting for another request would hand out a stored response
+            return self.fetch(key, request, next).await;
+        }
+
+        let cached = self.cache.get(&key, request.req.as_ref());
+        let revalidation = match cached {
+            Some(cached) => match cached.freshness(max_age) {
+                Freshness::Fresh => return Ok(cached.to_response("HIT")),
+                // One request refreshes the entry, the others keep getting the stale one
+                Freshness::StaleWhileRevalidate => match cached.start_revalidation() {
+                    Some(revalidation) => Some(revalidation),
+                    None => return Ok(cached.to_response("STALE")),
+                },
+                Freshness::Stale => None,
+            },
+            None => None,
+        };
+
+        let res = self.fetch_coalesced(key, request, next, max_age).await;
+        // Whatever came of the refresh, the next request may try again
+        drop(revalidation);
+        res
+    }
+}
+
+fn is_cacheable_status(status: StatusCode) -> bool {
+    matches!(
+        status as u16,
+        200 | 203 | 204 | 300 | 301 | 308 | 404 | 405 | 410 | 414 | 501
+    )
+}
+
+/// Host, path and query, which is also what `ResponseCache::purge` expects. The host
+/// keeps the responses of virtual hosts served by the same app apart.
+fn cache_key(request: &Request) -> String {
+    let url = request.url();
+    let host = match request.header(headers::HOST) {
+        Some(host) => host.last().as_str().to_ascii_lowercase(),
+        None => match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_owned(),
+            (None, _) => String::new(),
+        },
+    };
+
+    match url.query() {
+        Some(query) => format!("{}{}?{}", host, url.path(), query),
+        None => format!("{}{}", host, url.path()),
+    }
+}
+
+pub trait WithCache {
+    fn with_cache(
+        &mut self,
+        cache: ResponseCache,
+        configure: impl Fn(CacheMiddleware) -> CacheMiddleware,
+    ) -> &mut Self;
+}
+
+impl WithCache for Server {
+    fn with_cache(
+        &mut self,
+        cache: ResponseCache,
+        configure: impl Fn(CacheMiddleware) -> CacheMiddleware,
+    ) -> &mut Self {
+        let cache = CacheMiddleware::new(cache);
+
+        self.with((configure)(cache));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures_lite::future;
+    use http_types::{Request as HttpRequest, Response as HttpResponse, Url};
+
+    use super::*;
+
+    /// An app whose `/` endpoint answers with its call count, sending `cache_control`.
+    fn app(cache_control: &'static str, calls: Arc<AtomicUsize>) -> Server {
+        let mut app = crate::new();
+        app.with_cache(ResponseCache::default(), |cache| cache);
+        app.at("/").get(move |_: Request| {
+            let calls = calls.clone();
+            async move {
+                let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                // Give concurrent requests the time to pile up
+                async_std::task::sleep(Duration::from_millis(20)).await;
+                let mut res = Response::new(StatusCode::Ok);
+                res.insert_header(headers::CACHE_CONTROL, cache_control);
+                res.set_body(call.to_string());
+                Ok(res)
+            }
+        });
+        app.at("/").post(|_: Request| async move { Ok("") });
+        app
+    }
+
+    fn request(method: Method, url: &str) -> HttpRequest {
+        HttpRequest::new(method, Url::parse(url).unwrap())
+    }
+
+    fn get() -> HttpRequest {
+        request(Method::Get, "http://localhost/")
+    }
+
+    fn with_cache_control(value: &str) -> HttpRequest {
+        let mut req = get();
+        req.insert_header(headers::CACHE_CONTROL, value);
+        req
+    }
+
+    /// The `X-Cache` header and body of the response to `req`.
+    async fn send(app: &Server, req: HttpRequest) -> (String, String) {
+        let mut res: HttpResponse = app.respond(req).await.unwrap();
+        let x_cache = res.header("X-Cache").unwrap().as_str().to_owned();
+        (x_cache, res.body_string().await.unwrap())
+    }
+
+    fn pair(x_cache: &str, body: &str) -> (String, String) {
+        (x_cache.to_owned(), body.to_owned())
+    }
+
+    #[async_std::test]
+    async fn fresh_responses_are_served_from_the_cache() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = app("max-age=60", calls.clone());
+
+        assert_eq!(send(&app, get()).await, pair("MISS", "1"));
+        assert_eq!(send(&app, get()).await, pair("HIT", "1"));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[async_std::test]
+    async fn responses_without_freshness_are_not_stored() {
+        for cache_control in ["no-cache, max-age=60", "private, max-age=60", "max-age=0"] {
+            let calls = Arc::new(AtomicUsize::new(0));
+            let app = app(cache_control, calls);
+
+            assert_eq!(send(&app, get()).await, pair("MISS", "1"));
+            assert_eq!(send(&app, get()).await, pair("MISS", "2"));
+        }
+    }
+
+    #[async_std::test]
+    async fn request_directives_skip_the_lookup() {
+        let app = app("max-age=60", Arc::new(AtomicUsize::new(0)));
+        send(&app, get()).await;
+
+        assert_eq!(
+            send(&app, with_cache_control("no-cache")).await,
+            pair("MISS", "2")
+        );
+        assert_eq!(
+            send(&app, with_cache_control("max-age=0")).await,
+            pair("MISS", "3")
+        );
+        assert_eq!(
+            send(&app, with_cache_control("no-store")).await,
+            pair("BYPASS", "4")
+        );
+        // The responses to `no-cache` and `max-age=0` were stored again
+        assert_eq!(send(&app, get()).await, pair("HIT", "3"));
+    }
+
+    #[async_std::test]
+    async fn concurrent_misses_are_coalesced() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = app("max-age=60", calls.clone());
+
+        let (first, second) = future::zip(send(&app, get()), send(&app, get())).await;
+        assert_eq!(first, pair("MISS", "1"));
+        assert_eq!(second, pair("HIT", "1"));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[async_std::test]
+    async fn requests_refusing_stored_responses_are_not_coalesced() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = app("max-age=60", calls.clone());
+
+        let (first, second) = future::zip(
+            send(&app, get()),
+            send(&app, with_cache_control("no-cache")),
+        )
+        .await;
+        assert_eq!(first.0, "MISS");
+        assert_eq!(second.0, "MISS");
+        assert_ne!(first.1, second.1);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[async_std::test]
+    async fn failed_refreshes_give_up_the_revalidation() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut app = crate::new();
+        app.with_cache(ResponseCache::default(), |cache| cache);
+        let endpoint_calls = calls.clone();
+        app.at("/").get(move |_: Request| {
+            let calls = endpoint_calls.clone();
+            async move {
+                if calls.fetch_add(1, Ordering::SeqCst) > 0 {
+                    return Ok(Response::new(StatusCode::InternalServerError));
+                }
+                // Stale right away, but may be served while it is refreshed
+                let mut res = Response::new(StatusCode::Ok);
+                res.insert_header(
+                    headers::CACHE_CONTROL,
+                    "max-age=1, stale-while-revalidate=60",
+                );
+                res.insert_header(headers::AGE, "5");
+                res.set_body("stored");
+                Ok(res)
+            }
+        });
+
+        assert_eq!(send(&app, get()).await, pair("MISS", "stored"));
+        // Each of these claims the refresh, which fails and stores nothing
+        assert_eq!(send(&app, get()).await.0, "MISS");
+        assert_eq!(send(&app, get()).await.0, "MISS");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[async_std::test]
+    async fn hosts_are_cached_separately() {
+        let app = app("max-age=60", Arc::new(AtomicUsize::new(0)));
+
+        let mut a = get();
+        a.insert_header(headers::HOST, "a.example");
+        let mut b = get();
+        b.insert_header(headers::HOST, "b.example");
+        assert_eq!(send(&app, a).await, pair("MISS", "1"));
+        assert_eq!(send(&app, b).await, pair("MISS", "2"));
+
+        let mut a = get();
+        a.insert_header(headers::HOST, "A.example");
+        assert_eq!(send(&app, a).await, pair("HIT", "1"));
+    }
+
+    #[async_std::test]
+    async fn unsafe_requests_purge_the_cache() {
+        let app = app("max-age=60", Arc::new(AtomicUsize::new(0)));
+        send(&app, get()).await;
+
+        let res: HttpResponse = app
+            .respond(request(Method::Post, "http://localhost/"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(send(&app, get()).await, pair("MISS", "2"));
+    }
+
+    #[test]
+    fn keys_include_the_host() {
+        let key = |url: &str| cache_key(&Request::new(request(Method::Get, url), Vec::new()));
+
+        assert_eq!(key("http://localhost/a?b=c"), "localhost/a?b=c");
+        assert_eq!(key("http://localhost:8080/a"), "localhost:8080/a");
+    }
+
+    /// An app whose `/` endpoint answers with its call count, as an upstream response
+    /// generated `age` ago and expiring `lifetime` after its `Date`.
+    fn expires_app(age: Duration, lifetime: Duration, calls: Arc<AtomicUsize>) -> Server {
+        let mut app = crate::new();
+        app.with_cache(ResponseCache::default(), |cache| cache);
+        app.at("/").get(move |_: Request| {
+            let calls = calls.clone();
+            async move {
+                let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                let date = SystemTime::now() - age;
+                let mut res = Response::new(StatusCode::Ok);
+                res.insert_header(headers::DATE, Date::new(date).value());
+                res.insert_header(headers::EXPIRES, Expires::new_at(date + lifetime).value());
+                res.insert_header(headers::AGE, age.as_secs().to_string());
+                res.set_body(call.to_string());
+                Ok(res)
+            }
+        });
+        app
+    }
+
+    #[async_std::test]
+    async fn expires_lifetimes_do_not_count_the_age_twice() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = expires_app(
+            Duration::from_secs(50),
+            Duration::from_secs(60),
+            calls.clone(),
+        );
+
+        assert_eq!(send(&app, get()).await, pair("MISS", "1"));
+        let mut res: HttpResponse = app.respond(get()).await.unwrap();
+        assert_eq!(res.header("X-Cache").unwrap(), "HIT");
+        let age: u64 = res.header(headers::AGE).unwrap().as_str().parse().unwrap();
+        assert!((50..60).contains(&age), "{}", age);
+        assert_eq!(res.body_string().await.unwrap(), "1");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[async_std::test]
+    async fn responses_older_than_their_expires_lifetime_are_stale() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = expires_app(
+            Duration::from_secs(70),
+            Duration::from_secs(60),
+            calls.clone(),
+        );
+
+        assert_eq!(send(&app, get()).await, pair("MISS", "1"));
+        assert_eq!(send(&app, get()).await, pair("MISS", "2"));
+    }
+}
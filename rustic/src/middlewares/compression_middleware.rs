@@ -0,0 +1,601 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use async_compression::{
+    futures::bufread::{
+        BrotliDecoder, BrotliEncoder, GzipDecoder, GzipEncoder, ZlibDecoder, ZlibEncoder,
+        ZstdDecoder, ZstdEncoder,
+    },
+    Level,
+};
+use async_std::io::{BufReader, Read};
+use async_trait::async_trait;
+use http_types::{
+    content::Encoding,
+    headers::{self, HeaderValue},
+    Body, Error, Method, StatusCode,
+};
+
+use crate::{middlewares::LimitedBody, Middleware, Next, Request, Response, Server};
+
+pub(crate) const DEFAULT_MIN_SIZE: usize = 1024;
+pub(crate) const DEFAULT_MAX_DECOMPRESSED_SIZE: u64 = 10 * 1024 * 1024;
+pub(crate) const DEFAULT_ENCODINGS: [Encoding; 4] = [
+    Encoding::Brotli,
+    Encoding::Zstd,
+    Encoding::Gzip,
+    Encoding::Deflate,
+];
+
+/// Content types that are compressed already, compressing them again only costs time.
+pub(crate) const DEFAULT_SKIPPED_TYPES: [&str; 16] = [
+    "image/",
+    "audio/",
+    "video/",
+    "font/woff",
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/zstd",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/x-7z-compressed",
+    "application/x-rar-compressed",
+    "application/vnd.rar",
+    "application/pdf",
+    "application/octet-stream",
+    // Encoders buffer, which would hold events back
+    "text/event-stream",
+];
+
+/// How hard to compress, trading CPU time for smaller responses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionLevel {
+    Fastest,
+    /// A level suited to compressing on the fly, below the maximum of each algorithm.
+    Default,
+    Best,
+    /// Algorithm specific level, e.g. 0-11 for brotli or 1-9 for gzip.
+    Precise(i32),
+}
+
+impl CompressionLevel {
+    fn for_encoding(self, encoding: Encoding) -> Level {
+        match (self, encoding) {
+            (Self::Fastest, _) => Level::Fastest,
+            (Self::Best, _) => Level::Best,
+            (Self::Precise(level), _) => Level::Precise(level),
+            // Brotli defaults to its maximum, which is far too slow for dynamic responses
+            (Self::Default, Encoding::Brotli) => Level::Precise(4),
+            (Self::Default, _) => Level::Default,
+        }
+    }
+}
+
+/// Compresses response bodies with the best encoding the client accepts.
+///
+/// Bodies are compressed while they are sent, never buffered. Responses that are small,
+/// already encoded, of a compressed content type or marked `no-transform` are left alone.
+pub struct CompressionMiddleware {
+    encodings: Vec<Encoding>,
+    level: CompressionLevel,
+    min_size: usize,
+    skipped_types: Vec<String>,
+    decompress_requests: bool,
+    max_decompressed_size: u64,
+}
+
+impl CompressionMiddleware {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            encodings: DEFAULT_ENCODINGS.to_vec(),
+            level: CompressionLevel::Default,
+            min_size: DEFAULT_MIN_SIZE,
+            skipped_types: DEFAULT_SKIPPED_TYPES
+                .iter()
+                .map(|t| t.to_string())
+                .collect(),
+            decompress_requests: false,
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+        }
+    }
+
+    /// Encodings to offer, in order of preference for when the client weighs several
+    /// equally.
+    #[must_use]
+    pub fn encodings(mut self, encodings: &[Encoding]) -> Self {
+        self.encodings = encodings
+            .iter()
+            .copied()
+            .filter(|encoding| *encoding != Encoding::Identity)
+            .collect();
+        self
+    }
+
+    #[must_use]
+    pub fn level(mut self, level: CompressionLevel) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Don't compress bodies with a known length below `min_size` bytes.
+    #[must_use]
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Don't compress responses whose content type starts with `content_type`, in addition
+    /// to the already compressed types skipped by default.
+    #[must_use]
+    pub fn skip_content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.skipped_types
+            .push(content_type.into().to_ascii_lowercase());
+        self
+    }
+
+    /// Decode request bodies sent with a `Content-Encoding`, answering unknown encodings
+    /// with `415 Unsupported Media Type`.
+    #[must_use]
+    pub fn decompress_requests(mut self, decompress_requests: bool) -> Self {
+        self.decompress_requests = decompress_requests;
+        self
+    }
+
+    /// Answer `413 Payload Too Large` once a decoded request body grows past
+    /// `max_decompressed_size` bytes, however small it was on the wire.
+    #[must_use]
+    pub fn max_decompressed_size(mut self, max_decompressed_size: u64) -> Self {
+        self.max_decompressed_size = max_decompressed_size;
+        self
+    }
+
+    fn should_compress(&self, method: Method, res: &Response) -> bool {
+        let status = res.status();
+        if method == Method::Head
+            || status.is_informational()
+            || status == StatusCode::NoContent
+            || status == StatusCode::NotModified
+            || status == StatusCode::PartialContent
+        {
+            return false;
+        }
+
+        let headers = &res.res;
+        if headers.header(headers::CONTENT_ENCODING).is_some()
+            || headers.header(headers::CONTENT_RANGE).is_some()
+        {
+            return false;
+        }
+
+        let no_transform = headers
+            .header(headers::CACHE_CONTROL)
+            .is_some_and(|values| {
+                values.iter().any(|value| {
+                    value
+                        .as_str()
+                        .split(',')
+                        .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"))
+                })
+            });
+        if no_transform {
+            return false;
+        }
+
+        if res.res.len().is_some_and(|len| len < self.min_size) {
+            return false;
+        }
+
+        let content_type = headers
+            .header(headers::CONTENT_TYPE)
+            .map(|value| value.last().as_str().to_ascii_lowercase())
+            .unwrap_or_default();
+
+        // SVG is text, unlike the rest of image/*
+        content_type.starts_with("image/svg+xml")
+            || !self
+                .skipped_types
+                .iter()
+                .any(|skipped| content_type.starts_with(skipped.as_str()))
+    }
+
+    /// Swap the body for its decoded form, returning the flag set when it grows too large.
+    fn decompress_request(&self, request: &mut Request) -> crate::Result<Option<Arc<AtomicBool>>> {
+        let encoding = match request.header(&headers::CONTENT_ENCODING) {
+            Some(encoding) => encoding.last().as_str().trim().to_ascii_lowercase(),
+            None => return Ok(None),
+        };
+        if encoding == "identity" {
+            request.req.remove_header(headers::CONTENT_ENCODING);
+            return Ok(None);
+        }
+
+        let body = request.req.take_body();
+        let reader: Body = match encoding.as_str() {
+            "gzip" | "x-gzip" => reader_body(GzipDecoder::new(body)),
+            "deflate" => reader_body(ZlibDecoder::new(body)),
+            "br" => reader_body(BrotliDecoder::new(body)),
+            "zstd" => reader_body(ZstdDecoder::new(body)),
+            _ => {
+                return Err(Error::from_str(
+                    StatusCode::UnsupportedMediaType,
+                    format!("Unsupported Content-Encoding: {}", encoding),
+                ))
+            }
+        };
+
+        // The wire size says nothing about the decoded one, so count while reading
+        let exceeded = Arc::new(AtomicBool::new(false));
+        let limited = LimitedBody::new(reader, self.max_decompressed_size, exceeded.clone());
+
+        request.req.remove_header(headers::CONTENT_ENCODING);
+        request.req.remove_header(headers::CONTENT_LENGTH);
+        request.req.set_body(Body::from_reader(limited, None));
+        Ok(Some(exceeded))
+    }
+}
+
+#[async_trait]
+impl Middleware for CompressionMiddleware {
+    async fn handle(&self, mut request: Request, next: Next<'_>) -> crate::Result {
+        let exceeded = if self.decompress_requests {
+            self.decompress_request(&mut request)?
+        } else {
+            None
+        };
+
+        let method = request.method();
+        let accepted = request
+            .header(&headers::ACCEPT_ENCODING)
+            .map(|values| {
+                values
+                    .iter()
+                    .map(|value| value.as_str())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .and_then(|accept_encoding| negotiate(&accept_encoding, &self.encodings));
+
+        let mut res = next.run(request).await;
+
+        // Whatever the endpoint made of the failed read, the reason was the size
+        if exceeded.is_some_and(|exceeded| exceeded.load(Ordering::Relaxed)) {
+            return Ok(Response::new(StatusCode::PayloadTooLarge));
+        }
+
+        if !self.should_compress(method, &res) {
+            return Ok(res);
+        }
+
+        // The body depends on Accept-Encoding even when it ends up uncompressed
+        append_vary(&mut res);
+
+        let encoding = match accepted {
+            Some(encoding) => encoding,
+            None => return Ok(res),
+        };
+
+        let level = self.level.for_encoding(encoding);
+        let body = res.res.take_body();
+        let compressed = match encoding {
+            Encoding::Gzip => reader_body(GzipEncoder::with_quality(body, level)),
+            Encoding::Deflate => reader_body(ZlibEncoder::with_quality(body, level)),
+            Encoding::Brotli => reader_body(BrotliEncoder::with_quality(body, level)),
+            Encoding::Zstd => reader_body(ZstdEncoder::with_quality(body, level)),
+            _ => body,
+        };
+
+        res.res.remove_header(headers::CONTENT_LENGTH);
+        res.insert_header(headers::CONTENT_ENCODING, encoding.to_string());
+        weaken_etag(&mut res);
+        res.set_body(compressed);
+
+        Ok(res)
+    }
+}
+
+impl Default for CompressionMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn reader_body(reader: impl Read + Unpin + Send + Sync + 'static) -> Body {
+    Body::from_reader(BufReader::new(reader), None)
+}
+
+/// Pick the encoding with the highest q-value from `available`, ties go to the earlier
+/// one in `available`. Encodings with `q=0` are refused, `*` covers the unlisted ones.
+pub(crate) fn negotiate(accept_encoding: &str, available: &[Encoding]) -> Option<Encoding> {
+    let mut wildcard = None;
+    let mut weights = Vec::new();
+
+    for part in accept_encoding.split(',') {
+        let mut params = part.split(';');
+        let name = params
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        if name.is_empty() {
+            continue;
+        }
+
+        let weight = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        if name == "*" {
+            wildcard = Some(weight);
+        } else {
+            weights.push((name, weight));
+        }
+    }
+
+    available
+        .iter()
+        .filter_map(|encoding| {
+            let token = encoding.to_string();
+            let weight = weights
+                .iter()
+                .find(|(name, _)| *name == token || (token == "gzip" && name == "x-gzip"))
+                .map(|(_, weight)| *weight)
+                .or(wildcard)?;
+            (weight > 0.0).then_some((*encoding, weight))
+        })
+        .fold(
+            None,
+            |best: Option<(Encoding, f32)>, (encoding, weight)| match best {
+                Some((_, best_weight)) if best_weight >= weight => best,
+                _ => Some((encoding, weight)),
+            },
+        )
+        .map(|(encoding, _)| encoding)
+}
+
+fn append_vary(res: &mut Response) {
+    let varies = res.res.header(headers::VARY).is_some_and(|values| {
+        values.iter().any(|value| {
+            value.as_str().split(',').any(|name| {
+                let name = name.trim();
+                name == "*" || name.eq_ignore_ascii_case("accept-encoding")
+            })
+        })
+    });
+
+    if !varies {
+        res.append_header(headers::VARY, "Accept-Encoding");
+    }
+}
+
+/// The compressed body is no longer byte-for-byte what a strong validator describes.
+fn weaken_etag(res: &mut Response) {
+    let etag = match res.res.header(headers::ETAG) {
+        Some(etag) => etag.last().as_str().to_owned(),
+        None => return,
+    };
+
+    if !etag.starts_with("W/") {
+        if let Ok(weak) = HeaderValue::from_bytes(format!("W/{}", etag).into_bytes()) {
+            res.insert_header(headers::ETAG, weak);
+        }
+    }
+}
+
+pub trait WithCompression {
+    fn with_compression(
+        &mut self,
+        configure: impl Fn(CompressionMiddleware) -> CompressionMiddleware,
+    ) -> &mut Self;
+}
+
+impl WithCompression for Server {
+    fn with_compression(
+        &mut self,
+        configure: impl Fn(CompressionMiddleware) -> CompressionMiddleware,
+    ) -> &mut Self {
+        let compression = CompressionMiddleware::new();
+
+        self.with((configure)(compression));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read as _, Write as _};
+
+    use http_types::{headers::HeaderName, Request as HttpRequest, Response as HttpResponse, Url};
+
+    use super::*;
+
+    fn text() -> String {
+        "compressible ".repeat(200)
+    }
+
+    fn app(configure: impl Fn(CompressionMiddleware) -> CompressionMiddleware) -> Server {
+        let mut app = crate::new();
+        app.with_compression(configure);
+        app.at("/text").get(|_: Request| async move {
+            let mut res = Response::new(StatusCode::Ok);
+            res.insert_header(headers::CONTENT_TYPE, "text/plain");
+            res.insert_header(headers::ETAG, "\"v1\"");
+            res.set_body(text());
+            Ok(res)
+        });
+        app.at("/small")
+            .get(|_: Request| async move { Ok("small") });
+        app.at("/png").get(|_: Request| async move {
+            let mut res = Response::new(StatusCode::Ok);
+            res.insert_header(headers::CONTENT_TYPE, "image/png");
+            res.set_body(text());
+            Ok(res)
+        });
+        app.at("/no-transform").get(|_: Request| async move {
+            let mut res = Response::new(StatusCode::Ok);
+            res.insert_header(headers::CACHE_CONTROL, "public, no-transform");
+            res.set_body(text());
+            Ok(res)
+        });
+        app.at("/echo")
+            .post(|mut req: Request| async move { req.req.body_string().await });
+        app
+    }
+
+    fn request(method: Method, path: &str, accept_encoding: Option<&str>) -> HttpRequest {
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        let mut req = HttpRequest::new(method, url);
+        if let Some(accept_encoding) = accept_encoding {
+            req.insert_header(headers::ACCEPT_ENCODING, accept_encoding);
+        }
+        req
+    }
+
+    fn header(res: &HttpResponse, name: HeaderName) -> Option<String> {
+        res.header(name).map(|value| value.as_str().to_owned())
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn negotiation_follows_q_values() {
+        let available = DEFAULT_ENCODINGS;
+
+        assert_eq!(negotiate("gzip", &available), Some(Encoding::Gzip));
+        assert_eq!(negotiate("x-gzip", &available), Some(Encoding::Gzip));
+        assert_eq!(
+            negotiate("gzip;q=0.5, br;q=0.8", &available),
+            Some(Encoding::Brotli)
+        );
+        // Ties go to the preferred encoding
+        assert_eq!(negotiate("gzip, br", &available), Some(Encoding::Brotli));
+        assert_eq!(negotiate("*", &available), Some(Encoding::Brotli));
+        assert_eq!(
+            negotiate("*, br;q=0, zstd;q=0", &available),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(negotiate("gzip;q=0", &available), None);
+        assert_eq!(negotiate("identity", &available), None);
+        assert_eq!(negotiate("", &available), None);
+        assert_eq!(negotiate("gzip", &[Encoding::Brotli]), None);
+    }
+
+    #[async_std::test]
+    async fn bodies_are_compressed_with_the_accepted_encoding() {
+        let app = app(|compression| compression);
+        let mut res: HttpResponse = app
+            .respond(request(Method::Get, "/text", Some("gzip")))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            header(&res, headers::CONTENT_ENCODING).as_deref(),
+            Some("gzip")
+        );
+        assert_eq!(
+            header(&res, headers::VARY).as_deref(),
+            Some("Accept-Encoding")
+        );
+        assert_eq!(header(&res, headers::ETAG).as_deref(), Some("W/\"v1\""));
+
+        let compressed = res.body_bytes().await.unwrap();
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(compressed.as_slice())
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, text());
+    }
+
+    #[async_std::test]
+    async fn uncompressed_responses_still_vary() {
+        let app = app(|compression| compression);
+        let mut res: HttpResponse = app
+            .respond(request(Method::Get, "/text", None))
+            .await
+            .unwrap();
+
+        assert_eq!(header(&res, headers::CONTENT_ENCODING), None);
+        assert_eq!(
+            header(&res, headers::VARY).as_deref(),
+            Some("Accept-Encoding")
+        );
+        assert_eq!(header(&res, headers::ETAG).as_deref(), Some("\"v1\""));
+        assert_eq!(res.body_string().await.unwrap(), text());
+    }
+
+    #[async_std::test]
+    async fn some_responses_are_left_alone() {
+        let app = app(|compression| compression);
+
+        for path in ["/small", "/png", "/no-transform"] {
+            let res: HttpResponse = app
+                .respond(request(Method::Get, path, Some("gzip")))
+                .await
+                .unwrap();
+            assert_eq!(header(&res, headers::CONTENT_ENCODING), None, "{}", path);
+        }
+
+        let res: HttpResponse = app
+            .respond(request(Method::Head, "/text", Some("gzip")))
+            .await
+            .unwrap();
+        assert_eq!(header(&res, headers::CONTENT_ENCODING), None);
+
+        let app = self::app(|compression| compression.skip_content_type("TEXT/"));
+        let res: HttpResponse = app
+            .respond(request(Method::Get, "/text", Some("gzip")))
+            .await
+            .unwrap();
+        assert_eq!(header(&res, headers::CONTENT_ENCODING), None);
+    }
+
+    #[async_std::test]
+    async fn request_bodies_are_decompressed_when_asked_to() {
+        let app = app(|compression| compression.decompress_requests(true));
+
+        let mut req = request(Method::Post, "/echo", None);
+        req.insert_header(headers::CONTENT_ENCODING, "gzip");
+        req.set_body(gzip(b"hello"));
+        let mut res: HttpResponse = app.respond(req).await.unwrap();
+        assert_eq!(res.body_string().await.unwrap(), "hello");
+
+        let mut req = request(Method::Post, "/echo", None);
+        req.insert_header(headers::CONTENT_ENCODING, "compress");
+        req.set_body("hello");
+        let res: HttpResponse = app.respond(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UnsupportedMediaType);
+    }
+
+    #[async_std::test]
+    async fn decompressed_request_bodies_are_capped() {
+        let app = app(|compression| {
+            compression
+                .decompress_requests(true)
+                .max_decompressed_size(1024)
+        });
+
+        let mut req = request(Method::Post, "/echo", None);
+        req.insert_header(headers::CONTENT_ENCODING, "gzip");
+        req.set_body(gzip(&[b'a'; 1024]));
+        let mut res: HttpResponse = app.respond(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.body_string().await.unwrap().len(), 1024);
+
+        // A few dozen bytes on the wire, a megabyte once decoded
+        let bomb = gzip(&vec![b'a'; 1024 * 1024]);
+        assert!(bomb.len() < 2048);
+        let mut req = request(Method::Post, "/echo", None);
+        req.insert_header(headers::CONTENT_ENCODING, "gzip");
+        req.set_body(bomb);
+        let res: HttpResponse = app.respond(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PayloadTooLarge);
+    }
+}
@@ -0,0 +1,314 @@
+use async_trait::async_trait;
+use http_types::{conditional::ETag, Method, StatusCode};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    conditional::{self, Validators},
+    Middleware, Next, Request, Server,
+};
+
+pub(crate) const DEFAULT_MAX_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Answers conditional `GET` and `HEAD` requests with `304 Not Modified` or
+/// `412 Precondition Failed`.
+///
+/// Validators set by the endpoint (`ETag`, `Last-Modified`) are used as they are. Without
+/// an `ETag`, one is derived by hashing the body, which needs the body buffered and is
+/// skipped above `max_buffer_size` or for bodies of unknown length.
+///
+/// Other methods change state, so their preconditions have to be checked by the endpoint
+/// before it acts, see `Request::check_preconditions`.
+pub struct ConditionalMiddleware {
+    generate_etags: bool,
+    weak_etags: bool,
+    max_buffer_size: usize,
+}
+
+impl ConditionalMiddleware {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            generate_etags: true,
+            weak_etags: false,
+            max_buffer_size: DEFAULT_MAX_BUFFER_SIZE,
+        }
+    }
+
+    #[must_use]
+    pub fn generate_etags(mut self, generate_etags: bool) -> Self {
+        self.generate_etags = generate_etags;
+        self
+    }
+
+    /// Mark generated `ETag`s as weak, for bodies that may differ in bytes while meaning
+    /// the same, e.g. because of compression further out. Weak tags don't work with
+    /// `If-Match` or `If-Range`.
+    #[must_use]
+    pub fn weak_etags(mut self, weak_etags: bool) -> Self {
+        self.weak_etags = weak_etags;
+        self
+    }
+
+    #[must_use]
+    pub fn max_buffer_size(mut self, max_buffer_size: usize) -> Self {
+        self.max_buffer_size = max_buffer_size;
+        self
+    }
+
+    async fn hash_body(&self, res: &mut crate::Response) -> crate::Result<Option<ETag>> {
+        match res.res.len() {
+            Some(len) if len <= self.max_buffer_size => {}
+            _ => return Ok(None),
+        }
+
+        let body = res.res.take_body().into_bytes().await?;
+        let digest = Sha256::digest(&body);
+        res.set_body(body);
+
+        // 128 bits are plenty to tell representations apart
+        let tag = base64::encode_config(&digest[..16], base64::URL_SAFE_NO_PAD);
+        Ok(Some(if self.weak_etags {
+            ETag::new_weak(tag)
+        } else {
+            ETag::new(tag)
+        }))
+    }
+}
+
+#[async_trait]
+impl Middleware for ConditionalMiddleware {
+    async fn handle(&self, request: Request, next: Next<'_>) -> crate::Result {
+        let method = request.method();
+        if method != Method::Get && method != Method::Head {
+            return Ok(next.run(request).await);
+        }
+
+        let headers = request.req.clone();
+        let mut res = next.run(request).await;
+
+        // Preconditions only apply to responses that would be successful
+        if !res.status().is_success() || res.error().is_some() {
+            return Ok(res);
+        }
+
+        let mut validators = Validators::from_headers(&res.res);
+        if validators.etag.is_none() && self.generate_etags && res.status() == StatusCode::Ok {
+            validators.etag = self.hash_body(&mut res).await?;
+            validators.apply(&mut res.res);
+        }
+
+        if !conditional::is_conditional(&headers) {
+            return Ok(res);
+        }
+
+        match conditional::respond(validators.evaluate(&headers), &res.res) {
+            Some(mut short) => {
+                // Keep cookies the endpoint set, they still have to reach the client
+                short.cookie_events = std::mem::take(&mut res.cookie_events);
+                Ok(short)
+            }
+            None => Ok(res),
+        }
+    }
+}
+
+impl Default for ConditionalMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub trait WithConditional {
+    fn with_conditional(
+        &mut self,
+        configure: impl Fn(ConditionalMiddleware) -> ConditionalMiddleware,
+    ) -> &mut Self;
+}
+
+impl WithConditional for Server {
+    fn with_conditional(
+        &mut self,
+        configure: impl Fn(ConditionalMiddleware) -> ConditionalMiddleware,
+    ) -> &mut Self {
+        let conditional = ConditionalMiddleware::new();
+
+        self.with((configure)(conditional));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_types::{headers, Cookie, Request as HttpRequest, Response as HttpResponse, Url};
+
+    use super::*;
+    use crate::Response;
+
+    fn app(configure: impl Fn(ConditionalMiddleware) -> ConditionalMiddleware) -> Server {
+        let mut app = crate::new();
+        app.with_conditional(configure);
+        app.at("/generated")
+            .get(|_: Request| async move { Ok("generated") });
+        app.at("/tagged").get(|_: Request| async move {
+            let mut res = Response::new(StatusCode::Ok);
+            res.insert_header(headers::ETAG, "\"v1\"");
+            res.insert_cookie(Cookie::new("seen", "1"));
+            res.set_body("tagged");
+            Ok(res)
+        });
+        app.at("/missing")
+            .get(|_: Request| async move { Ok(Response::new(StatusCode::NotFound)) });
+        app.at("/tagged").put(|req: Request| async move {
+            if let Some(res) = req.check_preconditions(true, Some(ETag::new("v1".into())), None) {
+                return Ok(res);
+            }
+            Ok(Response::new(StatusCode::NoContent))
+        });
+        app.at("/missing").put(|req: Request| async move {
+            if let Some(res) = req.check_preconditions(false, None, None) {
+                return Ok(res);
+            }
+            Ok(Response::new(StatusCode::Created))
+        });
+        app
+    }
+
+    fn request(method: Method, path: &str, condition: Option<(&str, &str)>) -> HttpRequest {
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        let mut req = HttpRequest::new(method, url);
+        if let Some((name, value)) = condition {
+            req.insert_header(name, value);
+        }
+        req
+    }
+
+    async fn send(app: &Server, req: HttpRequest) -> HttpResponse {
+        app.respond(req).await.unwrap()
+    }
+
+    #[async_std::test]
+    async fn generated_etags_answer_if_none_match() {
+        let app = app(|conditional| conditional);
+
+        let res = send(&app, request(Method::Get, "/generated", None)).await;
+        let etag = res.header(headers::ETAG).unwrap().as_str().to_owned();
+        assert!(!etag.starts_with("W/"));
+
+        let res = send(
+            &app,
+            request(Method::Get, "/generated", Some(("If-None-Match", &etag))),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::NotModified);
+        assert_eq!(res.header(headers::ETAG).unwrap().as_str(), etag);
+
+        let res = send(
+            &app,
+            request(
+                Method::Get,
+                "/generated",
+                Some(("If-None-Match", "\"other\"")),
+            ),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::Ok);
+    }
+
+    #[async_std::test]
+    async fn generation_can_be_weakened_or_turned_off() {
+        let app = self::app(|conditional| conditional.weak_etags(true));
+        let res = send(&app, request(Method::Get, "/generated", None)).await;
+        assert!(res
+            .header(headers::ETAG)
+            .unwrap()
+            .as_str()
+            .starts_with("W/"));
+
+        let app = self::app(|conditional| conditional.generate_etags(false));
+        let res = send(&app, request(Method::Get, "/generated", None)).await;
+        assert!(res.header(headers::ETAG).is_none());
+
+        let app = self::app(|conditional| conditional.max_buffer_size(4));
+        let res = send(&app, request(Method::Get, "/generated", None)).await;
+        assert!(res.header(headers::ETAG).is_none());
+    }
+
+    #[async_std::test]
+    async fn not_modified_keeps_cookies() {
+        let app = app(|conditional| conditional);
+        let res = send(
+            &app,
+            request(Method::Get, "/tagged", Some(("If-None-Match", "\"v1\""))),
+        )
+        .await;
+
+        assert_eq!(res.status(), StatusCode::NotModified);
+        assert_eq!(res.header(headers::ETAG).unwrap().as_str(), "\"v1\"");
+        assert!(res.header(headers::SET_COOKIE).is_some());
+    }
+
+    #[async_std::test]
+    async fn if_match_failures_are_reported() {
+        let app = app(|conditional| conditional);
+
+        let res = send(
+            &app,
+            request(Method::Get, "/tagged", Some(("If-Match", "\"v2\""))),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::PreconditionFailed);
+
+        // Unsafe methods are checked by the endpoint
+        let res = send(
+            &app,
+            request(Method::Put, "/tagged", Some(("If-Match", "\"v2\""))),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::PreconditionFailed);
+        let res = send(
+            &app,
+            request(Method::Put, "/tagged", Some(("If-Match", "\"v1\""))),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::NoContent);
+    }
+
+    #[async_std::test]
+    async fn missing_resources_match_no_etag() {
+        let app = app(|conditional| conditional);
+
+        let res = send(
+            &app,
+            request(Method::Put, "/missing", Some(("If-Match", "*"))),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::PreconditionFailed);
+
+        // Create-only
+        let res = send(
+            &app,
+            request(Method::Put, "/missing", Some(("If-None-Match", "*"))),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::Created);
+        let res = send(
+            &app,
+            request(Method::Put, "/tagged", Some(("If-None-Match", "*"))),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::PreconditionFailed);
+    }
+
+    #[async_std::test]
+    async fn unsuccessful_responses_are_left_alone() {
+        let app = app(|conditional| conditional);
+        let res = send(
+            &app,
+            request(Method::Get, "/missing", Some(("If-None-Match", "*"))),
+        )
+        .await;
+
+        assert_eq!(res.status(), StatusCode::NotFound);
+        assert!(res.header(headers::ETAG).is_none());
+    }
+}
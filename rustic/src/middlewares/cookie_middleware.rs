@@ -2,19 +2,74 @@ use std::sync::{Arc, RwLock};
 
 use async_trait::async_trait;
 use http_types::{
-    cookies::{CookieJar, Delta},
+    cookies::{CookieJar, Delta, Key},
     headers, Cookie,
 };
+use kv_log_macro::{error, warn};
 
-use crate::{response::CookieEvent, Middleware, Next, Request};
+use super::cookie_policy::{CookiePattern, CookiePolicy};
+use crate::{response::CookieEvent, Middleware, Next, Request, Server};
 
 #[derive(Default)]
-pub struct CookieMiddleware;
+pub struct CookieMiddleware {
+    keys: Arc<CookieKeys>,
+    policies: Vec<(CookiePattern, CookiePolicy)>,
+}
 
 impl CookieMiddleware {
-    pub(crate) fn new() -> Self {
+    #[must_use]
+    pub fn new() -> Self {
         Self::default()
     }
+
+    /// Sign and encrypt cookies with a key derived from `secret`.
+    ///
+    /// Panics if `secret` is shorter than 32 bytes.
+    #[must_use]
+    pub fn key(mut self, secret: &[u8]) -> Self {
+        Arc::make_mut(&mut self.keys).current = Some(Key::derive_from(secret));
+        self
+    }
+
+    /// Keep accepting cookies signed or encrypted with an older key, so keys can be rotated
+    /// without invalidating every cookie at once.
+    ///
+    /// Panics if `secret` is shorter than 32 bytes.
+    #[must_use]
+    pub fn previous_key(mut self, secret: &[u8]) -> Self {
+        Arc::make_mut(&mut self.keys)
+            .previous
+            .push(Key::derive_from(secret));
+        self
+    }
+
+    /// Apply `policy` to every cookie sent whose name matches `pattern`.
+    ///
+    /// Only the first policy matching a cookie is applied, so register specific patterns
+    /// before general ones.
+    #[must_use]
+    pub fn policy(mut self, pattern: impl Into<CookiePattern>, policy: CookiePolicy) -> Self {
+        self.policies.push((pattern.into(), policy));
+        self
+    }
+
+    fn policy_for(&self, name: &str) -> Option<&CookiePolicy> {
+        self.policies
+            .iter()
+            .find(|(pattern, _)| pattern.matches(name))
+            .map(|(_, policy)| policy)
+    }
+
+    /// `None` with an error logged, the cookie is then left out rather than failing a
+    /// response that is otherwise complete.
+    fn signing_key(&self, cookie: &Cookie<'_>) -> Option<&Key> {
+        if self.keys.current.is_none() {
+            error!("Signed and private cookies need a key, configure one with `with_cookies`", {
+                name: cookie.name(),
+            });
+        }
+        self.keys.current.as_ref()
+    }
 }
 
 #[async_trait]
@@ -23,7 +78,7 @@ impl Middleware for CookieMiddleware {
         let cookie_jar = if let Some(cookie_data) = request.ext::<CookieData>() {
             cookie_data.content.clone()
         } else {
-            let cookie_data = CookieData::from_request(&request);
+            let cookie_data = CookieData::from_request(&request, self.keys.clone());
             let content = cookie_data.content.clone();
             request.set_ext(cookie_data);
             content
@@ -37,10 +92,32 @@ impl Middleware for CookieMiddleware {
 
         let jar = &mut *cookie_jar.write().unwrap();
 
-        for cookie in res.cookie_events.drain(..) {
-            match cookie {
-                CookieEvent::Added(cookie) => jar.add(cookie.clone()),
-                CookieEvent::Removed(cookie) => jar.remove(cookie.clone()),
+        for mut event in res.cookie_events.drain(..) {
+            let cookie = event.cookie_mut();
+            if let Some(policy) = self.policy_for(cookie.name()) {
+                policy.apply(cookie);
+            }
+
+            match event {
+                CookieEvent::Added(cookie)
+                | CookieEvent::AddedSigned(cookie)
+                | CookieEvent::AddedPrivate(cookie)
+                    if !has_valid_prefix(&cookie) =>
+                {
+                    reject_invalid_prefix(&cookie)
+                }
+                CookieEvent::Added(cookie) => jar.add(cookie),
+                CookieEvent::AddedSigned(cookie) => {
+                    if let Some(key) = self.signing_key(&cookie) {
+                        jar.add_signed(cookie, key);
+                    }
+                }
+                CookieEvent::AddedPrivate(cookie) => {
+                    if let Some(key) = self.signing_key(&cookie) {
+                        jar.add_private(cookie, key);
+                    }
+                }
+                CookieEvent::Removed(cookie) => jar.remove(cookie),
             }
         }
 
@@ -53,12 +130,83 @@ impl Middleware for CookieMiddleware {
     }
 }
 
+/// Browsers drop `__Secure-` cookies without `Secure`, and `__Host-` cookies that are not
+/// also scoped to `/` on the exact host that set them.
+fn has_valid_prefix(cookie: &Cookie<'_>) -> bool {
+    let name = cookie.name();
+    let secure = cookie.secure() == Some(true);
+
+    if has_prefix(name, "__Host-") {
+        secure && cookie.path() == Some("/") && cookie.domain().is_none()
+    } else if has_prefix(name, "__Secure-") {
+        secure
+    } else {
+        true
+    }
+}
+
+fn has_prefix(name: &str, prefix: &str) -> bool {
+    name.get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+fn reject_invalid_prefix(cookie: &Cookie<'_>) {
+    error!("Cookie violates its name prefix rules, not sending it", {
+        name: cookie.name(),
+    });
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct CookieKeys {
+    current: Option<Key>,
+    previous: Vec<Key>,
+}
+
+impl CookieKeys {
+    fn all(&self) -> impl Iterator<Item = &Key> {
+        self.current.iter().chain(self.previous.iter())
+    }
+
+    /// Check the signature of `cookie` against every known key, returning the cookie with
+    /// its original value.
+    pub(crate) fn verify(&self, cookie: &Cookie<'static>) -> Option<Cookie<'static>> {
+        let verified = self.all().find_map(|key| {
+            let mut jar = CookieJar::new();
+            jar.add_original(cookie.clone());
+            jar.signed(key).get(cookie.name())
+        });
+
+        if verified.is_none() {
+            warn!("Signed cookie failed verification", { name: cookie.name() });
+        }
+
+        verified
+    }
+
+    /// Decrypt and authenticate `cookie` with every known key, returning the cookie with
+    /// its plaintext value.
+    pub(crate) fn decrypt(&self, cookie: &Cookie<'static>) -> Option<Cookie<'static>> {
+        let decrypted = self.all().find_map(|key| {
+            let mut jar = CookieJar::new();
+            jar.add_original(cookie.clone());
+            jar.private(key).get(cookie.name())
+        });
+
+        if decrypted.is_none() {
+            warn!("Private cookie failed verification", { name: cookie.name() });
+        }
+
+        decrypted
+    }
+}
+
 pub struct CookieData {
     pub(crate) content: Arc<RwLock<LazyJar>>,
+    pub(crate) keys: Arc<CookieKeys>,
 }
 
 impl CookieData {
-    pub(crate) fn from_request(req: &Request) -> Self {
+    pub(crate) fn from_request(req: &Request, keys: Arc<CookieKeys>) -> Self {
         let jar = if let Some(cookie_headers) = req.header(&headers::COOKIE) {
             let mut jar = CookieJar::new();
             for cookie_header in cookie_headers {
@@ -76,6 +224,7 @@ impl CookieData {
 
         CookieData {
             content: Arc::new(RwLock::new(jar)),
+            keys,
         }
     }
 }
@@ -88,6 +237,14 @@ impl LazyJar {
         self.get_jar().add(cookie)
     }
 
+    fn add_signed(&mut self, cookie: Cookie<'static>, key: &Key) {
+        self.get_jar().signed(key).add(cookie)
+    }
+
+    fn add_private(&mut self, cookie: Cookie<'static>, key: &Key) {
+        self.get_jar().private(key).add(cookie)
+    }
+
     fn remove(&mut self, cookie: Cookie<'static>) {
         self.get_jar().remove(cookie)
     }
@@ -111,3 +268,160 @@ impl LazyJar {
         self.0.as_mut().unwrap()
     }
 }
+
+pub trait WithCookies {
+    fn with_cookies(
+        &mut self,
+        configure: impl Fn(CookieMiddleware) -> CookieMiddleware,
+    ) -> &mut Self;
+}
+
+impl WithCookies for Server {
+    fn with_cookies(
+        &mut self,
+        configure: impl Fn(CookieMiddleware) -> CookieMiddleware,
+    ) -> &mut Self {
+        let cookies = CookieMiddleware::new();
+
+        self.set_cookie_middleware((configure)(cookies));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_types::{Method, Request as HttpRequest, Response as HttpResponse, StatusCode, Url};
+
+    use super::*;
+    use crate::Response;
+
+    const KEY: [u8; 32] = [1; 32];
+    const OLD_KEY: [u8; 32] = [2; 32];
+
+    fn app(configure: impl Fn(CookieMiddleware) -> CookieMiddleware) -> Server {
+        let mut app = crate::new();
+        app.with_cookies(configure);
+        app.at("/set").get(|_: Request| async move {
+            let mut res = Response::new(StatusCode::Ok);
+            res.insert_cookie(Cookie::new("plain", "visible"));
+            res.insert_signed_cookie(Cookie::new("signed", "untampered"));
+            res.insert_private_cookie(Cookie::new("private", "secret"));
+            res.set_body("done");
+            Ok(res)
+        });
+        app.at("/get").get(|req: Request| async move {
+            let value = |cookie: Option<Cookie<'static>>| {
+                cookie.map_or_else(|| "-".to_owned(), |cookie| cookie.value().to_owned())
+            };
+            Ok(format!(
+                "{} {}",
+                value(req.signed_cookie("signed")),
+                value(req.private_cookie("private"))
+            ))
+        });
+        app.at("/prefixed").get(|_: Request| async move {
+            let mut res = Response::new(StatusCode::Ok);
+            res.insert_cookie(Cookie::new("__Host-insecure", "1"));
+            let mut secure = Cookie::new("__Host-secure", "1");
+            secure.set_secure(true);
+            secure.set_path("/");
+            res.insert_cookie(secure);
+            res.insert_cookie(Cookie::new("__Secure-insecure", "1"));
+            Ok(res)
+        });
+        app
+    }
+
+    async fn get(app: &Server, path: &str, cookies: &[String]) -> HttpResponse {
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        let mut req = HttpRequest::new(Method::Get, url);
+        if !cookies.is_empty() {
+            req.insert_header(headers::COOKIE, cookies.join("; "));
+        }
+        app.respond(req).await.unwrap()
+    }
+
+    /// `name=value` of every cookie set by the response.
+    fn set_cookies(res: &HttpResponse) -> Vec<String> {
+        res.header(headers::SET_COOKIE)
+            .map(|values| {
+                values
+                    .iter()
+                    .map(|value| {
+                        let cookie = Cookie::parse_encoded(value.as_str().to_owned()).unwrap();
+                        format!("{}={}", cookie.name(), cookie.value())
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn named<'a>(cookies: &'a [String], name: &str) -> Option<&'a String> {
+        cookies
+            .iter()
+            .find(|cookie| cookie.starts_with(&format!("{}=", name)))
+    }
+
+    #[async_std::test]
+    async fn signed_and_private_cookies_round_trip() {
+        let app = app(|cookies| cookies.key(&KEY));
+        let cookies = set_cookies(&get(&app, "/set", &[]).await);
+
+        assert_eq!(named(&cookies, "plain").unwrap(), "plain=visible");
+        assert!(named(&cookies, "signed").unwrap().contains("untampered"));
+        assert!(!named(&cookies, "private").unwrap().contains("secret"));
+
+        let mut res = get(&app, "/get", &cookies).await;
+        assert_eq!(res.body_string().await.unwrap(), "untampered secret");
+    }
+
+    #[async_std::test]
+    async fn tampered_cookies_are_rejected() {
+        let app = app(|cookies| cookies.key(&KEY));
+        let cookies: Vec<String> = set_cookies(&get(&app, "/set", &[]).await)
+            .into_iter()
+            .map(|cookie| cookie.replace("untampered", "tampered!!"))
+            .map(|cookie| {
+                if cookie.starts_with("private=") {
+                    format!("{}x", &cookie[..cookie.len() - 1])
+                } else {
+                    cookie
+                }
+            })
+            .collect();
+
+        let mut res = get(&app, "/get", &cookies).await;
+        assert_eq!(res.body_string().await.unwrap(), "- -");
+    }
+
+    #[async_std::test]
+    async fn cookies_from_a_previous_key_are_still_accepted() {
+        let old = app(|cookies| cookies.key(&OLD_KEY));
+        let cookies = set_cookies(&get(&old, "/set", &[]).await);
+
+        let rotated = app(|cookies| cookies.key(&KEY).previous_key(&OLD_KEY));
+        let mut res = get(&rotated, "/get", &cookies).await;
+        assert_eq!(res.body_string().await.unwrap(), "untampered secret");
+
+        let forgotten = app(|cookies| cookies.key(&KEY));
+        let mut res = get(&forgotten, "/get", &cookies).await;
+        assert_eq!(res.body_string().await.unwrap(), "- -");
+    }
+
+    #[async_std::test]
+    async fn missing_key_leaves_out_only_the_keyed_cookies() {
+        let app = app(|cookies| cookies);
+        let mut res = get(&app, "/set", &[]).await;
+
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(set_cookies(&res), ["plain=visible"]);
+        assert_eq!(res.body_string().await.unwrap(), "done");
+    }
+
+    #[async_std::test]
+    async fn cookies_breaking_prefix_rules_are_not_sent() {
+        let app = app(|cookies| cookies);
+        let cookies = set_cookies(&get(&app, "/prefixed", &[]).await);
+        assert_eq!(cookies, ["__Host-secure=1"]);
+    }
+}
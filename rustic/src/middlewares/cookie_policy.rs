@@ -0,0 +1,215 @@
+use std::fmt::Debug;
+
+use http_types::{cookies::SameSite, Cookie};
+use kv_log_macro::warn;
+use regex::Regex;
+
+/// Attributes that cookies matching a `CookiePattern` should carry.
+///
+/// Attributes missing on a cookie are filled in. Attributes a handler set to something else
+/// are reported with a warning, and overwritten as well if the policy is enforced.
+#[derive(Clone, Default)]
+pub struct CookiePolicy {
+    secure: Option<bool>,
+    http_only: Option<bool>,
+    same_site: Option<SameSite>,
+    path: Option<String>,
+    enforce: bool,
+}
+
+impl CookiePolicy {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = Some(secure);
+        self
+    }
+
+    #[must_use]
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = Some(http_only);
+        self
+    }
+
+    #[must_use]
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    #[must_use]
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Overwrite conflicting attributes instead of only warning about them.
+    #[must_use]
+    pub fn enforce(mut self, enforce: bool) -> Self {
+        self.enforce = enforce;
+        self
+    }
+
+    pub(crate) fn apply(&self, cookie: &mut Cookie<'static>) {
+        let name = cookie.name().to_owned();
+
+        if let Some(secure) = self.resolve(&name, "Secure", cookie.secure(), self.secure) {
+            cookie.set_secure(secure);
+        }
+
+        if let Some(http_only) = self.resolve(&name, "HttpOnly", cookie.http_only(), self.http_only)
+        {
+            cookie.set_http_only(http_only);
+        }
+
+        if let Some(same_site) = self.resolve(&name, "SameSite", cookie.same_site(), self.same_site)
+        {
+            cookie.set_same_site(same_site);
+        }
+
+        let path = cookie.path().map(str::to_owned);
+        if let Some(path) = self.resolve(&name, "Path", path, self.path.clone()) {
+            cookie.set_path(path);
+        }
+    }
+
+    /// Determine the value to set an attribute to, if any.
+    fn resolve<T: PartialEq + Debug>(
+        &self,
+        name: &str,
+        attribute: &str,
+        current: Option<T>,
+        wanted: Option<T>,
+    ) -> Option<T> {
+        let wanted = wanted?;
+
+        match current {
+            None => Some(wanted),
+            Some(current) if current == wanted => None,
+            Some(current) => {
+                warn!("Cookie violates the cookie policy", {
+                    name: name,
+                    attribute: attribute,
+                    value: format!("{:?}", current),
+                    expected: format!("{:?}", wanted),
+                    enforced: self.enforce,
+                });
+                self.enforce.then_some(wanted)
+            }
+        }
+    }
+}
+
+/// Selects the cookies a `CookiePolicy` applies to by name.
+#[derive(Clone)]
+pub enum CookiePattern {
+    Any,
+    Exact(String),
+    Match(Regex),
+}
+
+impl CookiePattern {
+    pub(crate) fn matches(&self, name: &str) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Exact(s) => s == name,
+            Self::Match(regex) => regex.is_match(name),
+        }
+    }
+}
+
+impl From<String> for CookiePattern {
+    fn from(s: String) -> Self {
+        if s == "*" {
+            return Self::Any;
+        }
+        Self::Exact(s)
+    }
+}
+
+impl From<&str> for CookiePattern {
+    fn from(s: &str) -> Self {
+        Self::from(s.to_string())
+    }
+}
+
+impl From<Regex> for CookiePattern {
+    fn from(regex: Regex) -> Self {
+        Self::Match(regex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> CookiePolicy {
+        CookiePolicy::new()
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Strict)
+            .path("/")
+    }
+
+    #[test]
+    fn missing_attributes_are_filled_in() {
+        let mut cookie = Cookie::new("id", "1");
+        policy().apply(&mut cookie);
+
+        assert_eq!(cookie.secure(), Some(true));
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.same_site(), Some(SameSite::Strict));
+        assert_eq!(cookie.path(), Some("/"));
+    }
+
+    #[test]
+    fn conflicting_attributes_are_kept_unless_enforced() {
+        let conflicting = || {
+            let mut cookie = Cookie::new("id", "1");
+            cookie.set_secure(false);
+            cookie.set_same_site(SameSite::None);
+            cookie.set_path("/admin");
+            cookie
+        };
+
+        let mut cookie = conflicting();
+        policy().apply(&mut cookie);
+        assert_eq!(cookie.secure(), Some(false));
+        assert_eq!(cookie.same_site(), Some(SameSite::None));
+        assert_eq!(cookie.path(), Some("/admin"));
+        assert_eq!(cookie.http_only(), Some(true));
+
+        let mut cookie = conflicting();
+        policy().enforce(true).apply(&mut cookie);
+        assert_eq!(cookie.secure(), Some(true));
+        assert_eq!(cookie.same_site(), Some(SameSite::Strict));
+        assert_eq!(cookie.path(), Some("/"));
+    }
+
+    #[test]
+    fn unset_policy_attributes_are_left_alone() {
+        let mut cookie = Cookie::new("id", "1");
+        cookie.set_http_only(false);
+        CookiePolicy::new().secure(true).apply(&mut cookie);
+
+        assert_eq!(cookie.secure(), Some(true));
+        assert_eq!(cookie.http_only(), Some(false));
+        assert_eq!(cookie.same_site(), None);
+        assert_eq!(cookie.path(), None);
+    }
+
+    #[test]
+    fn patterns_match_by_name() {
+        assert!(CookiePattern::from("*").matches("anything"));
+        assert!(CookiePattern::from("session").matches("session"));
+        assert!(!CookiePattern::from("session").matches("session2"));
+
+        let pattern = CookiePattern::from(Regex::new("^pref_").unwrap());
+        assert!(pattern.matches("pref_theme"));
+        assert!(!pattern.matches("theme"));
+    }
+}
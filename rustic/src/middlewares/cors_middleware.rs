@@ -37,10 +37,7 @@ impl CorsMiddleware {
 
     #[must_use]
     pub fn allow_credentials(mut self, allow_credentials: bool) -> Self {
-        self.allow_credentials = match allow_credentials.to_string().parse() {
-            Ok(header) => Some(header),
-            Err(_) => None,
-        };
+        self.allow_credentials = allow_credentials.to_string().parse().ok();
         self
     }
 
@@ -0,0 +1,520 @@
+use std::{collections::HashMap, sync::PoisonError};
+
+use async_std::io::ReadExt;
+use async_trait::async_trait;
+use http_types::{
+    cookies::SameSite,
+    headers::{self, HeaderName},
+    mime, Body, Cookie, Error, Method, StatusCode, Url,
+};
+use kv_log_macro::warn;
+use rand::RngCore;
+
+use crate::{middlewares::SessionData, Middleware, Next, Request, Server};
+
+pub(crate) const DEFAULT_HEADER_NAME: &str = "X-CSRF-Token";
+pub(crate) const DEFAULT_FIELD_NAME: &str = "csrf_token";
+pub(crate) const DEFAULT_COOKIE_NAME: &str = "csrf_token";
+/// Forms are small, anything bigger than this is refused before handlers run.
+pub(crate) const DEFAULT_MAX_FORM_SIZE: u64 = 64 * 1024;
+const SESSION_KEY: &str = "csrf_token";
+const TOKEN_BYTES: usize = 32;
+
+/// Where the expected token is kept between requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsrfStorage {
+    /// Double-submit cookie: the token lives in a cookie that scripts can read, and has
+    /// to be sent back in the form field or header.
+    Cookie,
+    /// The token lives in the session, `SessionMiddleware` has to run first.
+    Session,
+}
+
+/// The token for the current request, see `Request::csrf_token`.
+pub(crate) struct CsrfToken(pub(crate) String);
+
+pub struct CsrfMiddleware {
+    storage: CsrfStorage,
+    header_name: HeaderName,
+    field_name: String,
+    cookie_name: String,
+    allowed_origins: Vec<String>,
+    exempt: Vec<String>,
+    max_form_size: u64,
+}
+
+impl CsrfMiddleware {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            storage: CsrfStorage::Cookie,
+            header_name: DEFAULT_HEADER_NAME.into(),
+            field_name: DEFAULT_FIELD_NAME.to_owned(),
+            cookie_name: DEFAULT_COOKIE_NAME.to_owned(),
+            allowed_origins: Vec::new(),
+            exempt: Vec::new(),
+            max_form_size: DEFAULT_MAX_FORM_SIZE,
+        }
+    }
+
+    #[must_use]
+    pub fn storage(mut self, storage: CsrfStorage) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    #[must_use]
+    pub fn header_name(mut self, header_name: impl Into<HeaderName>) -> Self {
+        self.header_name = header_name.into();
+        self
+    }
+
+    /// Name of the `application/x-www-form-urlencoded` field holding the token.
+    #[must_use]
+    pub fn field_name(mut self, field_name: impl Into<String>) -> Self {
+        self.field_name = field_name.into();
+        self
+    }
+
+    #[must_use]
+    pub fn cookie_name(mut self, cookie_name: impl Into<String>) -> Self {
+        self.cookie_name = cookie_name.into();
+        self
+    }
+
+    /// The largest url-encoded form body read to look for the token field, larger ones are
+    /// answered with `413 Payload Too Large`. Requests sending the token in the header
+    /// are not affected.
+    #[must_use]
+    pub fn max_form_size(mut self, max_form_size: u64) -> Self {
+        self.max_form_size = max_form_size;
+        self
+    }
+
+    /// Accept requests from another origin, e.g. `https://admin.example.com`. The
+    /// request's own host is always accepted.
+    #[must_use]
+    pub fn allow_origin(mut self, origin: impl Into<String>) -> Self {
+        self.allowed_origins
+            .push(origin.into().trim_end_matches('/').to_owned());
+        self
+    }
+
+    /// Skip CSRF checks for `path`, or for every path starting with it if it ends with `*`.
+    #[must_use]
+    pub fn exempt(mut self, path: impl Into<String>) -> Self {
+        self.exempt.push(path.into());
+        self
+    }
+
+    fn is_exempt(&self, path: &str) -> bool {
+        self.exempt
+            .iter()
+            .any(|exempt| match exempt.strip_suffix('*') {
+                Some(prefix) => path.starts_with(prefix),
+                None => path == exempt,
+            })
+    }
+
+    /// The token issued to this client earlier, if any.
+    fn stored_token(&self, request: &Request) -> crate::Result<Option<String>> {
+        match self.storage {
+            CsrfStorage::Cookie => Ok(request
+                .cookie(&self.cookie_name)
+                .map(|cookie| cookie.value().to_owned())),
+            CsrfStorage::Session => Ok(session_data(request)?
+                .content
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get(SESSION_KEY)),
+        }
+    }
+
+    fn is_allowed_origin(&self, request: &Request) -> bool {
+        let origin = if let Some(origin) = request.header(&headers::ORIGIN) {
+            origin.last().as_str().to_owned()
+        } else if let Some(referer) = request.header(&headers::REFERER) {
+            match Url::parse(referer.last().as_str()) {
+                Ok(referer) => referer.origin().ascii_serialization(),
+                Err(_) => return false,
+            }
+        } else {
+            // Neither header was sent, only the token can be checked
+            return true;
+        };
+
+        if self.allowed_origins.contains(&origin) {
+            return true;
+        }
+
+        let host = request
+            .header(&headers::HOST)
+            .map(|host| host.last().as_str().to_owned())
+            .or_else(|| request.url().host_str().map(str::to_owned));
+
+        match (Url::parse(&origin), host) {
+            (Ok(origin), Some(host)) => origin_host(&origin) == host,
+            _ => false,
+        }
+    }
+
+    /// Look for the submitted token in the header, then in a url-encoded form body.
+    async fn submitted_token(&self, request: &mut Request) -> crate::Result<Option<String>> {
+        if let Some(token) = request.header(&self.header_name) {
+            return Ok(Some(token.last().as_str().to_owned()));
+        }
+
+        let is_form = request
+            .req
+            .content_type()
+            .is_some_and(|content_type| content_type.essence() == mime::FORM.essence());
+        if !is_form {
+            return Ok(None);
+        }
+
+        let too_large = || {
+            Error::from_str(
+                StatusCode::PayloadTooLarge,
+                "Form too large to look for a CSRF token",
+            )
+        };
+        if request
+            .req
+            .len()
+            .is_some_and(|len| len as u64 > self.max_form_size)
+        {
+            return Err(too_large());
+        }
+
+        // Bodies without a length are cut off one byte past the limit
+        let mut bytes = Vec::new();
+        request
+            .req
+            .take_body()
+            .take(self.max_form_size + 1)
+            .read_to_end(&mut bytes)
+            .await?;
+        if bytes.len() as u64 > self.max_form_size {
+            return Err(too_large());
+        }
+
+        let form = Body::from_bytes(bytes.clone())
+            .into_form::<HashMap<String, String>>()
+            .await;
+        request.req.set_body(Body::from_bytes(bytes));
+
+        Ok(form.ok().and_then(|mut form| form.remove(&self.field_name)))
+    }
+
+    fn build_cookie(&self, token: String) -> Cookie<'static> {
+        let mut cookie = Cookie::new(self.cookie_name.clone(), token);
+        cookie.set_path("/");
+        cookie.set_same_site(SameSite::Strict);
+        cookie
+    }
+}
+
+#[async_trait]
+impl Middleware for CsrfMiddleware {
+    async fn handle(&self, mut request: Request, next: Next<'_>) -> crate::Result {
+        if self.is_exempt(request.url().path()) {
+            return Ok(next.run(request).await);
+        }
+
+        let stored = self.stored_token(&request)?;
+
+        if !is_safe_method(request.method()) {
+            if !self.is_allowed_origin(&request) {
+                warn!("CSRF check failed, cross-origin request", {
+                    path: request.url().path(),
+                });
+                return Err(Error::from_str(
+                    StatusCode::Forbidden,
+                    "Cross-origin request rejected",
+                ));
+            }
+
+            let submitted = self.submitted_token(&mut request).await?;
+            let valid = match (&stored, &submitted) {
+                (Some(stored), Some(submitted)) => {
+                    constant_time_eq(stored.as_bytes(), submitted.as_bytes())
+                }
+                _ => false,
+            };
+
+            if !valid {
+                warn!("CSRF check failed, missing or invalid token", {
+                    path: request.url().path(),
+                });
+                return Err(Error::from_str(
+                    StatusCode::Forbidden,
+                    "CSRF token missing or invalid",
+                ));
+            }
+        }
+
+        let (token, issued) = match stored {
+            Some(token) => (token, false),
+            None => (generate_token(), true),
+        };
+
+        if issued && self.storage == CsrfStorage::Session {
+            session_data(&request)?
+                .content
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(SESSION_KEY, &token)?;
+        }
+
+        request.set_ext(CsrfToken(token.clone()));
+
+        let mut res = next.run(request).await;
+
+        if issued && self.storage == CsrfStorage::Cookie {
+            res.insert_cookie(self.build_cookie(token));
+        }
+
+        Ok(res)
+    }
+}
+
+impl Default for CsrfMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn session_data(request: &Request) -> crate::Result<&SessionData> {
+    request.ext::<SessionData>().ok_or_else(|| {
+        Error::from_str(
+            StatusCode::InternalServerError,
+            "CsrfStorage::Session needs SessionMiddleware to run first",
+        )
+    })
+}
+
+fn is_safe_method(method: Method) -> bool {
+    matches!(
+        method,
+        Method::Get | Method::Head | Method::Options | Method::Trace
+    )
+}
+
+/// `host[:port]` as it appears in a `Host` header.
+fn origin_host(url: &Url) -> String {
+    match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_owned(),
+        _ => String::new(),
+    }
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+pub trait WithCsrf {
+    fn with_csrf(&mut self, configure: impl Fn(CsrfMiddleware) -> CsrfMiddleware) -> &mut Self;
+}
+
+impl WithCsrf for Server {
+    fn with_csrf(&mut self, configure: impl Fn(CsrfMiddleware) -> CsrfMiddleware) -> &mut Self {
+        let csrf = CsrfMiddleware::new();
+
+        self.with((configure)(csrf));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::io::Cursor;
+    use http_types::{Request as HttpRequest, Response as HttpResponse};
+
+    use super::*;
+    use crate::{MemoryStore, WithSessions};
+
+    fn app(configure: impl Fn(CsrfMiddleware) -> CsrfMiddleware) -> Server {
+        let mut app = crate::new();
+        app.with_csrf(configure);
+        app.at("/form")
+            .get(|req: Request| async move { Ok(req.csrf_token().unwrap_or_default().to_owned()) });
+        app.at("/form")
+            .post(|mut req: Request| async move { req.req.body_string().await });
+        app.at("/webhook")
+            .post(|_: Request| async move { Ok("hook") });
+        app
+    }
+
+    fn request(method: Method, path: &str) -> HttpRequest {
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        let mut req = HttpRequest::new(method, url);
+        req.insert_header(headers::HOST, "localhost");
+        req
+    }
+
+    /// The token issued by `GET /form` and the cookie carrying it.
+    async fn issue(app: &Server) -> (String, String) {
+        let mut res: HttpResponse = app.respond(request(Method::Get, "/form")).await.unwrap();
+        let cookie = res.header(headers::SET_COOKIE).unwrap().as_str().to_owned();
+        let cookie = Cookie::parse(cookie).unwrap();
+        let token = res.body_string().await.unwrap();
+        assert_eq!(cookie.value(), token);
+        (token, format!("{}={}", cookie.name(), cookie.value()))
+    }
+
+    fn post(cookie: &str) -> HttpRequest {
+        let mut req = request(Method::Post, "/form");
+        req.insert_header(headers::COOKIE, cookie);
+        req
+    }
+
+    async fn status(app: &Server, req: HttpRequest) -> StatusCode {
+        let res: HttpResponse = app.respond(req).await.unwrap();
+        res.status()
+    }
+
+    #[async_std::test]
+    async fn token_is_accepted_from_the_header() {
+        let app = app(|csrf| csrf);
+        let (token, cookie) = issue(&app).await;
+
+        assert_eq!(status(&app, post(&cookie)).await, StatusCode::Forbidden);
+
+        let mut req = post(&cookie);
+        req.insert_header(DEFAULT_HEADER_NAME, token);
+        assert_eq!(status(&app, req).await, StatusCode::Ok);
+
+        let mut req = post(&cookie);
+        req.insert_header(DEFAULT_HEADER_NAME, "forged");
+        assert_eq!(status(&app, req).await, StatusCode::Forbidden);
+    }
+
+    #[async_std::test]
+    async fn token_is_accepted_from_the_form_which_stays_readable() {
+        let app = app(|csrf| csrf);
+        let (token, cookie) = issue(&app).await;
+
+        let mut req = post(&cookie);
+        let form = format!("name=x&{}={}", DEFAULT_FIELD_NAME, token);
+        req.set_body(form.clone());
+        req.set_content_type(mime::FORM);
+        let mut res: HttpResponse = app.respond(req).await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.body_string().await.unwrap(), form);
+    }
+
+    #[async_std::test]
+    async fn oversized_forms_are_refused() {
+        let app = app(|csrf| csrf.max_form_size(16));
+        let (token, cookie) = issue(&app).await;
+        let form = format!("{}={}&padding=xxxxxxxx", DEFAULT_FIELD_NAME, token);
+
+        let mut req = post(&cookie);
+        req.set_body(form.clone());
+        req.set_content_type(mime::FORM);
+        assert_eq!(status(&app, req).await, StatusCode::PayloadTooLarge);
+
+        // Without a length the body is cut off while it is read
+        let mut req = post(&cookie);
+        req.set_body(Body::from_reader(Cursor::new(form.into_bytes()), None));
+        req.set_content_type(mime::FORM);
+        assert_eq!(status(&app, req).await, StatusCode::PayloadTooLarge);
+    }
+
+    #[async_std::test]
+    async fn cross_origin_requests_are_refused() {
+        let app = app(|csrf| csrf.allow_origin("https://admin.example/"));
+        let (token, cookie) = issue(&app).await;
+
+        for (origin, expected) in [
+            ("https://evil.example", StatusCode::Forbidden),
+            ("https://admin.example", StatusCode::Ok),
+            ("http://localhost", StatusCode::Ok),
+        ] {
+            let mut req = post(&cookie);
+            req.insert_header(DEFAULT_HEADER_NAME, token.as_str());
+            req.insert_header(headers::ORIGIN, origin);
+            assert_eq!(status(&app, req).await, expected, "{}", origin);
+        }
+    }
+
+    #[async_std::test]
+    async fn exempt_paths_are_not_checked() {
+        let app = app(|csrf| csrf.exempt("/web*"));
+        assert_eq!(
+            status(&app, request(Method::Post, "/webhook")).await,
+            StatusCode::Ok
+        );
+        assert_eq!(
+            status(&app, request(Method::Post, "/form")).await,
+            StatusCode::Forbidden
+        );
+    }
+
+    #[async_std::test]
+    async fn session_storage_keeps_the_token_in_the_session() {
+        let mut app = crate::new();
+        app.with_sessions(MemoryStore::new(), |sessions| sessions);
+        app.with_csrf(|csrf| csrf.storage(CsrfStorage::Session));
+        app.at("/form")
+            .get(|req: Request| async move { Ok(req.csrf_token().unwrap_or_default().to_owned()) });
+        app.at("/form")
+            .post(|_: Request| async move { Ok("posted") });
+
+        let mut res: HttpResponse = app.respond(request(Method::Get, "/form")).await.unwrap();
+        let session =
+            Cookie::parse(res.header(headers::SET_COOKIE).unwrap().as_str().to_owned()).unwrap();
+        assert_ne!(session.name(), DEFAULT_COOKIE_NAME);
+        let token = res.body_string().await.unwrap();
+
+        let mut req = post(&format!("{}={}", session.name(), session.value()));
+        req.insert_header(DEFAULT_HEADER_NAME, token);
+        assert_eq!(status(&app, req).await, StatusCode::Ok);
+    }
+
+    /// Panics while holding the session lock, as a handler caught by `Next::run` could.
+    struct PoisonSession;
+
+    #[async_trait]
+    impl Middleware for PoisonSession {
+        async fn handle(&self, request: Request, next: Next<'_>) -> crate::Result {
+            let content = request.ext::<SessionData>().unwrap().content.clone();
+            let _ = std::thread::spawn(move || {
+                let _session = content.write().unwrap();
+                panic!("handler failed while holding the session");
+            })
+            .join();
+            Ok(next.run(request).await)
+        }
+    }
+
+    #[async_std::test]
+    async fn poisoned_sessions_still_hold_the_token() {
+        let mut app = crate::new();
+        app.with_sessions(MemoryStore::new(), |sessions| sessions);
+        app.with(PoisonSession);
+        app.with_csrf(|csrf| csrf.storage(CsrfStorage::Session));
+        app.at("/form")
+            .get(|req: Request| async move { Ok(req.csrf_token().unwrap_or_default().to_owned()) });
+        app.at("/form")
+            .post(|_: Request| async move { Ok("posted") });
+
+        let mut res: HttpResponse = app.respond(request(Method::Get, "/form")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+        let session =
+            Cookie::parse(res.header(headers::SET_COOKIE).unwrap().as_str().to_owned()).unwrap();
+        let token = res.body_string().await.unwrap();
+
+        let mut req = post(&format!("{}={}", session.name(), session.value()));
+        req.insert_header(DEFAULT_HEADER_NAME, token);
+        assert_eq!(status(&app, req).await, StatusCode::Ok);
+    }
+}
@@ -0,0 +1,404 @@
+use std::net::IpAddr;
+
+use async_trait::async_trait;
+use http_types::headers::{self, HeaderValues};
+
+use crate::{
+    trusted_proxies::{parse_ip, TrustedProxies},
+    Middleware, Next, Request, Server,
+};
+
+/// What the proxies in front of the server say about the client, read by
+/// `Request::remote_addr`, `Request::scheme` and `Request::host`.
+#[derive(Clone, Debug)]
+pub(crate) struct ForwardedData {
+    pub(crate) remote_addr: Option<IpAddr>,
+    pub(crate) scheme: Option<String>,
+    pub(crate) host: Option<String>,
+}
+
+/// Resolves the client address, scheme and host from `Forwarded`, or from
+/// `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host` when there is no
+/// `Forwarded` header.
+///
+/// The headers are only believed as far as they were written by trusted proxies: the chain
+/// of addresses is followed from the connected peer towards the client until it reaches an
+/// address that isn't trusted, which is taken as the client. Anyone can prepend entries,
+/// so the entries further left are never looked at.
+pub struct ForwardedMiddleware {
+    trusted_proxies: TrustedProxies,
+}
+
+/// One proxy's account of the request it received.
+#[derive(Debug, Default)]
+struct Hop {
+    /// `None` for `unknown` and obfuscated identifiers.
+    client: Option<IpAddr>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+impl ForwardedMiddleware {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            trusted_proxies: TrustedProxies::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn trusted_proxies(mut self, trusted_proxies: TrustedProxies) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+
+    fn resolve(&self, request: &Request) -> ForwardedData {
+        let mut data = ForwardedData {
+            remote_addr: request.peer_addr().and_then(parse_ip),
+            scheme: None,
+            host: None,
+        };
+
+        let hops = match request.header(headers::FORWARDED) {
+            Some(forwarded) => parse_forwarded(forwarded),
+            None => parse_x_forwarded(request),
+        };
+
+        for hop in hops.into_iter().rev() {
+            match data.remote_addr {
+                Some(proxy) if self.trusted_proxies.contains(proxy) => {}
+                _ => break,
+            }
+
+            data.scheme = hop.proto.or(data.scheme);
+            data.host = hop.host.or(data.host);
+            match hop.client {
+                Some(client) => data.remote_addr = Some(client),
+                // The proxy won't say, the closest address known is its own
+                None => break,
+            }
+        }
+
+        data
+    }
+}
+
+#[async_trait]
+impl Middleware for ForwardedMiddleware {
+    async fn handle(&self, mut request: Request, next: Next<'_>) -> crate::Result {
+        let data = self.resolve(&request);
+        request.set_ext(data);
+        Ok(next.run(request).await)
+    }
+}
+
+impl Default for ForwardedMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `Forwarded: for=192.0.2.60;proto=https, for="[2001:db8::1]:4711"` (RFC 7239)
+fn parse_forwarded(values: &HeaderValues) -> Vec<Hop> {
+    values
+        .iter()
+        .flat_map(|value| value.as_str().split(','))
+        .map(|element| {
+            let mut hop = Hop::default();
+            for pair in element.split(';') {
+                let (name, value) = match pair.split_once('=') {
+                    Some((name, value)) => (name.trim(), value.trim().trim_matches('"')),
+                    None => continue,
+                };
+
+                if name.eq_ignore_ascii_case("for") {
+                    hop.client = parse_ip(value);
+                } else if name.eq_ignore_ascii_case("proto") {
+                    hop.proto = parse_scheme(value);
+                } else if name.eq_ignore_ascii_case("host") {
+                    hop.host = parse_host(value);
+                }
+            }
+            hop
+        })
+        .collect()
+}
+
+/// `X-Forwarded-Proto` and `X-Forwarded-Host` are matched up with `X-Forwarded-For` when
+/// they list as many entries, and otherwise only describe the closest proxy.
+fn parse_x_forwarded(request: &Request) -> Vec<Hop> {
+    let list = |name: &str| -> Vec<String> {
+        request
+            .header(name)
+            .map(|values| {
+                values
+                    .iter()
+                    .flat_map(|value| value.as_str().split(','))
+                    .map(|entry| entry.trim().to_owned())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let mut hops: Vec<Hop> = list("X-Forwarded-For")
+        .iter()
+        .map(|client| Hop {
+            client: parse_ip(client),
+            ..Hop::default()
+        })
+        .collect();
+
+    let protos = list("X-Forwarded-Proto");
+    let hosts = list("X-Forwarded-Host");
+    let hop_count = hops.len();
+    let assign = |hops: &mut Vec<Hop>, entries: Vec<String>, set: fn(&mut Hop, &str)| {
+        if entries.len() == hop_count {
+            for (hop, entry) in hops.iter_mut().zip(&entries) {
+                set(hop, entry);
+            }
+        } else if let Some(entry) = entries.last() {
+            match hops.last_mut() {
+                Some(hop) => set(hop, entry),
+                // Only the protocol or host was forwarded, for a client at the peer address
+                None => {
+                    let mut hop = Hop::default();
+                    set(&mut hop, entry);
+                    hops.push(hop);
+                }
+            }
+        }
+    };
+    assign(&mut hops, protos, |hop, proto| {
+        hop.proto = parse_scheme(proto)
+    });
+    assign(&mut hops, hosts, |hop, host| hop.host = parse_host(host));
+
+    hops
+}
+
+fn parse_scheme(scheme: &str) -> Option<String> {
+    let scheme = scheme.to_ascii_lowercase();
+    matches!(scheme.as_str(), "http" | "https" | "ws" | "wss").then_some(scheme)
+}
+
+/// Anything that could be a `host[:port]`, so it is safe to build URLs from.
+fn parse_host(host: &str) -> Option<String> {
+    let valid = !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']'));
+    valid.then(|| host.to_ascii_lowercase())
+}
+
+pub trait WithForwarded {
+    fn with_forwarded(
+        &mut self,
+        configure: impl Fn(ForwardedMiddleware) -> ForwardedMiddleware,
+    ) -> &mut Self;
+}
+
+impl WithForwarded for Server {
+    fn with_forwarded(
+        &mut self,
+        configure: impl Fn(ForwardedMiddleware) -> ForwardedMiddleware,
+    ) -> &mut Self {
+        let forwarded = ForwardedMiddleware::new();
+
+        self.with((configure)(forwarded));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_types::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    /// Trusts the loopback address and `10.0.0.0/8`.
+    fn middleware() -> ForwardedMiddleware {
+        ForwardedMiddleware::new().trusted_proxies(
+            TrustedProxies::new()
+                .trust("127.0.0.1".parse().unwrap())
+                .trust("10.0.0.0/8".parse().unwrap()),
+        )
+    }
+
+    fn request(peer: &str, headers: &[(&str, &str)]) -> Request {
+        let mut req = HttpRequest::new(Method::Get, Url::parse("http://localhost/").unwrap());
+        req.set_peer_addr(Some(peer));
+        for (name, value) in headers {
+            req.append_header(*name, *value);
+        }
+        Request::new(req, Vec::new())
+    }
+
+    fn resolve(peer: &str, headers: &[(&str, &str)]) -> ForwardedData {
+        middleware().resolve(&request(peer, headers))
+    }
+
+    #[test]
+    fn untrusted_peers_are_the_client() {
+        let data = resolve(
+            "198.51.100.7:5000",
+            &[("Forwarded", "for=192.0.2.1;proto=https;host=forged.example")],
+        );
+        assert_eq!(data.remote_addr, Some(ip("198.51.100.7")));
+        assert_eq!(data.scheme, None);
+        assert_eq!(data.host, None);
+
+        let data = ForwardedMiddleware::new().resolve(&request(
+            "127.0.0.1:5000",
+            &[("X-Forwarded-For", "192.0.2.1")],
+        ));
+        assert_eq!(data.remote_addr, Some(ip("127.0.0.1")));
+    }
+
+    #[test]
+    fn the_chain_is_followed_until_an_untrusted_address() {
+        let data = resolve(
+            "127.0.0.1:5000",
+            &[(
+                "Forwarded",
+                "for=203.0.113.1, for=198.51.100.7;proto=https;host=example.com, for=10.0.0.2",
+            )],
+        );
+        assert_eq!(data.remote_addr, Some(ip("198.51.100.7")));
+        assert_eq!(data.scheme.as_deref(), Some("https"));
+        assert_eq!(data.host.as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn entries_spread_over_several_headers_form_one_chain() {
+        let data = resolve(
+            "127.0.0.1:5000",
+            &[
+                ("Forwarded", "for=203.0.113.1"),
+                ("Forwarded", "for=\"[2001:db8::1]:4711\""),
+                ("Forwarded", "for=10.0.0.2"),
+            ],
+        );
+        assert_eq!(data.remote_addr, Some(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn a_fully_trusted_chain_ends_at_the_leftmost_entry() {
+        let data = resolve(
+            "127.0.0.1:5000",
+            &[("X-Forwarded-For", "10.0.0.3, 10.0.0.2")],
+        );
+        assert_eq!(data.remote_addr, Some(ip("10.0.0.3")));
+    }
+
+    #[test]
+    fn unknown_clients_stop_at_the_proxy() {
+        let data = resolve(
+            "127.0.0.1:5000",
+            &[(
+                "Forwarded",
+                "for=192.0.2.1, for=unknown;proto=https, for=10.0.0.2",
+            )],
+        );
+        assert_eq!(data.remote_addr, Some(ip("10.0.0.2")));
+        assert_eq!(data.scheme.as_deref(), Some("https"));
+
+        let data = resolve("127.0.0.1:5000", &[("Forwarded", "for=_hidden")]);
+        assert_eq!(data.remote_addr, Some(ip("127.0.0.1")));
+    }
+
+    #[test]
+    fn forwarded_takes_precedence_over_x_forwarded() {
+        let data = resolve(
+            "127.0.0.1:5000",
+            &[
+                ("Forwarded", "for=192.0.2.1"),
+                ("X-Forwarded-For", "192.0.2.2"),
+                ("X-Forwarded-Proto", "https"),
+            ],
+        );
+        assert_eq!(data.remote_addr, Some(ip("192.0.2.1")));
+        assert_eq!(data.scheme, None);
+    }
+
+    #[test]
+    fn x_forwarded_lists_of_equal_length_are_matched_up() {
+        let data = resolve(
+            "127.0.0.1:5000",
+            &[
+                ("X-Forwarded-For", "192.0.2.1, 10.0.0.2"),
+                ("X-Forwarded-Proto", "https, http"),
+                ("X-Forwarded-Host", "example.com, internal"),
+            ],
+        );
+        assert_eq!(data.remote_addr, Some(ip("192.0.2.1")));
+        assert_eq!(data.scheme.as_deref(), Some("https"));
+        assert_eq!(data.host.as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn single_x_forwarded_values_describe_the_closest_proxy() {
+        let data = resolve(
+            "127.0.0.1:5000",
+            &[
+                ("X-Forwarded-For", "192.0.2.1, 10.0.0.2"),
+                ("X-Forwarded-Proto", "https"),
+            ],
+        );
+        assert_eq!(data.remote_addr, Some(ip("192.0.2.1")));
+        assert_eq!(data.scheme.as_deref(), Some("https"));
+
+        let data = resolve(
+            "127.0.0.1:5000",
+            &[
+                ("X-Forwarded-Proto", "https"),
+                ("X-Forwarded-Host", "Example.com:8443"),
+            ],
+        );
+        assert_eq!(data.remote_addr, Some(ip("127.0.0.1")));
+        assert_eq!(data.scheme.as_deref(), Some("https"));
+        assert_eq!(data.host.as_deref(), Some("example.com:8443"));
+    }
+
+    #[test]
+    fn unsafe_schemes_and_hosts_are_ignored() {
+        let data = resolve(
+            "127.0.0.1:5000",
+            &[(
+                "Forwarded",
+                "for=192.0.2.1;proto=javascript;host=\"evil.com/path\"",
+            )],
+        );
+        assert_eq!(data.remote_addr, Some(ip("192.0.2.1")));
+        assert_eq!(data.scheme, None);
+        assert_eq!(data.host, None);
+    }
+
+    #[async_std::test]
+    async fn requests_see_the_resolved_client() {
+        let mut app = crate::new();
+        app.with_forwarded(|forwarded| {
+            forwarded.trusted_proxies(TrustedProxies::new().trust("127.0.0.1".parse().unwrap()))
+        });
+        app.at("/").get(|req: Request| async move {
+            Ok(format!(
+                "{} {} {}",
+                req.remote_addr().unwrap(),
+                req.scheme(),
+                req.host().unwrap()
+            ))
+        });
+
+        let mut req = HttpRequest::new(Method::Get, Url::parse("http://localhost/").unwrap());
+        req.set_peer_addr(Some("127.0.0.1:5000"));
+        req.insert_header("Forwarded", "for=192.0.2.1;proto=https;host=example.com");
+        let mut res: HttpResponse = app.respond(req).await.unwrap();
+        assert_eq!(
+            res.body_string().await.unwrap(),
+            "192.0.2.1 https example.com"
+        );
+    }
+}
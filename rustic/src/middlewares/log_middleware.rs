@@ -1,28 +1,115 @@
+use std::{sync::Arc, time::Instant};
+
 use async_trait::async_trait;
+use http_types::headers;
 use kv_log_macro::{error, info, warn};
+use time::OffsetDateTime;
 
-use crate::{Middleware, Next, Request, Server};
+use crate::{
+    access_log::{AccessRecord, FileSink, LogFormat},
+    Middleware, Next, Request, Response, Server,
+};
 
-pub struct LogMiddleware {}
+/// Logs every request, by default as structured records to the global logger, a record
+/// when the request comes in and one when the response goes out.
+///
+/// With a `LogFormat`, one access log line is written per request instead. Lines go to the
+/// global logger, or to a `FileSink` when one is given, in `LogFormat::Combined` unless
+/// told otherwise.
+pub struct LogMiddleware {
+    format: Option<LogFormat>,
+    file: Option<Arc<FileSink>>,
+}
 
 impl LogMiddleware {
-    fn new() -> Self {
-        Self {}
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            format: None,
+            file: None,
+        }
     }
-}
 
-#[async_trait]
-impl Middleware for LogMiddleware {
-    async fn handle(&self, request: Request, next: Next<'_>) -> crate::Result {
+    #[must_use]
+    pub fn format(mut self, format: LogFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    #[must_use]
+    pub fn file(mut self, file: FileSink) -> Self {
+        self.file = Some(Arc::new(file));
+        self
+    }
+
+    async fn log_access(&self, request: Request, next: Next<'_>, format: LogFormat) -> Response {
+        let header = |name| {
+            request
+                .header(name)
+                .map(|values| values.last().as_str().to_owned())
+        };
+        let mut record = AccessRecord {
+            time: OffsetDateTime::now_utc(),
+            remote_addr: request.remote_addr(),
+            method: request.method().to_string(),
+            target: match request.url().query() {
+                Some(query) => format!("{}?{}", request.url().path(), query),
+                None => request.url().path().to_owned(),
+            },
+            version: request
+                .req
+                .version()
+                .map_or_else(|| "HTTP/1.1".to_owned(), |version| version.to_string()),
+            status: 0,
+            bytes: None,
+            referer: header(headers::REFERER),
+            user_agent: header(headers::USER_AGENT),
+            request_id: request.request_id().map(str::to_owned),
+            duration: Default::default(),
+        };
+
+        let start = Instant::now();
+        let response = next.run(request).await;
+        record.duration = start.elapsed();
+        record.status = response.status() as u16;
+        record.bytes = response.res.len().map(|len| len as u64);
+
+        // The access log has no room for the reason a handler failed
+        if response.status().is_server_error() {
+            if let Some(error) = response.error() {
+                error!("Internal error", {
+                    message: error.to_string(),
+                    error_type: error.type_name(),
+                    request_id: record.request_id.as_deref().unwrap_or("-"),
+                });
+            }
+        }
+
+        let line = format.format(&record);
+        match &self.file {
+            Some(file) => {
+                if let Err(e) = file.clone().write(line).await {
+                    error!("Failed to write the access log", { error: e.to_string() });
+                }
+            }
+            None => info!("{}", line),
+        }
+        response
+    }
+
+    async fn log_records(&self, request: Request, next: Next<'_>) -> Response {
         let path = request.url().path().to_owned();
         let method = request.method().to_string();
+        let request_id = request.request_id().unwrap_or("-").to_owned();
         info!("<-- Request received", {
             method: method,
             path: path,
+            request_id: request_id,
         });
-        let start = std::time::Instant::now();
+        let start = Instant::now();
         let response = next.run(request).await;
         let status = response.status();
+        let duration_ms = start.elapsed().as_secs_f64() * 1000.0;
         if status.is_server_error() {
             if let Some(error) = response.error() {
                 error!("Internal error --> Response sent", {
@@ -30,15 +117,17 @@ impl Middleware for LogMiddleware {
                     error_type: error.type_name(),
                     method: method,
                     path: path,
-                    status: format!("{} - {}", status as u16, status.canonical_reason()),
-                    duration: format!("{:?}", start.elapsed()),
+                    request_id: request_id,
+                    status: status as u16,
+                    duration_ms: duration_ms,
                 });
             } else {
                 error!("Internal error --> Response sent", {
                     method: method,
                     path: path,
-                    status: format!("{} - {}", status as u16, status.canonical_reason()),
-                    duration: format!("{:?}", start.elapsed()),
+                    request_id: request_id,
+                    status: status as u16,
+                    duration_ms: duration_ms,
                 });
             }
         } else if status.is_client_error() {
@@ -48,31 +137,55 @@ impl Middleware for LogMiddleware {
                     error_type: error.type_name(),
                     method: method,
                     path: path,
-                    status: format!("{} - {}", status as u16, status.canonical_reason()),
-                    duration: format!("{:?}", start.elapsed()),
+                    request_id: request_id,
+                    status: status as u16,
+                    duration_ms: duration_ms,
                 });
             } else {
                 warn!("Client error --> Response sent", {
                     method: method,
                     path: path,
-                    status: format!("{} - {}", status as u16, status.canonical_reason()),
-                    duration: format!("{:?}", start.elapsed()),
+                    request_id: request_id,
+                    status: status as u16,
+                    duration_ms: duration_ms,
                 });
             }
         } else {
             info!("--> Response sent", {
                 method: method,
                 path: path,
-                status: format!("{} - {}", status as u16, status.canonical_reason()),
-                duration: format!("{:?}", start.elapsed()),
+                request_id: request_id,
+                status: status as u16,
+                duration_ms: duration_ms,
             });
         }
-        Ok(response)
+        response
+    }
+}
+
+#[async_trait]
+impl Middleware for LogMiddleware {
+    async fn handle(&self, request: Request, next: Next<'_>) -> crate::Result {
+        let format = match (self.format, &self.file) {
+            (None, None) => return Ok(self.log_records(request, next).await),
+            (format, _) => format.unwrap_or(LogFormat::Combined),
+        };
+        Ok(self.log_access(request, next, format).await)
+    }
+}
+
+impl Default for LogMiddleware {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 pub trait WithLogging {
     fn with_logging(&mut self) -> &mut Self;
+
+    /// Write an access log line per request, in `LogFormat::Combined` to the global logger
+    /// unless `configure` picks another format or a `FileSink`.
+    fn with_access_log(&mut self, configure: impl Fn(LogMiddleware) -> LogMiddleware) -> &mut Self;
 }
 
 impl WithLogging for Server {
@@ -80,4 +193,60 @@ impl WithLogging for Server {
         self.with(LogMiddleware::new());
         self
     }
+
+    fn with_access_log(&mut self, configure: impl Fn(LogMiddleware) -> LogMiddleware) -> &mut Self {
+        let access_log = LogMiddleware::new().format(LogFormat::Combined);
+
+        self.with((configure)(access_log));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_types::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    use super::*;
+    use crate::{fs::test_dir::TestDir, Error, StatusCode};
+
+    #[async_std::test]
+    async fn access_lines_go_to_the_file() {
+        let dir = TestDir::new();
+        let path = dir.path().join("access.log");
+
+        let mut app = crate::new();
+        app.with_access_log(|log| {
+            log.format(LogFormat::Common)
+                .file(FileSink::open(&path).unwrap())
+        });
+        app.at("/ok").get(|_: Request| async move { Ok("hello") });
+        app.at("/fail").get(|_: Request| async move {
+            Err::<String, _>(Error::from_str(StatusCode::InternalServerError, "boom"))
+        });
+
+        for target in ["/ok?x=1", "/fail"] {
+            let url = Url::parse("http://localhost")
+                .unwrap()
+                .join(target)
+                .unwrap();
+            let mut req = HttpRequest::new(Method::Get, url);
+            req.set_peer_addr(Some("192.0.2.1:5000"));
+            let _: HttpResponse = app.respond(req).await.unwrap();
+        }
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 2, "{}", log);
+        assert!(lines[0].starts_with("192.0.2.1 - - ["), "{}", lines[0]);
+        assert!(
+            lines[0].ends_with("] \"GET /ok?x=1 HTTP/1.1\" 200 5"),
+            "{}",
+            lines[0]
+        );
+        assert!(
+            lines[1].contains("] \"GET /fail HTTP/1.1\" 500 "),
+            "{}",
+            lines[1]
+        );
+    }
 }
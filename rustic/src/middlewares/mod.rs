@@ -1,9 +1,36 @@
 mod auth_middleware;
+mod body_limit_middleware;
+mod cache_middleware;
+mod compression_middleware;
+mod conditional_middleware;
 mod cookie_middleware;
+mod cookie_policy;
 mod cors_middleware;
+mod csrf_middleware;
+mod forwarded_middleware;
 mod log_middleware;
+mod rate_limit_middleware;
+mod request_id_middleware;
+mod session_middleware;
 
 pub use auth_middleware::{AuthMiddleware, BasicAuthScheme, BearerAuthScheme, WithHttpAuth};
-pub use cookie_middleware::{CookieData, CookieMiddleware};
+pub(crate) use auth_middleware::Authenticated;
+pub use body_limit_middleware::{BodyLimitMiddleware, WithBodyLimit};
+pub(crate) use body_limit_middleware::LimitedBody;
+pub use cache_middleware::{CacheMiddleware, WithCache};
+pub use compression_middleware::{CompressionLevel, CompressionMiddleware, WithCompression};
+pub(crate) use compression_middleware::negotiate as negotiate_encoding;
+pub use conditional_middleware::{ConditionalMiddleware, WithConditional};
+pub use cookie_middleware::{CookieData, CookieMiddleware, WithCookies};
+pub use cookie_policy::{CookiePattern, CookiePolicy};
 pub use cors_middleware::{CorsMiddleware, Origin, WithCors};
+pub use csrf_middleware::{CsrfMiddleware, CsrfStorage, WithCsrf};
+pub(crate) use csrf_middleware::CsrfToken;
+pub use forwarded_middleware::{ForwardedMiddleware, WithForwarded};
+pub(crate) use forwarded_middleware::ForwardedData;
 pub use log_middleware::{LogMiddleware, WithLogging};
+pub use rate_limit_middleware::{RateLimitKey, RateLimitMiddleware, WithRateLimit};
+pub use request_id_middleware::{RequestIdFormat, RequestIdMiddleware, WithRequestId};
+pub(crate) use request_id_middleware::RequestId;
+pub use session_middleware::{SessionMiddleware, WithSessions};
+pub(crate) use session_middleware::SessionData;
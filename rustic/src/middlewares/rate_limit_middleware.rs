@@ -0,0 +1,382 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use http_types::{
+    headers::{self, HeaderName},
+    StatusCode,
+};
+use kv_log_macro::warn;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    middlewares::Authenticated,
+    rate_limit::{MemoryRateLimitStore, RateLimitAlgorithm, RateLimitDecision, RateLimitStore},
+    Middleware, Next, Request, Response, Server,
+};
+
+/// 128 bits of the digest are plenty to keep identities apart.
+const DIGEST_BYTES: usize = 16;
+
+type KeyFn = dyn Fn(&Request) -> Option<String> + Send + Sync + 'static;
+
+/// What requests are grouped by when counting them against the limit.
+///
+/// Requests without a key, e.g. without the configured header, are not limited.
+#[derive(Clone)]
+pub enum RateLimitKey {
+    /// IP address of the connected peer.
+    PeerIp,
+    /// IP address of the client, which differs from the peer behind proxies trusted by
+    /// `ForwardedMiddleware`.
+    RemoteIp,
+    /// The `Authorization` header, i.e. the authenticated identity. Only a SHA-256 digest
+    /// of it ends up in the key, which is the same in every process sharing a store.
+    ///
+    /// The header is only used once `AuthMiddleware` verified it, so add the rate limit
+    /// after the auth middleware. Requests with unverified credentials are keyed by the
+    /// peer IP instead, otherwise a client could get a fresh limit for every made up
+    /// `Authorization` value.
+    Authorization,
+    /// The value of a header such as an API key, kept as a SHA-256 digest like
+    /// `Authorization`.
+    Header(HeaderName),
+    Custom(Arc<KeyFn>),
+}
+
+impl RateLimitKey {
+    pub fn custom<F>(key: F) -> Self
+    where
+        F: Fn(&Request) -> Option<String> + Send + Sync + 'static,
+    {
+        Self::Custom(Arc::new(key))
+    }
+
+    fn extract(&self, request: &Request) -> Option<String> {
+        match self {
+            Self::PeerIp => request
+                .peer_addr()
+                .map(|peer_addr| format!("ip:{}", strip_port(peer_addr))),
+            Self::RemoteIp => request
+                .remote_addr()
+                .map(|remote_addr| format!("ip:{}", remote_addr)),
+            Self::Authorization => match request.ext::<Authenticated>() {
+                Some(_) => request
+                    .header(&headers::AUTHORIZATION)
+                    .map(|value| format!("auth:{}", digest(value.as_str()))),
+                None => Self::PeerIp.extract(request),
+            },
+            Self::Header(name) => request
+                .header(name)
+                .map(|value| format!("header:{}:{}", name, digest(value.as_str()))),
+            Self::Custom(key) => (key)(request).map(|key| format!("custom:{}", key)),
+        }
+    }
+
+    /// What can be logged of `key`, custom keys could hold anything.
+    fn loggable<'a>(&self, key: &'a str) -> &'a str {
+        match self {
+            Self::Custom(_) => "custom",
+            _ => key,
+        }
+    }
+}
+
+/// Don't keep credentials around as map keys, or write them to the logs.
+fn digest(value: &str) -> String {
+    Sha256::digest(value.as_bytes())[..DIGEST_BYTES]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub struct RateLimitMiddleware {
+    algorithm: RateLimitAlgorithm,
+    key: RateLimitKey,
+    store: Arc<dyn RateLimitStore>,
+}
+
+impl RateLimitMiddleware {
+    #[must_use]
+    pub fn new(algorithm: RateLimitAlgorithm) -> Self {
+        Self {
+            algorithm,
+            key: RateLimitKey::PeerIp,
+            store: Arc::new(MemoryRateLimitStore::new()),
+        }
+    }
+
+    #[must_use]
+    pub fn key(mut self, key: RateLimitKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Keep the limit state in `store` instead of process memory, e.g. to share limits
+    /// between instances.
+    #[must_use]
+    pub fn store(mut self, store: impl RateLimitStore) -> Self {
+        self.store = Arc::new(store);
+        self
+    }
+}
+
+#[async_trait]
+impl Middleware for RateLimitMiddleware {
+    async fn handle(&self, request: Request, next: Next<'_>) -> crate::Result {
+        let key = match self.key.extract(&request) {
+            Some(key) => key,
+            None => return Ok(next.run(request).await),
+        };
+
+        let decision = self.store.hit(&key, &self.algorithm).await?;
+
+        if !decision.allowed() {
+            warn!("Rate limit exceeded", {
+                key: self.key.loggable(&key),
+                path: request.url().path(),
+            });
+
+            let mut res = Response::new(StatusCode::TooManyRequests);
+            insert_rate_limit_headers(&mut res, &decision);
+            if let Some(retry_after) = decision.retry_after() {
+                res.insert_header(headers::RETRY_AFTER, seconds(retry_after).to_string());
+            }
+            return Ok(res);
+        }
+
+        let mut res = next.run(request).await;
+        insert_rate_limit_headers(&mut res, &decision);
+        Ok(res)
+    }
+}
+
+fn insert_rate_limit_headers(res: &mut Response, decision: &RateLimitDecision) {
+    res.insert_header("RateLimit-Limit", decision.limit().to_string());
+    res.insert_header("RateLimit-Remaining", decision.remaining().to_string());
+    res.insert_header("RateLimit-Reset", seconds(decision.reset()).to_string());
+}
+
+/// Whole seconds, rounded up so clients never retry too early.
+fn seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+/// `127.0.0.1:8080` -> `127.0.0.1`, `[::1]:8080` -> `::1`
+fn strip_port(addr: &str) -> String {
+    match addr.parse::<SocketAddr>() {
+        Ok(addr) => addr.ip().to_string(),
+        Err(_) => addr.to_owned(),
+    }
+}
+
+pub trait WithRateLimit {
+    fn with_rate_limit(
+        &mut self,
+        algorithm: RateLimitAlgorithm,
+        configure: impl Fn(RateLimitMiddleware) -> RateLimitMiddleware,
+    ) -> &mut Self;
+}
+
+impl WithRateLimit for Server {
+    fn with_rate_limit(
+        &mut self,
+        algorithm: RateLimitAlgorithm,
+        configure: impl Fn(RateLimitMiddleware) -> RateLimitMiddleware,
+    ) -> &mut Self {
+        let rate_limit = RateLimitMiddleware::new(algorithm);
+
+        self.with((configure)(rate_limit));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_types::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    use super::*;
+    use crate::WithHttpAuth;
+
+    fn request(path: &str) -> HttpRequest {
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        HttpRequest::new(Method::Get, url)
+    }
+
+    fn with_header(name: &str, value: &str) -> HttpRequest {
+        let mut req = request("/");
+        req.insert_header(name, value);
+        req
+    }
+
+    fn app(key: RateLimitKey) -> Server {
+        let mut app = crate::new();
+        app.with_rate_limit(
+            RateLimitAlgorithm::token_bucket(2, Duration::from_secs(60)),
+            |rate_limit| rate_limit.key(key.clone()),
+        );
+        app.at("/").get(|_: Request| async move { Ok("ok") });
+        app
+    }
+
+    fn header(res: &HttpResponse, name: &str) -> Option<String> {
+        res.header(name).map(|value| value.as_str().to_owned())
+    }
+
+    fn extract(key: &RateLimitKey, req: HttpRequest) -> Option<String> {
+        key.extract(&Request::new(req, Vec::new()))
+    }
+
+    /// As if `AuthMiddleware` had verified the credentials of `req`.
+    fn extract_authenticated(key: &RateLimitKey, req: HttpRequest) -> Option<String> {
+        let mut req = Request::new(req, Vec::new());
+        req.set_ext(Authenticated);
+        key.extract(&req)
+    }
+
+    #[async_std::test]
+    async fn requests_over_the_limit_are_rejected() {
+        let app = app(RateLimitKey::Header("X-Api-Key".into()));
+
+        let res: HttpResponse = app.respond(with_header("X-Api-Key", "a")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(header(&res, "RateLimit-Limit").as_deref(), Some("2"));
+        assert_eq!(header(&res, "RateLimit-Remaining").as_deref(), Some("1"));
+        assert_eq!(header(&res, "Retry-After"), None);
+
+        let res: HttpResponse = app.respond(with_header("X-Api-Key", "a")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(header(&res, "RateLimit-Remaining").as_deref(), Some("0"));
+
+        let res: HttpResponse = app.respond(with_header("X-Api-Key", "a")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TooManyRequests);
+        assert_eq!(header(&res, "RateLimit-Remaining").as_deref(), Some("0"));
+        let retry_after: u64 = header(&res, "Retry-After").unwrap().parse().unwrap();
+        assert!((29..=31).contains(&retry_after), "{}", retry_after);
+
+        // Other keys have their own limit
+        let res: HttpResponse = app.respond(with_header("X-Api-Key", "b")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+    }
+
+    #[async_std::test]
+    async fn requests_without_a_key_are_not_limited() {
+        let app = app(RateLimitKey::Header("X-Api-Key".into()));
+
+        for _ in 0..5 {
+            let res: HttpResponse = app.respond(request("/")).await.unwrap();
+            assert_eq!(res.status(), StatusCode::Ok);
+            assert_eq!(header(&res, "RateLimit-Limit"), None);
+        }
+    }
+
+    #[test]
+    fn authorization_keys_are_stable_digests() {
+        let key = extract_authenticated(
+            &RateLimitKey::Authorization,
+            with_header("Authorization", "Bearer secret"),
+        );
+        // The first 16 bytes of SHA-256("Bearer secret")
+        assert_eq!(
+            key.as_deref(),
+            Some("auth:bffde20413347b7a00e1363de3f97ca6")
+        );
+
+        let other = extract_authenticated(
+            &RateLimitKey::Authorization,
+            with_header("Authorization", "Bearer other"),
+        );
+        assert_ne!(other, key);
+    }
+
+    #[test]
+    fn unverified_authorization_falls_back_to_the_peer_ip() {
+        let mut req = with_header("Authorization", "Bearer made-up");
+        req.set_peer_addr(Some("192.0.2.1:5000"));
+        assert_eq!(
+            extract(&RateLimitKey::Authorization, req).as_deref(),
+            Some("ip:192.0.2.1")
+        );
+        assert_eq!(extract(&RateLimitKey::Authorization, request("/")), None);
+    }
+
+    #[async_std::test]
+    async fn made_up_credentials_share_the_peer_limit() {
+        let mut app = crate::new();
+        app.with_rate_limit(
+            RateLimitAlgorithm::token_bucket(2, Duration::from_secs(60)),
+            |rate_limit| rate_limit.key(RateLimitKey::Authorization),
+        );
+        app.with_token_auth(|token| token == "secret");
+        app.at("/").get(|_: Request| async move { Ok("ok") });
+
+        let mut statuses = Vec::new();
+        for n in 0..3 {
+            statuses.push(bearer(&app, &format!("guess-{}", n)).await);
+        }
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::Unauthorized,
+                StatusCode::Unauthorized,
+                StatusCode::TooManyRequests
+            ]
+        );
+    }
+
+    async fn bearer(app: &Server, token: &str) -> StatusCode {
+        // `BearerAuthScheme` takes base64 tokens
+        let mut req = with_header(
+            "Authorization",
+            &format!("Bearer {}", base64::encode(token)),
+        );
+        req.set_peer_addr(Some("192.0.2.1:5000"));
+        let res: HttpResponse = app.respond(req).await.unwrap();
+        res.status()
+    }
+
+    #[async_std::test]
+    async fn verified_credentials_get_their_own_limit() {
+        let mut app = crate::new();
+        app.with_token_auth(|token| token == "secret" || token == "other");
+        app.with_rate_limit(
+            RateLimitAlgorithm::token_bucket(1, Duration::from_secs(60)),
+            |rate_limit| rate_limit.key(RateLimitKey::Authorization),
+        );
+        app.at("/").get(|_: Request| async move { Ok("ok") });
+
+        assert_eq!(bearer(&app, "secret").await, StatusCode::Ok);
+        assert_eq!(bearer(&app, "secret").await, StatusCode::TooManyRequests);
+        assert_eq!(bearer(&app, "other").await, StatusCode::Ok);
+    }
+
+    #[test]
+    fn keys_are_namespaced() {
+        let mut req = request("/");
+        req.set_peer_addr(Some("[::1]:8080"));
+        assert_eq!(
+            extract(&RateLimitKey::PeerIp, req).as_deref(),
+            Some("ip:::1")
+        );
+        assert_eq!(
+            extract(
+                &RateLimitKey::Header("X-Api-Key".into()),
+                with_header("X-Api-Key", "a")
+            )
+            .as_deref(),
+            // The first 16 bytes of SHA-256("a")
+            Some("header:x-api-key:ca978112ca1bbdcafac231b39a23dc4d")
+        );
+        let custom = RateLimitKey::custom(|req: &Request| Some(req.url().path().to_owned()));
+        assert_eq!(
+            extract(&custom, request("/a")).as_deref(),
+            Some("custom:/a")
+        );
+    }
+
+    #[test]
+    fn seconds_round_up() {
+        assert_eq!(seconds(Duration::ZERO), 0);
+        assert_eq!(seconds(Duration::from_secs(2)), 2);
+        assert_eq!(seconds(Duration::from_millis(2001)), 3);
+    }
+}
@@ -0,0 +1,293 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use http_types::headers::HeaderName;
+use rand::RngCore;
+
+use crate::{Middleware, Next, Request, Server};
+
+pub(crate) const DEFAULT_HEADER_NAME: &str = "X-Request-Id";
+/// Longer incoming IDs are replaced rather than copied into every log record.
+pub(crate) const MAX_INCOMING_LEN: usize = 128;
+
+/// The ID of the current request, read by `Request::request_id` and `LogMiddleware`.
+#[derive(Clone, Debug)]
+pub(crate) struct RequestId(pub(crate) String);
+
+/// The kind of ID generated for requests that don't come with one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestIdFormat {
+    /// `f47ac10b-58cc-4372-a567-0e02b2c3d479`
+    UuidV4,
+    /// `01ARZ3NDEKTSV4RRFFQ69G5FAV`, sorting by creation time.
+    Ulid,
+}
+
+/// Gives every request an ID to correlate logs across services: the one sent by the
+/// client or an upstream service in the request ID header, or a new one. The ID is sent
+/// back in the same header.
+///
+/// Add it before `LogMiddleware` for the ID to show up in the logs.
+pub struct RequestIdMiddleware {
+    header_name: HeaderName,
+    format: RequestIdFormat,
+    trust_incoming: bool,
+}
+
+impl RequestIdMiddleware {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            header_name: DEFAULT_HEADER_NAME.into(),
+            format: RequestIdFormat::UuidV4,
+            trust_incoming: true,
+        }
+    }
+
+    #[must_use]
+    pub fn header_name(mut self, header_name: impl Into<HeaderName>) -> Self {
+        self.header_name = header_name.into();
+        self
+    }
+
+    #[must_use]
+    pub fn format(mut self, format: RequestIdFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Whether to keep the ID a request comes with, on by default. Turn it off when the
+    /// server is reached directly by clients that could pick IDs to confuse the logs.
+    #[must_use]
+    pub fn trust_incoming(mut self, trust_incoming: bool) -> Self {
+        self.trust_incoming = trust_incoming;
+        self
+    }
+
+    fn incoming(&self, request: &Request) -> Option<String> {
+        if !self.trust_incoming {
+            return None;
+        }
+
+        let id = request.header(&self.header_name)?.last().as_str().trim();
+        let valid = !id.is_empty()
+            && id.len() <= MAX_INCOMING_LEN
+            && id
+                .chars()
+                .all(|c| c.is_ascii_graphic() && c != '"' && c != '\\');
+        valid.then(|| id.to_owned())
+    }
+
+    fn generate(&self) -> String {
+        match self.format {
+            RequestIdFormat::UuidV4 => uuid_v4(),
+            RequestIdFormat::Ulid => ulid(),
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for RequestIdMiddleware {
+    async fn handle(&self, mut request: Request, next: Next<'_>) -> crate::Result {
+        let id = self.incoming(&request).unwrap_or_else(|| self.generate());
+        request.set_ext(RequestId(id.clone()));
+
+        let mut res = next.run(request).await;
+        res.insert_header(&self.header_name, id.as_str());
+        Ok(res)
+    }
+}
+
+impl Default for RequestIdMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn uuid_v4() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// 48 bits of milliseconds since the epoch and 80 random bits, in Crockford's base32.
+fn ulid() -> String {
+    const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis());
+    let mut random = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut random[6..]);
+    let value = (millis & 0xffff_ffff_ffff) << 80 | u128::from_be_bytes(random);
+
+    (0..26)
+        .rev()
+        .map(|digit| ALPHABET[((value >> (digit * 5)) & 0x1f) as usize] as char)
+        .collect()
+}
+
+pub trait WithRequestId {
+    fn with_request_id(
+        &mut self,
+        configure: impl Fn(RequestIdMiddleware) -> RequestIdMiddleware,
+    ) -> &mut Self;
+}
+
+impl WithRequestId for Server {
+    fn with_request_id(
+        &mut self,
+        configure: impl Fn(RequestIdMiddleware) -> RequestIdMiddleware,
+    ) -> &mut Self {
+        let request_id = RequestIdMiddleware::new();
+
+        self.with((configure)(request_id));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_types::{Method, Request as HttpRequest, Response as HttpResponse, StatusCode, Url};
+
+    use super::*;
+
+    fn app(configure: impl Fn(RequestIdMiddleware) -> RequestIdMiddleware) -> Server {
+        let mut app = crate::new();
+        app.with_request_id(configure);
+        app.at("/")
+            .get(|req: Request| async move { Ok(req.request_id().unwrap_or("none").to_owned()) });
+        app.at("/fail").get(|_: Request| async move {
+            Err::<String, _>(crate::Error::from_str(StatusCode::ImATeapot, "no"))
+        });
+        app
+    }
+
+    /// The ID the endpoint saw, and the one sent back in `header`.
+    async fn ids(app: &Server, header: &str, incoming: Option<&str>) -> (String, String) {
+        let mut req = HttpRequest::new(Method::Get, Url::parse("http://localhost/").unwrap());
+        if let Some(incoming) = incoming {
+            req.insert_header(header, incoming);
+        }
+        let mut res: HttpResponse = app.respond(req).await.unwrap();
+        let sent = res.header(header).unwrap().as_str().to_owned();
+        (res.body_string().await.unwrap(), sent)
+    }
+
+    fn is_uuid_v4(id: &str) -> bool {
+        let groups: Vec<&str> = id.split('-').collect();
+        groups.iter().map(|group| group.len()).eq([8, 4, 4, 4, 12])
+            && id
+                .chars()
+                .all(|c| c == '-' || c.is_ascii_digit() || ('a'..='f').contains(&c))
+            && groups[2].starts_with('4')
+            && groups[3].starts_with(['8', '9', 'a', 'b'])
+    }
+
+    #[test]
+    fn uuids_are_random_version_4() {
+        let first = uuid_v4();
+        assert!(is_uuid_v4(&first), "{}", first);
+        assert_ne!(first, uuid_v4());
+    }
+
+    #[test]
+    fn ulids_are_crockford_base32_and_sort_by_time() {
+        let first = ulid();
+        assert_eq!(first.len(), 26);
+        assert!(first
+            .chars()
+            .all(|c| c.is_ascii_digit() || (c.is_ascii_uppercase() && !"ILOU".contains(c))));
+        // 48 bits of time leave the first character at 7 or below
+        assert!(first.as_bytes()[0] <= b'7');
+
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let second = ulid();
+        assert!(second[..10] > first[..10], "{} {}", first, second);
+    }
+
+    #[async_std::test]
+    async fn requests_without_an_id_get_a_new_one() {
+        let app = app(|request_id| request_id);
+        let (seen, sent) = ids(&app, DEFAULT_HEADER_NAME, None).await;
+        assert!(is_uuid_v4(&seen), "{}", seen);
+        assert_eq!(seen, sent);
+
+        let (other, _) = ids(&app, DEFAULT_HEADER_NAME, None).await;
+        assert_ne!(seen, other);
+    }
+
+    #[async_std::test]
+    async fn incoming_ids_are_echoed() {
+        let app = app(|request_id| request_id);
+        let (seen, sent) = ids(&app, DEFAULT_HEADER_NAME, Some(" upstream-42 ")).await;
+        assert_eq!(seen, "upstream-42");
+        assert_eq!(sent, "upstream-42");
+    }
+
+    #[async_std::test]
+    async fn invalid_incoming_ids_are_replaced() {
+        let app = app(|request_id| request_id);
+        let too_long = "a".repeat(MAX_INCOMING_LEN + 1);
+        for incoming in [
+            "",
+            "two words",
+            "quo\"te",
+            "back\\slash",
+            "tab\tbed",
+            &too_long,
+        ] {
+            let (seen, sent) = ids(&app, DEFAULT_HEADER_NAME, Some(incoming)).await;
+            assert!(is_uuid_v4(&seen), "{:?} kept as {:?}", incoming, seen);
+            assert_eq!(seen, sent);
+        }
+
+        let longest = "a".repeat(MAX_INCOMING_LEN);
+        let (seen, _) = ids(&app, DEFAULT_HEADER_NAME, Some(&longest)).await;
+        assert_eq!(seen, longest);
+    }
+
+    #[async_std::test]
+    async fn untrusted_incoming_ids_are_replaced() {
+        let app = app(|request_id| request_id.trust_incoming(false));
+        let (seen, sent) = ids(&app, DEFAULT_HEADER_NAME, Some("upstream-42")).await;
+        assert!(is_uuid_v4(&seen), "{}", seen);
+        assert_eq!(seen, sent);
+    }
+
+    #[async_std::test]
+    async fn header_name_and_format_are_configurable() {
+        let app = app(|request_id| {
+            request_id
+                .header_name("X-Trace")
+                .format(RequestIdFormat::Ulid)
+        });
+        let (seen, sent) = ids(&app, "X-Trace", None).await;
+        assert_eq!(seen.len(), 26);
+        assert_eq!(seen, sent);
+
+        let (seen, _) = ids(&app, "X-Trace", Some("trace-1")).await;
+        assert_eq!(seen, "trace-1");
+    }
+
+    #[async_std::test]
+    async fn error_responses_carry_the_id() {
+        let app = app(|request_id| request_id);
+        let mut req = HttpRequest::new(Method::Get, Url::parse("http://localhost/fail").unwrap());
+        req.insert_header(DEFAULT_HEADER_NAME, "upstream-42");
+        let res: HttpResponse = app.respond(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::ImATeapot);
+        assert_eq!(res.header(DEFAULT_HEADER_NAME).unwrap(), "upstream-42");
+    }
+}
@@ -0,0 +1,333 @@
+use std::{
+    sync::{Arc, PoisonError, RwLock},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use http_types::{cookies::SameSite, Cookie};
+
+use crate::{
+    sessions::{Session, SessionStore},
+    Middleware, Next, Request, Server,
+};
+
+pub struct SessionMiddleware<Store: SessionStore> {
+    store: Store,
+    cookie_name: String,
+    cookie_path: String,
+    cookie_domain: Option<String>,
+    same_site: SameSite,
+    secure: bool,
+    max_age: Option<Duration>,
+    idle_timeout: Option<Duration>,
+}
+
+pub(crate) const DEFAULT_COOKIE_NAME: &str = "rustic.sid";
+pub(crate) const DEFAULT_COOKIE_PATH: &str = "/";
+
+impl<Store: SessionStore> SessionMiddleware<Store> {
+    #[must_use]
+    pub fn new(store: Store) -> Self {
+        Self {
+            store,
+            cookie_name: DEFAULT_COOKIE_NAME.to_owned(),
+            cookie_path: DEFAULT_COOKIE_PATH.to_owned(),
+            cookie_domain: None,
+            same_site: SameSite::Lax,
+            secure: false,
+            max_age: None,
+            idle_timeout: None,
+        }
+    }
+
+    #[must_use]
+    pub fn cookie_name(mut self, cookie_name: impl Into<String>) -> Self {
+        self.cookie_name = cookie_name.into();
+        self
+    }
+
+    #[must_use]
+    pub fn cookie_path(mut self, cookie_path: impl Into<String>) -> Self {
+        self.cookie_path = cookie_path.into();
+        self
+    }
+
+    #[must_use]
+    pub fn cookie_domain(mut self, cookie_domain: impl Into<String>) -> Self {
+        self.cookie_domain = Some(cookie_domain.into());
+        self
+    }
+
+    #[must_use]
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    #[must_use]
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Absolute lifetime of a session, counted from its creation.
+    #[must_use]
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Expire sessions that have not been used for `idle_timeout`.
+    #[must_use]
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    fn build_cookie(&self, value: String, session: &Session) -> Cookie<'static> {
+        let mut cookie = Cookie::new(self.cookie_name.clone(), value);
+        cookie.set_path(self.cookie_path.clone());
+        cookie.set_http_only(true);
+        cookie.set_same_site(self.same_site);
+        cookie.set_secure(self.secure);
+
+        if let Some(domain) = &self.cookie_domain {
+            cookie.set_domain(domain.clone());
+        }
+
+        if let Some(expires_in) = session.expires_in() {
+            cookie.set_max_age(time::Duration::seconds(expires_in.as_secs() as i64));
+        }
+
+        cookie
+    }
+
+    fn build_removal_cookie(&self) -> Cookie<'static> {
+        let mut cookie = Cookie::named(self.cookie_name.clone());
+        cookie.set_path(self.cookie_path.clone());
+
+        if let Some(domain) = &self.cookie_domain {
+            cookie.set_domain(domain.clone());
+        }
+
+        cookie
+    }
+
+    async fn load_session(&self, cookie_value: Option<&str>) -> crate::Result<Option<Session>> {
+        let session = match cookie_value {
+            Some(cookie_value) => self.store.load_session(cookie_value).await?,
+            None => None,
+        };
+
+        match session {
+            Some(session) if session.is_expired() => {
+                self.store.destroy_session(session.id()).await?;
+                Ok(None)
+            }
+            session => Ok(session),
+        }
+    }
+}
+
+#[async_trait]
+impl<Store: SessionStore> Middleware for SessionMiddleware<Store> {
+    async fn handle(&self, mut request: Request, next: Next<'_>) -> crate::Result {
+        let cookie_value = request
+            .cookie(&self.cookie_name)
+            .map(|cookie| cookie.value().to_owned());
+
+        let loaded = self.load_session(cookie_value.as_deref()).await?;
+        let loaded_id = loaded.as_ref().map(|session| session.id().to_owned());
+
+        let mut session = loaded.unwrap_or_default();
+        session.refresh_expiry(self.max_age, self.idle_timeout);
+
+        let content = Arc::new(RwLock::new(session));
+        request.set_ext(SessionData {
+            content: content.clone(),
+        });
+
+        let mut res = next.run(request).await;
+
+        // Still readable if a handler panicked while updating it
+        let session = content
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+
+        if session.is_destroyed() {
+            if let Some(id) = &loaded_id {
+                self.store.destroy_session(id).await?;
+            }
+            if cookie_value.is_some() {
+                res.remove_cookie(self.build_removal_cookie());
+            }
+            return Ok(res);
+        }
+
+        // The session was regenerated, the old id must not stay usable
+        if let Some(id) = loaded_id.as_ref().filter(|id| *id != session.id()) {
+            self.store.destroy_session(id).await?;
+        }
+
+        let touched = loaded_id.is_some() && self.idle_timeout.is_some();
+        if session.data_changed() || touched {
+            let value = self.store.store_session(&session).await?;
+            res.insert_cookie(self.build_cookie(value, &session));
+        } else if cookie_value.is_some() && loaded_id.is_none() {
+            // Unknown, tampered or expired session cookie
+            res.remove_cookie(self.build_removal_cookie());
+        }
+
+        Ok(res)
+    }
+}
+
+pub(crate) struct SessionData {
+    pub(crate) content: Arc<RwLock<Session>>,
+}
+
+pub trait WithSessions {
+    fn with_sessions<Store: SessionStore>(
+        &mut self,
+        store: Store,
+        configure: impl Fn(SessionMiddleware<Store>) -> SessionMiddleware<Store>,
+    ) -> &mut Self;
+}
+
+impl WithSessions for Server {
+    fn with_sessions<Store: SessionStore>(
+        &mut self,
+        store: Store,
+        configure: impl Fn(SessionMiddleware<Store>) -> SessionMiddleware<Store>,
+    ) -> &mut Self {
+        let sessions = SessionMiddleware::new(store);
+
+        self.with((configure)(sessions));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_types::{headers, Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    use super::*;
+    use crate::{CookieStore, MemoryStore};
+
+    fn app<Store: SessionStore>(store: Store) -> Server {
+        let mut app = crate::new();
+        app.with_sessions(store, |sessions| sessions);
+        app.at("/visit").get(|mut req: Request| async move {
+            let visits: usize = req.session().get("visits").unwrap_or_default();
+            req.session_mut().insert("visits", visits + 1)?;
+            Ok((visits + 1).to_string())
+        });
+        app.at("/peek")
+            .get(|req: Request| async move { Ok(req.session().len().to_string()) });
+        app.at("/login").get(|mut req: Request| async move {
+            let mut session = req.session_mut();
+            session.regenerate();
+            session.insert("user", "admin")?;
+            Ok("")
+        });
+        app.at("/logout").get(|mut req: Request| async move {
+            req.session_mut().destroy();
+            Ok("")
+        });
+        app
+    }
+
+    async fn get(app: &Server, path: &str, cookie: Option<&str>) -> (String, Option<String>) {
+        let url = Url::parse("http://localhost").unwrap().join(path).unwrap();
+        let mut req = HttpRequest::new(Method::Get, url);
+        if let Some(cookie) = cookie {
+            req.insert_header(
+                headers::COOKIE,
+                format!("{}={}", DEFAULT_COOKIE_NAME, cookie),
+            );
+        }
+        let mut res: HttpResponse = app.respond(req).await.unwrap();
+        let set_cookie = res
+            .header(headers::SET_COOKIE)
+            .map(|value| value.as_str().to_owned());
+        (res.body_string().await.unwrap(), set_cookie)
+    }
+
+    fn cookie_value(set_cookie: &str) -> String {
+        Cookie::parse(set_cookie.to_owned())
+            .unwrap()
+            .value()
+            .to_owned()
+    }
+
+    #[async_std::test]
+    async fn changed_session_is_stored_and_loaded_back() {
+        let app = app(MemoryStore::new());
+
+        let (body, set_cookie) = get(&app, "/visit", None).await;
+        assert_eq!(body, "1");
+        let cookie = cookie_value(&set_cookie.unwrap());
+
+        let (body, _) = get(&app, "/visit", Some(&cookie)).await;
+        assert_eq!(body, "2");
+    }
+
+    #[async_std::test]
+    async fn untouched_session_sets_no_cookie() {
+        let app = app(MemoryStore::new());
+        let (body, set_cookie) = get(&app, "/peek", None).await;
+        assert_eq!(body, "0");
+        assert_eq!(set_cookie, None);
+    }
+
+    #[async_std::test]
+    async fn regenerating_invalidates_the_old_id() {
+        let store = MemoryStore::new();
+        let app = app(store.clone());
+
+        let (_, set_cookie) = get(&app, "/visit", None).await;
+        let old = cookie_value(&set_cookie.unwrap());
+        let (_, set_cookie) = get(&app, "/login", Some(&old)).await;
+        let new = cookie_value(&set_cookie.unwrap());
+
+        assert_ne!(old, new);
+        assert_eq!(store.count().await, 1);
+        // The old cookie now starts an empty session and gets removed
+        let (body, set_cookie) = get(&app, "/peek", Some(&old)).await;
+        assert_eq!(body, "0");
+        assert!(set_cookie.unwrap().contains("Max-Age=0"));
+        let (body, _) = get(&app, "/peek", Some(&new)).await;
+        assert_eq!(body, "2");
+    }
+
+    #[async_std::test]
+    async fn destroyed_session_is_removed() {
+        let store = MemoryStore::new();
+        let app = app(store.clone());
+
+        let (_, set_cookie) = get(&app, "/visit", None).await;
+        let cookie = cookie_value(&set_cookie.unwrap());
+        let (_, set_cookie) = get(&app, "/logout", Some(&cookie)).await;
+
+        assert!(set_cookie.unwrap().contains("Max-Age=0"));
+        assert_eq!(store.count().await, 0);
+    }
+
+    #[async_std::test]
+    async fn tampered_cookie_store_session_is_dropped() {
+        let app = app(CookieStore::new(&[7; 32]));
+
+        let (_, set_cookie) = get(&app, "/visit", None).await;
+        let cookie = cookie_value(&set_cookie.unwrap());
+        let (body, _) = get(&app, "/visit", Some(&cookie)).await;
+        assert_eq!(body, "2");
+
+        let mut tampered = cookie.into_bytes();
+        let last = tampered.len() - 1;
+        tampered[last] = if tampered[last] == b'A' { b'B' } else { b'A' };
+        let tampered = String::from_utf8(tampered).unwrap();
+        let (body, _) = get(&app, "/visit", Some(&tampered)).await;
+        assert_eq!(body, "1");
+    }
+}
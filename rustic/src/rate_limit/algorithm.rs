@@ -0,0 +1,318 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RateLimitAlgorithm {
+    /// Allows bursts of up to `capacity` requests, refilling the whole bucket evenly over
+    /// `period`.
+    TokenBucket { capacity: u64, period: Duration },
+    /// Allows `limit` requests in any `window`, weighting the previous fixed window by how
+    /// much of it still overlaps.
+    SlidingWindow { limit: u64, window: Duration },
+}
+
+/// Per-key state of a `RateLimitAlgorithm`, as kept by a `RateLimitStore`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RateLimitState {
+    TokenBucket {
+        tokens: f64,
+        updated_at: u64,
+    },
+    SlidingWindow {
+        window_start: u64,
+        current: u64,
+        previous: u64,
+    },
+}
+
+/// Outcome of a single hit against a rate limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimitDecision {
+    pub(crate) allowed: bool,
+    pub(crate) limit: u64,
+    pub(crate) remaining: u64,
+    pub(crate) reset: Duration,
+    pub(crate) retry_after: Option<Duration>,
+}
+
+impl RateLimitDecision {
+    #[must_use]
+    pub fn allowed(&self) -> bool {
+        self.allowed
+    }
+
+    #[must_use]
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    #[must_use]
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    /// Time until the quota is fully available again.
+    #[must_use]
+    pub fn reset(&self) -> Duration {
+        self.reset
+    }
+
+    /// Time until the next request would be allowed, set for rejected hits.
+    #[must_use]
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+}
+
+impl RateLimitAlgorithm {
+    #[must_use]
+    pub fn token_bucket(capacity: u64, period: Duration) -> Self {
+        Self::TokenBucket { capacity, period }
+    }
+
+    #[must_use]
+    pub fn sliding_window(limit: u64, window: Duration) -> Self {
+        Self::SlidingWindow { limit, window }
+    }
+
+    #[must_use]
+    pub fn limit(&self) -> u64 {
+        match self {
+            Self::TokenBucket { capacity, .. } => *capacity,
+            Self::SlidingWindow { limit, .. } => *limit,
+        }
+    }
+
+    /// How long a key's state matters after its last update.
+    #[must_use]
+    pub fn retention(&self) -> Duration {
+        match self {
+            Self::TokenBucket { period, .. } => *period,
+            Self::SlidingWindow { window, .. } => *window * 2,
+        }
+    }
+
+    /// Record one hit at the current time on top of `state`, which is `None` for keys seen
+    /// for the first time or with state of another algorithm.
+    #[must_use]
+    pub fn apply(&self, state: Option<RateLimitState>) -> (RateLimitState, RateLimitDecision) {
+        self.apply_at(state, unix_millis())
+    }
+
+    fn apply_at(
+        &self,
+        state: Option<RateLimitState>,
+        now: u64,
+    ) -> (RateLimitState, RateLimitDecision) {
+        match *self {
+            Self::TokenBucket { capacity, period } => token_bucket(capacity, period, state, now),
+            Self::SlidingWindow { limit, window } => sliding_window(limit, window, state, now),
+        }
+    }
+}
+
+fn token_bucket(
+    capacity: u64,
+    period: Duration,
+    state: Option<RateLimitState>,
+    now: u64,
+) -> (RateLimitState, RateLimitDecision) {
+    let capacity_f = capacity as f64;
+    // Tokens refilled per millisecond
+    let rate = capacity_f / (period.as_millis().max(1) as f64);
+
+    let tokens = match state {
+        Some(RateLimitState::TokenBucket { tokens, updated_at }) => {
+            let elapsed = now.saturating_sub(updated_at) as f64;
+            (tokens + elapsed * rate).min(capacity_f)
+        }
+        _ => capacity_f,
+    };
+
+    let allowed = tokens >= 1.0;
+    let tokens = if allowed { tokens - 1.0 } else { tokens };
+
+    let retry_after = (!allowed).then(|| millis((1.0 - tokens) / rate));
+    let decision = RateLimitDecision {
+        allowed,
+        limit: capacity,
+        remaining: tokens.floor() as u64,
+        reset: millis((capacity_f - tokens) / rate),
+        retry_after,
+    };
+
+    (
+        RateLimitState::TokenBucket {
+            tokens,
+            updated_at: now,
+        },
+        decision,
+    )
+}
+
+fn sliding_window(
+    limit: u64,
+    window: Duration,
+    state: Option<RateLimitState>,
+    now: u64,
+) -> (RateLimitState, RateLimitDecision) {
+    let window_ms = (window.as_millis() as u64).max(1);
+    let window_start = now - now % window_ms;
+
+    let (mut current, previous) = match state {
+        Some(RateLimitState::SlidingWindow {
+            window_start: start,
+            current,
+            previous,
+        }) => {
+            if start == window_start {
+                (current, previous)
+            } else if start + window_ms == window_start {
+                (0, current)
+            } else {
+                (0, 0)
+            }
+        }
+        _ => (0, 0),
+    };
+
+    let elapsed = now - window_start;
+    let until_window_end = window_ms - elapsed;
+    let previous_weight = until_window_end as f64 / window_ms as f64;
+    let estimated = previous as f64 * previous_weight + current as f64;
+
+    let allowed = estimated + 1.0 <= limit as f64;
+    if allowed {
+        current += 1;
+    }
+
+    let estimated = previous as f64 * previous_weight + current as f64;
+    let retry_after = (!allowed).then(|| {
+        if current >= limit || previous == 0 {
+            Duration::from_millis(until_window_end)
+        } else {
+            // Wait until enough of the previous window has slid out
+            let excess = estimated + 1.0 - limit as f64;
+            millis(excess / previous as f64 * window_ms as f64)
+        }
+    });
+
+    let decision = RateLimitDecision {
+        allowed,
+        limit,
+        remaining: (limit as f64 - estimated).max(0.0).floor() as u64,
+        reset: Duration::from_millis(until_window_end),
+        retry_after,
+    };
+
+    (
+        RateLimitState::SlidingWindow {
+            window_start,
+            current,
+            previous,
+        },
+        decision,
+    )
+}
+
+fn millis(ms: f64) -> Duration {
+    Duration::from_millis(ms.max(0.0).ceil() as u64)
+}
+
+pub(crate) fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hits `algorithm` at each of `times`, carrying the state along.
+    fn hits(algorithm: RateLimitAlgorithm, times: &[u64]) -> Vec<RateLimitDecision> {
+        let mut state = None;
+        times
+            .iter()
+            .map(|&now| {
+                let (next, decision) = algorithm.apply_at(state, now);
+                state = Some(next);
+                decision
+            })
+            .collect()
+    }
+
+    #[test]
+    fn token_bucket_allows_bursts_and_refills() {
+        // One token per 1024ms, exact in floating point
+        let algorithm = RateLimitAlgorithm::token_bucket(2, Duration::from_millis(2048));
+        let decisions = hits(algorithm, &[0, 0, 0, 1024, 1024]);
+
+        assert!(decisions[0].allowed());
+        assert_eq!(decisions[0].remaining(), 1);
+        assert_eq!(decisions[0].reset(), Duration::from_millis(1024));
+        assert!(decisions[1].allowed());
+        assert_eq!(decisions[1].remaining(), 0);
+        assert_eq!(decisions[1].retry_after(), None);
+
+        assert!(!decisions[2].allowed());
+        assert_eq!(
+            decisions[2].retry_after(),
+            Some(Duration::from_millis(1024))
+        );
+
+        assert!(decisions[3].allowed());
+        assert!(!decisions[4].allowed());
+    }
+
+    #[test]
+    fn token_bucket_refills_up_to_capacity() {
+        let algorithm = RateLimitAlgorithm::token_bucket(2, Duration::from_millis(2048));
+        let decisions = hits(algorithm, &[0, 0, 1_000_000]);
+
+        assert!(decisions[2].allowed());
+        assert_eq!(decisions[2].remaining(), 1);
+    }
+
+    #[test]
+    fn sliding_window_weights_the_previous_window() {
+        let algorithm = RateLimitAlgorithm::sliding_window(2, Duration::from_millis(1000));
+        let decisions = hits(algorithm, &[10_000, 10_250, 10_500, 11_500, 11_500]);
+
+        assert!(decisions[0].allowed());
+        assert_eq!(decisions[0].remaining(), 1);
+        assert_eq!(decisions[0].reset(), Duration::from_millis(1000));
+        assert!(decisions[1].allowed());
+
+        assert!(!decisions[2].allowed());
+        assert_eq!(decisions[2].retry_after(), Some(Duration::from_millis(500)));
+
+        // Half of the previous window's 2 hits still count
+        assert!(decisions[3].allowed());
+        assert_eq!(decisions[3].remaining(), 0);
+        assert!(!decisions[4].allowed());
+        assert_eq!(decisions[4].retry_after(), Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn sliding_window_forgets_older_windows() {
+        let algorithm = RateLimitAlgorithm::sliding_window(2, Duration::from_millis(1000));
+        let decisions = hits(algorithm, &[10_000, 10_000, 12_000]);
+
+        assert!(decisions[2].allowed());
+        assert_eq!(decisions[2].remaining(), 1);
+    }
+
+    #[test]
+    fn state_of_another_algorithm_is_ignored() {
+        let (state, _) =
+            RateLimitAlgorithm::sliding_window(1, Duration::from_secs(1)).apply_at(None, 0);
+        let (_, decision) =
+            RateLimitAlgorithm::token_bucket(3, Duration::from_secs(1)).apply_at(Some(state), 0);
+
+        assert!(decision.allowed());
+        assert_eq!(decision.remaining(), 2);
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+
+use super::{
+    algorithm::unix_millis, RateLimitAlgorithm, RateLimitDecision, RateLimitState, RateLimitStore,
+};
+
+/// Keeps rate limit state in process memory, so limits apply per process.
+///
+/// State of keys that are no longer limited is dropped every so often while hits come in.
+#[derive(Clone, Default)]
+pub struct MemoryRateLimitStore {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<String, Entry>,
+    last_cleanup: u64,
+}
+
+struct Entry {
+    state: RateLimitState,
+    last_hit: u64,
+}
+
+impl Inner {
+    fn cleanup(&mut self, max_idle: Duration, now: u64) {
+        let cutoff = now.saturating_sub(max_idle.as_millis() as u64);
+        self.entries.retain(|_, entry| entry.last_hit >= cutoff);
+        self.last_cleanup = now;
+    }
+}
+
+impl MemoryRateLimitStore {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drop the state of keys that have not been hit within `max_idle`.
+    pub fn cleanup(&self, max_idle: Duration) {
+        self.inner.lock().unwrap().cleanup(max_idle, unix_millis());
+    }
+
+    #[must_use]
+    pub fn count(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryRateLimitStore {
+    async fn hit(
+        &self,
+        key: &str,
+        algorithm: &RateLimitAlgorithm,
+    ) -> crate::Result<RateLimitDecision> {
+        let now = unix_millis();
+        let retention = algorithm.retention();
+        let mut inner = self.inner.lock().unwrap();
+
+        if now.saturating_sub(inner.last_cleanup) > retention.as_millis() as u64 {
+            inner.cleanup(retention, now);
+        }
+
+        let state = inner.entries.get(key).map(|entry| entry.state);
+        let (state, decision) = algorithm.apply(state);
+        inner.entries.insert(
+            key.to_owned(),
+            Entry {
+                state,
+                last_hit: now,
+            },
+        );
+
+        Ok(decision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn hits_are_counted_per_key() {
+        let store = MemoryRateLimitStore::new();
+        let algorithm = RateLimitAlgorithm::token_bucket(1, Duration::from_secs(60));
+
+        assert!(store.hit("a", &algorithm).await.unwrap().allowed());
+        assert!(!store.hit("a", &algorithm).await.unwrap().allowed());
+        assert!(store.hit("b", &algorithm).await.unwrap().allowed());
+        assert_eq!(store.count(), 2);
+    }
+
+    #[async_std::test]
+    async fn cleanup_drops_idle_keys() {
+        let store = MemoryRateLimitStore::new();
+        let algorithm = RateLimitAlgorithm::token_bucket(1, Duration::from_secs(60));
+        store.hit("a", &algorithm).await.unwrap();
+
+        store.cleanup(Duration::from_secs(60));
+        assert_eq!(store.count(), 1);
+
+        async_std::task::sleep(Duration::from_millis(5)).await;
+        store.cleanup(Duration::from_millis(1));
+        assert_eq!(store.count(), 0);
+        assert!(store.hit("a", &algorithm).await.unwrap().allowed());
+    }
+}
@@ -0,0 +1,21 @@
+mod algorithm;
+mod memory_store;
+
+use async_trait::async_trait;
+
+pub use algorithm::{RateLimitAlgorithm, RateLimitDecision, RateLimitState};
+pub use memory_store::MemoryRateLimitStore;
+
+/// Keeps rate limit state per key.
+///
+/// `hit` has to read, update and write the state of `key` atomically. Backends can use
+/// `RateLimitAlgorithm::apply` to compute the new state, so only the storage itself needs
+/// implementing.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync + 'static {
+    async fn hit(
+        &self,
+        key: &str,
+        algorithm: &RateLimitAlgorithm,
+    ) -> crate::Result<RateLimitDecision>;
+}
@@ -1,7 +1,15 @@
-use http_types::{format_err, Cookie, Method, Url};
+use std::{net::IpAddr, sync::PoisonError, time::SystemTime};
+
+use http_types::{conditional::ETag, format_err, headers, Cookie, Method, Url};
 use routefinder::Captures;
 
-use crate::middlewares::CookieData;
+use crate::{
+    conditional::{self, Validators},
+    middlewares::{CookieData, CsrfToken, ForwardedData, RequestId, SessionData},
+    sessions::{SessionMut, SessionRef},
+    trusted_proxies::parse_ip,
+    Response,
+};
 
 pub struct Request {
     pub(crate) req: http_types::Request,
@@ -45,6 +53,45 @@ impl Request {
         self.req.method()
     }
 
+    /// Address of the connected peer, as set by the listener.
+    pub fn peer_addr(&self) -> Option<&str> {
+        self.req.peer_addr()
+    }
+
+    /// Address the peer connected to, as set by the listener.
+    pub fn local_addr(&self) -> Option<&str> {
+        self.req.local_addr()
+    }
+
+    /// IP address of the client, resolved by `ForwardedMiddleware` when the request came
+    /// through trusted proxies and otherwise the one of the connected peer.
+    pub fn remote_addr(&self) -> Option<IpAddr> {
+        match self.ext::<ForwardedData>() {
+            Some(forwarded) => forwarded.remote_addr,
+            None => self.peer_addr().and_then(parse_ip),
+        }
+    }
+
+    /// The scheme the client used, `http` unless a trusted proxy said otherwise.
+    pub fn scheme(&self) -> &str {
+        self.ext::<ForwardedData>()
+            .and_then(|forwarded| forwarded.scheme.as_deref())
+            .unwrap_or_else(|| self.url().scheme())
+    }
+
+    /// The host the client asked for, with the port if it gave one.
+    pub fn host(&self) -> Option<&str> {
+        self.ext::<ForwardedData>()
+            .and_then(|forwarded| forwarded.host.as_deref())
+            .or_else(|| self.header(headers::HOST).map(|host| host.last().as_str()))
+            .or_else(|| self.url().host_str())
+    }
+
+    /// The ID given to the request by `RequestIdMiddleware`.
+    pub fn request_id(&self) -> Option<&str> {
+        self.ext::<RequestId>().map(|id| id.0.as_str())
+    }
+
     pub fn param(&self, key: &str) -> crate::Result<&str> {
         self.route_params
             .iter()
@@ -59,6 +106,88 @@ impl Request {
             .and_then(|cookie_data| cookie_data.content.read().unwrap().get(name).cloned())
     }
 
+    /// Read a cookie set with `Response::insert_signed_cookie`, `None` if it is missing or
+    /// its signature does not match any configured key.
+    #[must_use]
+    pub fn signed_cookie(&self, name: &str) -> Option<Cookie<'static>> {
+        let cookie_data = self.ext::<CookieData>()?;
+        let cookie = cookie_data.content.read().unwrap().get(name).cloned()?;
+        cookie_data.keys.verify(&cookie)
+    }
+
+    /// Read a cookie set with `Response::insert_private_cookie`, `None` if it is missing or
+    /// cannot be decrypted with any configured key.
+    #[must_use]
+    pub fn private_cookie(&self, name: &str) -> Option<Cookie<'static>> {
+        let cookie_data = self.ext::<CookieData>()?;
+        let cookie = cookie_data.content.read().unwrap().get(name).cloned()?;
+        cookie_data.keys.decrypt(&cookie)
+    }
+
+    /// The token to embed in forms or send in a header, set by `CsrfMiddleware`.
+    #[must_use]
+    pub fn csrf_token(&self) -> Option<&str> {
+        self.ext::<CsrfToken>().map(|token| token.0.as_str())
+    }
+
+    /// Evaluate the conditional headers of the request against the current validators of
+    /// the resource, returning the `304` or `412` response to send instead of handling it.
+    ///
+    /// Pass `exists: false` when the resource doesn't exist yet, the validators are ignored
+    /// then: `If-Match` fails, even `If-Match: *`, and `If-None-Match: *` lets a create-only
+    /// `PUT` proceed. Endpoints changing state should call this before making any change.
+    #[must_use]
+    pub fn check_preconditions(
+        &self,
+        exists: bool,
+        etag: Option<ETag>,
+        last_modified: Option<SystemTime>,
+    ) -> Option<Response> {
+        let validators = exists.then_some(Validators {
+            etag,
+            last_modified,
+        });
+
+        let mut current = http_types::Response::new(200);
+        if let Some(validators) = &validators {
+            validators.apply(&mut current);
+        }
+        conditional::respond(
+            conditional::evaluate(validators.as_ref(), &self.req),
+            &current,
+        )
+    }
+
+    /// The session of the request, locked for reading until the returned guard is dropped.
+    ///
+    /// Panics if `SessionMiddleware` is not used for this request.
+    pub fn session(&self) -> SessionRef<'_> {
+        SessionRef::new(
+            self.session_data()
+                .content
+                .read()
+                .unwrap_or_else(PoisonError::into_inner),
+        )
+    }
+
+    /// The session of the request, locked for writing until the returned guard is dropped.
+    /// Changes are saved once the response is ready.
+    ///
+    /// Panics if `SessionMiddleware` is not used for this request.
+    pub fn session_mut(&mut self) -> SessionMut<'_> {
+        SessionMut::new(
+            self.session_data()
+                .content
+                .write()
+                .unwrap_or_else(PoisonError::into_inner),
+        )
+    }
+
+    fn session_data(&self) -> &SessionData {
+        self.ext::<SessionData>()
+            .expect("SessionMiddleware must be used to access the session")
+    }
+
     pub fn get_underlying_request(&self) -> http_types::Request {
         self.req.clone()
     }
@@ -1,14 +1,30 @@
+use async_std::stream::Stream;
 use http_types::{
     headers::{HeaderName, ToHeaderValues},
     Body, Cookie, Error, Mime, StatusCode,
 };
-use std::fmt::Debug;
+use std::{error::Error as StdError, fmt::Debug};
+
+use crate::BodyExt;
 
 pub(crate) enum CookieEvent {
     Added(Cookie<'static>),
+    AddedSigned(Cookie<'static>),
+    AddedPrivate(Cookie<'static>),
     Removed(Cookie<'static>),
 }
 
+impl CookieEvent {
+    pub(crate) fn cookie_mut(&mut self) -> &mut Cookie<'static> {
+        match self {
+            Self::Added(cookie)
+            | Self::AddedSigned(cookie)
+            | Self::AddedPrivate(cookie)
+            | Self::Removed(cookie) => cookie,
+        }
+    }
+}
+
 pub struct Response {
     pub(crate) res: http_types::Response,
     pub(crate) error: Option<Error>,
@@ -30,6 +46,16 @@ impl Response {
         }
     }
 
+    /// A `200` streaming the chunks of `stream` as they are produced, see `Body::from_stream`.
+    pub fn from_stream<S, T, E>(stream: S) -> Self
+    where
+        S: Stream<Item = Result<T, E>> + Send + 'static,
+        T: AsRef<[u8]> + Send + Sync + Unpin + 'static,
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        Body::from_stream(stream).into()
+    }
+
     pub fn insert_header(&mut self, key: impl Into<HeaderName>, value: impl ToHeaderValues) {
         self.res.insert_header(key, value);
     }
@@ -50,6 +76,18 @@ impl Response {
         self.cookie_events.push(CookieEvent::Added(cookie));
     }
 
+    /// Send `cookie` signed with the key configured on `CookieMiddleware`, so its value
+    /// can be read but not tampered with.
+    pub fn insert_signed_cookie(&mut self, cookie: Cookie<'static>) {
+        self.cookie_events.push(CookieEvent::AddedSigned(cookie));
+    }
+
+    /// Send `cookie` encrypted with the key configured on `CookieMiddleware`, so its value
+    /// can neither be read nor tampered with.
+    pub fn insert_private_cookie(&mut self, cookie: Cookie<'static>) {
+        self.cookie_events.push(CookieEvent::AddedPrivate(cookie));
+    }
+
     pub fn remove_cookie(&mut self, cookie: Cookie<'static>) {
         self.cookie_events.push(CookieEvent::Removed(cookie));
     }
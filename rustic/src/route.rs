@@ -1,12 +1,15 @@
 use std::{io, path::Path, sync::Arc};
 
+use futures_core::Future;
 use kv_log_macro::info;
 
 use crate::{
     endpoint::{Endpoint, MiddlewareEndpoint},
-    fs::{ServeDir, ServeFile},
+    fs::{EmbeddedDir, ServeDir, ServeEmbedded, ServeFile},
     middleware::Middleware,
     router::Router,
+    websocket::{WebSocketConnection, WebSocketEndpoint},
+    Request,
 };
 
 pub struct Route<'a> {
@@ -47,17 +50,86 @@ impl<'a> Route<'a> {
     }
 
     pub fn serve_dir(&mut self, dir: impl AsRef<Path>) -> io::Result<()> {
+        self.serve_dir_with(dir, |serve_dir| serve_dir)
+    }
+
+    /// Serve `dir` with options such as index files, listings or a single-page-app
+    /// fallback, set through `configure`.
+    pub fn serve_dir_with(
+        &mut self,
+        dir: impl AsRef<Path>,
+        configure: impl Fn(ServeDir) -> ServeDir,
+    ) -> io::Result<()> {
         let dir = dir.as_ref().to_owned().canonicalize()?;
         let prefix = self.path().to_string();
-        self.get(ServeDir::new(prefix, dir));
+        let serve_dir = Arc::new((configure)(ServeDir::new(prefix, dir)));
+
+        if serve_dir.is_writable() {
+            for method in [
+                http_types::Method::Put,
+                http_types::Method::Delete,
+                http_types::Method::MkCol,
+                http_types::Method::PropFind,
+            ] {
+                self.method(method, serve_dir.clone());
+            }
+        }
+        self.get(serve_dir);
         Ok(())
     }
 
     pub fn serve_file(&mut self, file: impl AsRef<Path>) -> io::Result<()> {
-        self.get(ServeFile::init(file)?);
+        self.serve_file_with(file, |serve_file| serve_file)
+    }
+
+    /// Serve `file` with options set through `configure`.
+    pub fn serve_file_with(
+        &mut self,
+        file: impl AsRef<Path>,
+        configure: impl Fn(ServeFile) -> ServeFile,
+    ) -> io::Result<()> {
+        self.get((configure)(ServeFile::init(file)?));
         Ok(())
     }
 
+    /// Serve a directory included in the binary with `embed_dir!`.
+    pub fn serve_embedded(&mut self, dir: EmbeddedDir) -> &mut Self {
+        self.serve_embedded_with(dir, |serve_embedded| serve_embedded)
+    }
+
+    /// Serve a directory included in the binary with options set through `configure`.
+    pub fn serve_embedded_with(
+        &mut self,
+        dir: EmbeddedDir,
+        configure: impl Fn(ServeEmbedded) -> ServeEmbedded,
+    ) -> &mut Self {
+        let prefix = self.path().to_string();
+        self.get((configure)(ServeEmbedded::new(prefix, dir)))
+    }
+
+    /// Accept WebSocket connections on this route, each one handed to `handler` once the
+    /// handshake went through the route's middleware.
+    pub fn websocket<F, Fut>(&mut self, handler: F) -> &mut Self
+    where
+        F: Fn(Request, WebSocketConnection) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = crate::Result<()>> + Send + 'static,
+    {
+        self.websocket_with(handler, |websocket| websocket)
+    }
+
+    /// Accept WebSocket connections with options set through `configure`.
+    pub fn websocket_with<F, Fut>(
+        &mut self,
+        handler: F,
+        configure: impl Fn(WebSocketEndpoint<F>) -> WebSocketEndpoint<F>,
+    ) -> &mut Self
+    where
+        F: Fn(Request, WebSocketConnection) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = crate::Result<()>> + Send + 'static,
+    {
+        self.get((configure)(WebSocketEndpoint::new(handler)))
+    }
+
     pub fn method(&mut self, method: http_types::Method, ep: impl Endpoint) -> &mut Self {
         self.router.add(
             &self.path,
@@ -12,6 +12,8 @@ pub(crate) struct Router {
 pub(crate) struct Selection<'a> {
     pub(crate) endpoint: &'a DynEndpoint,
     pub(crate) params: Captures<'static, 'static>,
+    /// The pattern of the matched route, `None` when nothing matched.
+    pub(crate) route: Option<String>,
 }
 
 impl Router {
@@ -25,7 +27,7 @@ impl Router {
     pub(crate) fn add(&mut self, path: &str, method: http_types::Method, ep: Box<DynEndpoint>) {
         self.method_map
             .entry(method)
-            .or_insert_with(MethodRouter::new)
+            .or_default()
             .add(path, ep)
             .unwrap()
     }
@@ -39,11 +41,13 @@ impl Router {
             Selection {
                 endpoint: m.handler(),
                 params: m.captures().into_owned(),
+                route: Some(m.route().to_string()),
             }
         } else if let Some(m) = self.all_method_router.best_match(path) {
             Selection {
                 endpoint: m.handler(),
                 params: m.captures().into_owned(),
+                route: Some(m.route().to_string()),
             }
         } else if method == http_types::Method::Head {
             // If it is a HTTP HEAD request then check if there is a callback in the endpoints map
@@ -61,11 +65,13 @@ impl Router {
             Selection {
                 endpoint: &method_not_allowed,
                 params: Captures::default(),
+                route: None,
             }
         } else {
             Selection {
                 endpoint: &not_found_endpoint,
                 params: Captures::default(),
+                route: None,
             }
         }
     }
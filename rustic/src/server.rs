@@ -4,6 +4,7 @@ use async_std::io;
 use kv_log_macro::info;
 
 use crate::{
+    catch_panic::{self, PanicContext, PanicHandler, PanicReport},
     listeners::{to_listener::ToListener, Listener},
     middleware::{Middleware, Next},
     middlewares,
@@ -15,6 +16,7 @@ use crate::{
 pub struct Server {
     router: Arc<Router>,
     middleware: Arc<Vec<Arc<dyn Middleware>>>,
+    panic_handler: PanicHandler,
 }
 
 impl Server {
@@ -23,6 +25,7 @@ impl Server {
         Self {
             router: Arc::new(Router::new()),
             middleware: Arc::new(vec![Arc::new(middlewares::CookieMiddleware::new())]),
+            panic_handler: Arc::new(catch_panic::log_panic),
         }
     }
 
@@ -50,18 +53,51 @@ impl Server {
         self
     }
 
+    /// Replace the built-in `CookieMiddleware`, which always runs before any other middleware.
+    pub(crate) fn set_cookie_middleware(&mut self, cookies: middlewares::CookieMiddleware) {
+        info!("Configuring middleware {}", cookies.name());
+        let m = Arc::get_mut(&mut self.middleware)
+            .expect("Registering middleware is not possible after the Server has started");
+        m[0] = Arc::new(cookies);
+    }
+
+    /// Replace the default panic reporting (an error log record) with `handler`.
+    ///
+    /// Panics are still answered with a 500 response either way.
+    pub fn with_panic_handler<F>(&mut self, handler: F) -> &mut Self
+    where
+        F: Fn(&PanicReport) + Send + Sync + 'static,
+    {
+        self.panic_handler = Arc::new(handler);
+        self
+    }
+
     pub async fn respond<Req, Res>(&self, req: Req) -> http_types::Result<Res>
     where
         Req: Into<http_types::Request>,
         Res: From<http_types::Response>,
     {
         let req = req.into();
-        let Self { router, middleware } = self.clone();
+        let Self {
+            router,
+            middleware,
+            panic_handler,
+        } = self.clone();
 
         let method = req.method().to_owned();
-        let Selection { endpoint, params } = router.route(req.url().path(), method);
+        let Selection {
+            endpoint,
+            params,
+            route,
+        } = router.route(req.url().path(), method);
         let route_params = vec![params];
-        let req = Request::new(req, route_params);
+        let mut req = Request::new(req, route_params);
+        req.set_ext(PanicContext {
+            handler: panic_handler,
+            method,
+            path: req.url().path().into(),
+            route: route.map(Into::into),
+        });
 
         let next = Next {
             endpoint,
@@ -74,11 +110,18 @@ impl Server {
     }
 }
 
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Clone for Server {
     fn clone(&self) -> Self {
         Self {
             router: self.router.clone(),
             middleware: self.middleware.clone(),
+            panic_handler: self.panic_handler.clone(),
         }
     }
 }
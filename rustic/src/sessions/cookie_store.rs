@@ -0,0 +1,77 @@
+use async_trait::async_trait;
+use http_types::{
+    cookies::{CookieJar, Key},
+    Cookie,
+};
+use kv_log_macro::warn;
+
+use super::{Session, SessionStore};
+
+const SIGNING_COOKIE_NAME: &str = "session";
+const MAX_COOKIE_SIZE: usize = 4096;
+
+/// Keeps the whole session in the cookie, signed so clients cannot tamper with it.
+///
+/// Session data is readable by the client, and everything has to fit into a single
+/// cookie (about 4KB). Nothing is kept on the server, so a copy of an old cookie stays
+/// valid until it expires even after the session was regenerated or destroyed.
+pub struct CookieStore {
+    key: Key,
+}
+
+impl CookieStore {
+    /// Panics if `secret` is shorter than 32 bytes.
+    #[must_use]
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            key: Key::derive_from(secret),
+        }
+    }
+}
+
+#[async_trait]
+impl SessionStore for CookieStore {
+    async fn load_session(&self, cookie_value: &str) -> crate::Result<Option<Session>> {
+        let mut jar = CookieJar::new();
+        jar.add_original(Cookie::new(SIGNING_COOKIE_NAME, cookie_value.to_owned()));
+
+        let verified = match jar.signed(&self.key).get(SIGNING_COOKIE_NAME) {
+            Some(cookie) => cookie,
+            None => {
+                warn!("Session cookie signature mismatch, starting a new session");
+                return Ok(None);
+            }
+        };
+
+        let session = base64::decode_config(verified.value(), base64::URL_SAFE_NO_PAD)
+            .ok()
+            .and_then(|json| serde_json::from_slice::<Session>(&json).ok());
+
+        Ok(session.filter(|session| !session.is_expired()))
+    }
+
+    async fn store_session(&self, session: &Session) -> crate::Result<String> {
+        let json = serde_json::to_vec(session)?;
+        let value = base64::encode_config(json, base64::URL_SAFE_NO_PAD);
+
+        let mut jar = CookieJar::new();
+        jar.signed(&self.key)
+            .add(Cookie::new(SIGNING_COOKIE_NAME, value));
+        let signed = jar
+            .get(SIGNING_COOKIE_NAME)
+            .map(|cookie| cookie.value().to_owned())
+            .unwrap_or_default();
+
+        if signed.len() > MAX_COOKIE_SIZE {
+            warn!("Session cookie is larger than browsers accept", {
+                size: signed.len(),
+            });
+        }
+
+        Ok(signed)
+    }
+
+    async fn destroy_session(&self, _id: &str) -> crate::Result<()> {
+        Ok(())
+    }
+}
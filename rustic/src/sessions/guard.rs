@@ -0,0 +1,66 @@
+use std::{
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::{RwLockReadGuard, RwLockWriteGuard},
+};
+
+use super::Session;
+
+/// Read access to the session of a request, from `Request::session`.
+///
+/// The session stays locked while this is alive. It is `!Send` on purpose, so a handler
+/// holding it across an `.await` doesn't compile instead of blocking other readers of the
+/// lock on the executor.
+pub struct SessionRef<'a> {
+    guard: RwLockReadGuard<'a, Session>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a> SessionRef<'a> {
+    pub(crate) fn new(guard: RwLockReadGuard<'a, Session>) -> Self {
+        Self {
+            guard,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl Deref for SessionRef<'_> {
+    type Target = Session;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+/// Write access to the session of a request, from `Request::session_mut`. Changes are
+/// saved once the response is ready.
+///
+/// Like `SessionRef`, it is `!Send` on purpose and can't be held across an `.await`.
+pub struct SessionMut<'a> {
+    guard: RwLockWriteGuard<'a, Session>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a> SessionMut<'a> {
+    pub(crate) fn new(guard: RwLockWriteGuard<'a, Session>) -> Self {
+        Self {
+            guard,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl Deref for SessionMut<'_> {
+    type Target = Session;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl DerefMut for SessionMut<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
@@ -0,0 +1,60 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_std::sync::RwLock;
+use async_trait::async_trait;
+
+use super::{Session, SessionStore};
+
+/// Keeps sessions in process memory; the cookie only carries the session id.
+///
+/// Sessions are lost on restart and are not shared between processes. Expired sessions
+/// are never returned, but stay in memory until `cleanup` is called.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    sessions: Arc<RwLock<HashMap<String, Session>>>,
+}
+
+impl MemoryStore {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drop all expired sessions.
+    pub async fn cleanup(&self) {
+        self.sessions
+            .write()
+            .await
+            .retain(|_, session| !session.is_expired());
+    }
+
+    pub async fn count(&self) -> usize {
+        self.sessions.read().await.len()
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemoryStore {
+    async fn load_session(&self, cookie_value: &str) -> crate::Result<Option<Session>> {
+        Ok(self
+            .sessions
+            .read()
+            .await
+            .get(cookie_value)
+            .filter(|session| !session.is_expired())
+            .cloned())
+    }
+
+    async fn store_session(&self, session: &Session) -> crate::Result<String> {
+        self.sessions
+            .write()
+            .await
+            .insert(session.id().to_owned(), session.clone());
+        Ok(session.id().to_owned())
+    }
+
+    async fn destroy_session(&self, id: &str) -> crate::Result<()> {
+        self.sessions.write().await.remove(id);
+        Ok(())
+    }
+}
@@ -0,0 +1,24 @@
+mod cookie_store;
+mod guard;
+mod memory_store;
+mod session;
+
+use async_trait::async_trait;
+
+pub use cookie_store::CookieStore;
+pub use guard::{SessionMut, SessionRef};
+pub use memory_store::MemoryStore;
+pub use session::Session;
+
+/// Persists sessions between requests.
+///
+/// The value returned by `store_session` is what ends up in the session cookie, and is
+/// handed back to `load_session` on the next request.
+#[async_trait]
+pub trait SessionStore: Send + Sync + 'static {
+    async fn load_session(&self, cookie_value: &str) -> crate::Result<Option<Session>>;
+
+    async fn store_session(&self, session: &Session) -> crate::Result<String>;
+
+    async fn destroy_session(&self, id: &str) -> crate::Result<()>;
+}
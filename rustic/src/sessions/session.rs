@@ -0,0 +1,217 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rand::RngCore;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+const SESSION_ID_BYTES: usize = 32;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Session {
+    id: String,
+    data: HashMap<String, String>,
+    created_at: u64,
+    expires_at: Option<u64>,
+    #[serde(skip)]
+    data_changed: bool,
+    #[serde(skip)]
+    destroyed: bool,
+}
+
+impl Session {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            id: generate_id(),
+            data: HashMap::new(),
+            created_at: unix_now(),
+            expires_at: None,
+            data_changed: false,
+            destroyed: false,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Deserialize the value stored under `key`, if there is one of type `T`.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        self.data
+            .get(key)
+            .and_then(|value| serde_json::from_str(value).ok())
+    }
+
+    pub fn insert<T: Serialize>(&mut self, key: &str, value: T) -> serde_json::Result<()> {
+        let value = serde_json::to_string(&value)?;
+        if self.data.get(key) != Some(&value) {
+            self.data.insert(key.to_owned(), value);
+            self.data_changed = true;
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        if self.data.remove(key).is_some() {
+            self.data_changed = true;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        if !self.data.is_empty() {
+            self.data.clear();
+            self.data_changed = true;
+        }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Give the session a fresh id while keeping its data, e.g. after a login.
+    ///
+    /// The session stored under the old id is destroyed at the end of the request.
+    pub fn regenerate(&mut self) {
+        self.id = generate_id();
+        self.data_changed = true;
+    }
+
+    /// Remove the session from the store and the client at the end of the request.
+    pub fn destroy(&mut self) {
+        self.destroyed = true;
+    }
+
+    #[must_use]
+    pub fn is_destroyed(&self) -> bool {
+        self.destroyed
+    }
+
+    #[must_use]
+    pub fn data_changed(&self) -> bool {
+        self.data_changed
+    }
+
+    #[must_use]
+    pub fn created_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.created_at)
+    }
+
+    #[must_use]
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.expires_at
+            .map(|expires_at| UNIX_EPOCH + Duration::from_secs(expires_at))
+    }
+
+    #[must_use]
+    pub fn is_expired(&self) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at <= unix_now())
+    }
+
+    /// Time left until the session expires, `None` for sessions without expiry.
+    #[must_use]
+    pub fn expires_in(&self) -> Option<Duration> {
+        self.expires_at
+            .map(|expires_at| Duration::from_secs(expires_at.saturating_sub(unix_now())))
+    }
+
+    /// Recompute the expiry from an absolute lifetime and an idle timeout, whichever ends
+    /// first.
+    pub(crate) fn refresh_expiry(
+        &mut self,
+        max_age: Option<Duration>,
+        idle_timeout: Option<Duration>,
+    ) {
+        let absolute = max_age.map(|max_age| self.created_at + max_age.as_secs());
+        let idle = idle_timeout.map(|idle_timeout| unix_now() + idle_timeout.as_secs());
+
+        self.expires_at = match (absolute, idle) {
+            (Some(absolute), Some(idle)) => Some(absolute.min(idle)),
+            (absolute, idle) => absolute.or(idle),
+        };
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn generate_id() -> String {
+    let mut bytes = [0u8; SESSION_ID_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_actual_changes_mark_data_changed() {
+        let mut session = Session::new();
+        session.remove("missing");
+        session.clear();
+        assert!(!session.data_changed());
+
+        session.insert("answer", 42).unwrap();
+        assert!(session.data_changed());
+        assert_eq!(session.get::<u32>("answer"), Some(42));
+        assert_eq!(session.get::<String>("answer"), None);
+
+        let mut stored = session.clone();
+        stored.data_changed = false;
+        stored.insert("answer", 42).unwrap();
+        assert!(!stored.data_changed());
+    }
+
+    #[test]
+    fn regenerate_keeps_data_under_a_new_id() {
+        let mut session = Session::new();
+        session.insert("user", "admin").unwrap();
+        let id = session.id().to_owned();
+
+        session.regenerate();
+        assert_ne!(session.id(), id);
+        assert_eq!(session.get::<String>("user").as_deref(), Some("admin"));
+    }
+
+    #[test]
+    fn expiry_is_the_earlier_of_max_age_and_idle_timeout() {
+        let mut session = Session::new();
+        session.refresh_expiry(None, None);
+        assert_eq!(session.expires_in(), None);
+
+        session.refresh_expiry(
+            Some(Duration::from_secs(60)),
+            Some(Duration::from_secs(600)),
+        );
+        assert!(session.expires_in().unwrap() <= Duration::from_secs(60));
+
+        session.refresh_expiry(
+            Some(Duration::from_secs(600)),
+            Some(Duration::from_secs(60)),
+        );
+        assert!(session.expires_in().unwrap() <= Duration::from_secs(60));
+        assert!(!session.is_expired());
+
+        session.created_at -= 120;
+        session.refresh_expiry(Some(Duration::from_secs(60)), None);
+        assert!(session.is_expired());
+    }
+}
@@ -0,0 +1,217 @@
+use std::time::Duration;
+
+use async_std::{channel, future, io, stream::StreamExt, task};
+use async_trait::async_trait;
+use futures_core::Future;
+use http_types::{headers, Body, StatusCode};
+use kv_log_macro::{error, info};
+
+use super::SseSender;
+use crate::{BodyExt, Endpoint, Request, Response};
+
+/// How often a comment is sent on an idle stream, so proxies don't time it out.
+pub(crate) const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(15);
+/// How many encoded events may wait for a slow client before `SseSender::send` waits too.
+pub(crate) const DEFAULT_BUFFER: usize = 16;
+
+/// An endpoint streaming server-sent events from `handler`, e.g.
+/// `app.at("/events").get(rustic::sse(|req, sender| async move { ... }))`.
+///
+/// The response is sent as soon as the route's middleware lets it through, the handler
+/// keeps running in its own task until it returns or the client disconnects.
+pub fn sse<F, Fut>(handler: F) -> SseEndpoint<F>
+where
+    F: Fn(Request, SseSender) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = crate::Result<()>> + Send + 'static,
+{
+    SseEndpoint {
+        handler,
+        keep_alive: Some(DEFAULT_KEEP_ALIVE),
+        buffer: DEFAULT_BUFFER,
+    }
+}
+
+pub struct SseEndpoint<F> {
+    handler: F,
+    keep_alive: Option<Duration>,
+    buffer: usize,
+}
+
+impl<F> SseEndpoint<F> {
+    /// How long the stream may stay idle before a keep-alive comment is sent, `None` to
+    /// never send one.
+    #[must_use]
+    pub fn keep_alive(mut self, keep_alive: Option<Duration>) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// How many events may be queued for a client that reads slower than they are sent.
+    #[must_use]
+    pub fn buffer(mut self, buffer: usize) -> Self {
+        self.buffer = buffer.max(1);
+        self
+    }
+}
+
+#[async_trait]
+impl<F, Fut> Endpoint for SseEndpoint<F>
+where
+    F: Fn(Request, SseSender) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = crate::Result<()>> + Send + 'static,
+{
+    async fn call(&self, req: Request) -> crate::Result {
+        let last_event_id = req
+            .header("Last-Event-ID")
+            .map(|id| id.last().as_str().to_owned());
+        let path = req.url().path().to_owned();
+
+        let (chunks, receiver) = channel::bounded(self.buffer);
+        let sender = SseSender::new(chunks, last_event_id);
+
+        // Closed when the handler returns, which ends the keep-alive task and with it the stream
+        let (done, handler_running) = channel::bounded::<()>(1);
+
+        if let Some(keep_alive) = self.keep_alive {
+            let sender = sender.clone();
+            task::spawn(async move {
+                while future::timeout(keep_alive, handler_running.recv())
+                    .await
+                    .is_err()
+                {
+                    if sender.comment("keep-alive").await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        let fut = (self.handler)(req, sender);
+        task::spawn(async move {
+            if let Err(e) = fut.await {
+                error!("Event stream handler failed", {
+                    path: path,
+                    error: e.to_string(),
+                });
+            } else {
+                info!("Event stream closed", { path: path });
+            }
+            drop(done);
+        });
+
+        let mut res = Response::new(StatusCode::Ok);
+        res.insert_header(headers::CACHE_CONTROL, "no-cache");
+        // Keeps nginx from holding events back until its buffer fills
+        res.insert_header("X-Accel-Buffering", "no");
+
+        // Dropping the body, e.g. when the client went away, closes the channel for the handler
+        let mut body = Body::from_stream(receiver.map(Ok::<_, io::Error>));
+        body.set_mime("text/event-stream");
+        res.set_body(body);
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_types::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    use super::*;
+    use crate::{Server, SseEvent};
+
+    fn request(last_event_id: Option<&str>) -> HttpRequest {
+        let mut req = HttpRequest::new(Method::Get, Url::parse("http://localhost/events").unwrap());
+        if let Some(last_event_id) = last_event_id {
+            req.insert_header("Last-Event-ID", last_event_id);
+        }
+        req
+    }
+
+    #[async_std::test]
+    async fn events_are_streamed_until_the_handler_returns() {
+        let mut app = Server::new();
+        app.at("/events")
+            .get(sse(|_, sender: SseSender| async move {
+                sender.send(SseEvent::new("one").id("1")).await?;
+                sender.send(SseEvent::new("two").id("2")).await?;
+                Ok(())
+            }));
+
+        let mut res: HttpResponse = app.respond(request(None)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.content_type().unwrap().essence(), "text/event-stream");
+        assert_eq!(res.header(headers::CACHE_CONTROL).unwrap(), "no-cache");
+        assert_eq!(res.header("X-Accel-Buffering").unwrap(), "no");
+        assert_eq!(
+            res.body_string().await.unwrap(),
+            "id: 1\ndata: one\n\nid: 2\ndata: two\n\n"
+        );
+    }
+
+    #[async_std::test]
+    async fn handlers_resume_after_the_last_event_id() {
+        let mut app = Server::new();
+        app.at("/events")
+            .get(sse(|_, sender: SseSender| async move {
+                let next = sender
+                    .last_event_id()
+                    .and_then(|id| id.parse::<u32>().ok())
+                    .map_or(0, |id| id + 1);
+                sender
+                    .send(SseEvent::new("next").id(next.to_string()))
+                    .await?;
+                Ok(())
+            }));
+
+        let mut res: HttpResponse = app.respond(request(Some("41"))).await.unwrap();
+        assert_eq!(res.body_string().await.unwrap(), "id: 42\ndata: next\n\n");
+    }
+
+    #[async_std::test]
+    async fn idle_streams_get_keep_alive_comments() {
+        let mut app = Server::new();
+        app.at("/events").get(
+            sse(|_, sender: SseSender| async move {
+                task::sleep(Duration::from_millis(100)).await;
+                sender.send(SseEvent::new("late")).await?;
+                Ok(())
+            })
+            .keep_alive(Some(Duration::from_millis(20))),
+        );
+
+        let mut res: HttpResponse = app.respond(request(None)).await.unwrap();
+        let body = res.body_string().await.unwrap();
+        assert!(body.starts_with(": keep-alive\n\n"), "{:?}", body);
+        assert!(body.ends_with("data: late\n\n"), "{:?}", body);
+    }
+
+    #[async_std::test]
+    async fn handlers_learn_that_the_client_went_away() {
+        let (report, reported) = channel::bounded(1);
+        let mut app = Server::new();
+        app.at("/events").get(
+            sse(move |_, sender: SseSender| {
+                let report = report.clone();
+                async move {
+                    let mut sent = 0;
+                    while sender.send(SseEvent::new("tick")).await.is_ok() {
+                        sent += 1;
+                    }
+                    report.send(sent).await?;
+                    Ok(())
+                }
+            })
+            .buffer(2)
+            .keep_alive(None),
+        );
+
+        let res: HttpResponse = app.respond(request(None)).await.unwrap();
+        drop(res);
+
+        let sent = future::timeout(Duration::from_secs(5), reported.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(sent <= 2, "{}", sent);
+    }
+}
@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+/// A server-sent event, written to the stream as `id`, `event`, `retry` and `data` fields.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SseEvent {
+    data: String,
+    id: Option<String>,
+    event: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl SseEvent {
+    /// An event carrying `data`, sent as one `data` field per line.
+    pub fn new(data: impl Into<String>) -> Self {
+        Self {
+            data: data.into(),
+            id: None,
+            event: None,
+            retry: None,
+        }
+    }
+
+    /// The id a reconnecting client sends back as `Last-Event-ID`.
+    #[must_use]
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// The event type, dispatched to `addEventListener(event, ...)` instead of `onmessage`.
+    #[must_use]
+    pub fn event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// How long the client waits before reconnecting after the stream ends.
+    #[must_use]
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    pub(crate) fn encode(&self) -> String {
+        let mut encoded = String::new();
+
+        if let Some(id) = &self.id {
+            push_field(&mut encoded, "id", &single_line(id));
+        }
+        if let Some(event) = &self.event {
+            push_field(&mut encoded, "event", &single_line(event));
+        }
+        if let Some(retry) = self.retry {
+            push_field(&mut encoded, "retry", &retry.as_millis().to_string());
+        }
+        for line in self
+            .data
+            .split("\r\n")
+            .flat_map(|line| line.split(['\r', '\n']))
+        {
+            push_field(&mut encoded, "data", line);
+        }
+
+        encoded.push('\n');
+        encoded
+    }
+}
+
+fn push_field(encoded: &mut String, name: &str, value: &str) {
+    encoded.push_str(name);
+    encoded.push_str(": ");
+    encoded.push_str(value);
+    encoded.push('\n');
+}
+
+/// A line break would end the field early and start a field the client never sent.
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n'], "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_come_in_order() {
+        let event = SseEvent::new("hello")
+            .retry(Duration::from_secs(3))
+            .event("greeting")
+            .id("7");
+
+        assert_eq!(
+            event.encode(),
+            "id: 7\nevent: greeting\nretry: 3000\ndata: hello\n\n"
+        );
+    }
+
+    #[test]
+    fn every_line_of_data_gets_a_field() {
+        assert_eq!(
+            SseEvent::new("a\nb\r\nc\rd").encode(),
+            "data: a\ndata: b\ndata: c\ndata: d\n\n"
+        );
+        assert_eq!(SseEvent::new("").encode(), "data: \n\n");
+        assert_eq!(SseEvent::new("a\n").encode(), "data: a\ndata: \n\n");
+    }
+
+    #[test]
+    fn line_breaks_cannot_inject_fields() {
+        let event = SseEvent::new("x")
+            .id("1\ndata: forged")
+            .event("update\r\nretry: 0");
+
+        assert_eq!(
+            event.encode(),
+            "id: 1data: forged\nevent: updateretry: 0\ndata: x\n\n"
+        );
+    }
+}
@@ -0,0 +1,7 @@
+mod endpoint;
+mod event;
+mod sender;
+
+pub use endpoint::{sse, SseEndpoint};
+pub use event::SseEvent;
+pub use sender::{Disconnected, SseSender};
@@ -0,0 +1,99 @@
+use std::{error, fmt};
+
+use async_std::channel;
+
+use super::SseEvent;
+
+/// The client went away, nothing more can be sent on the stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Disconnected;
+
+impl fmt::Display for Disconnected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the event stream client disconnected")
+    }
+}
+
+impl error::Error for Disconnected {}
+
+/// Pushes events to one client of an `sse` endpoint.
+///
+/// Sends wait while the client is behind on reading, and fail with `Disconnected` once it
+/// is gone, which is the handler's cue to return.
+#[derive(Clone, Debug)]
+pub struct SseSender {
+    sender: channel::Sender<Vec<u8>>,
+    last_event_id: Option<String>,
+}
+
+impl SseSender {
+    pub(crate) fn new(sender: channel::Sender<Vec<u8>>, last_event_id: Option<String>) -> Self {
+        Self {
+            sender,
+            last_event_id,
+        }
+    }
+
+    pub async fn send(&self, event: SseEvent) -> Result<(), Disconnected> {
+        self.send_raw(event.encode()).await
+    }
+
+    /// Send a comment, which clients ignore but proxies see as traffic.
+    pub async fn comment(&self, comment: &str) -> Result<(), Disconnected> {
+        let mut encoded = String::new();
+        for line in comment.split(['\r', '\n']) {
+            encoded.push_str(": ");
+            encoded.push_str(line);
+            encoded.push('\n');
+        }
+        encoded.push('\n');
+        self.send_raw(encoded).await
+    }
+
+    /// The id of the last event the client saw before reconnecting, so the handler can
+    /// resume after it instead of starting over.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    pub fn is_disconnected(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    async fn send_raw(&self, encoded: String) -> Result<(), Disconnected> {
+        self.sender
+            .send(encoded.into_bytes())
+            .await
+            .map_err(|_| Disconnected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn comments_are_prefixed_per_line() {
+        let (chunks, receiver) = channel::bounded(4);
+        let sender = SseSender::new(chunks, Some("41".to_owned()));
+        assert_eq!(sender.last_event_id(), Some("41"));
+
+        sender.comment("keep\nalive").await.unwrap();
+        sender.send(SseEvent::new("data")).await.unwrap();
+
+        assert_eq!(receiver.recv().await.unwrap(), b": keep\n: alive\n\n");
+        assert_eq!(receiver.recv().await.unwrap(), b"data: data\n\n");
+    }
+
+    #[async_std::test]
+    async fn sends_fail_once_the_client_is_gone() {
+        let (chunks, receiver) = channel::bounded(4);
+        let sender = SseSender::new(chunks, None);
+        assert!(!sender.is_disconnected());
+
+        drop(receiver);
+        assert!(sender.is_disconnected());
+        assert_eq!(sender.send(SseEvent::new("lost")).await, Err(Disconnected));
+        assert_eq!(sender.comment("lost").await, Err(Disconnected));
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    error::Error as StdError,
+    pin::Pin,
+    sync::{Mutex, PoisonError},
+    task::{Context, Poll},
+};
+
+use async_std::{
+    io::{self, BufRead, Read},
+    stream::Stream,
+};
+use http_types::Body;
+
+/// Bodies produced by an async stream of chunks.
+pub trait BodyExt: Sized {
+    /// A body of unknown length, sent with chunked transfer encoding.
+    ///
+    /// The stream is only polled when the connection asks for more data, so a slow client
+    /// slows down the producer, and it is dropped as soon as the client disconnects. An
+    /// error from the stream aborts the response.
+    fn from_stream<S, T, E>(stream: S) -> Self
+    where
+        S: Stream<Item = Result<T, E>> + Send + 'static,
+        T: AsRef<[u8]> + Send + Sync + Unpin + 'static,
+        E: Into<Box<dyn StdError + Send + Sync>>;
+}
+
+impl BodyExt for Body {
+    fn from_stream<S, T, E>(stream: S) -> Self
+    where
+        S: Stream<Item = Result<T, E>> + Send + 'static,
+        T: AsRef<[u8]> + Send + Sync + Unpin + 'static,
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        Body::from_reader(StreamReader::new(stream), None)
+    }
+}
+
+/// Reads the chunks of a stream in order, polling it only once the current chunk is used up.
+pub(crate) struct StreamReader<S, T> {
+    /// Only ever accessed through `&mut self`, the lock just makes the reader `Sync` as
+    /// `Body` requires without asking the same of the stream.
+    stream: Mutex<Pin<Box<S>>>,
+    chunk: Option<T>,
+    pos: usize,
+}
+
+impl<S, T> StreamReader<S, T> {
+    pub(crate) fn new(stream: S) -> Self {
+        Self {
+            stream: Mutex::new(Box::pin(stream)),
+            chunk: None,
+            pos: 0,
+        }
+    }
+}
+
+impl<S, T, E> BufRead for StreamReader<S, T>
+where
+    S: Stream<Item = Result<T, E>>,
+    T: AsRef<[u8]> + Unpin,
+    E: Into<Box<dyn StdError + Send + Sync>>,
+{
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        let stream = this
+            .stream
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        while this
+            .chunk
+            .as_ref()
+            .is_none_or(|chunk| this.pos == chunk.as_ref().len())
+        {
+            match stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    this.chunk = Some(chunk);
+                    this.pos = 0;
+                }
+                Poll::Ready(Some(Err(e))) => {
+                    return Poll::Ready(Err(io::Error::other(e)));
+                }
+                Poll::Ready(None) => return Poll::Ready(Ok(&[])),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        match &this.chunk {
+            Some(chunk) => Poll::Ready(Ok(&chunk.as_ref()[this.pos..])),
+            None => Poll::Ready(Ok(&[])),
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        let len = this.chunk.as_ref().map_or(0, |chunk| chunk.as_ref().len());
+        this.pos = (this.pos + amt).min(len);
+    }
+}
+
+impl<S, T, E> Read for StreamReader<S, T>
+where
+    S: Stream<Item = Result<T, E>>,
+    T: AsRef<[u8]> + Unpin,
+    E: Into<Box<dyn StdError + Send + Sync>>,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let available = match self.as_mut().poll_fill_buf(cx) {
+            Poll::Ready(Ok(available)) => available,
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Poll::Ready(Ok(len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use async_std::{io::ReadExt, stream, task};
+    use http_types::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    use super::*;
+    use crate::{Request, Response};
+
+    fn chunks(chunks: &'static [&'static str]) -> impl Stream<Item = io::Result<&'static str>> {
+        stream::from_iter(chunks.iter().copied().map(Ok))
+    }
+
+    #[async_std::test]
+    async fn chunks_are_read_in_order() {
+        let mut reader = StreamReader::new(chunks(&["Hello", "", ", ", "", "world"]));
+        let mut body = String::new();
+        reader.read_to_string(&mut body).await.unwrap();
+        assert_eq!(body, "Hello, world");
+    }
+
+    #[async_std::test]
+    async fn small_reads_span_chunks() {
+        let mut reader = StreamReader::new(chunks(&["abc", "de"]));
+        let mut buf = [0u8; 2];
+        let mut reads = Vec::new();
+        loop {
+            let len = reader.read(&mut buf).await.unwrap();
+            if len == 0 {
+                break;
+            }
+            reads.push(String::from_utf8(buf[..len].to_vec()).unwrap());
+        }
+        assert_eq!(reads, ["ab", "c", "de"]);
+    }
+
+    #[async_std::test]
+    async fn the_stream_is_polled_only_when_data_is_needed() {
+        let polled = Arc::new(AtomicUsize::new(0));
+        let counter = polled.clone();
+        let stream = stream::repeat_with(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok::<_, io::Error>(vec![b'x'; 4])
+        });
+
+        let mut reader = StreamReader::new(stream);
+        let mut buf = [0u8; 3];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(polled.load(Ordering::SeqCst), 1);
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(polled.load(Ordering::SeqCst), 2);
+    }
+
+    #[async_std::test]
+    async fn stream_errors_abort_the_read_after_earlier_chunks() {
+        let stream = stream::from_iter(vec![
+            Ok("partial"),
+            Err(io::Error::other("producer failed")),
+            Ok("never read"),
+        ]);
+        let mut reader = StreamReader::new(stream);
+
+        let mut buf = [0u8; 16];
+        let len = reader.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"partial");
+
+        let e = reader.read(&mut buf).await.unwrap_err();
+        assert_eq!(e.to_string(), "producer failed");
+    }
+
+    #[async_std::test]
+    async fn streamed_responses_are_chunked() {
+        let mut app = crate::new();
+        app.at("/").get(|_: Request| async move {
+            Ok(Response::from_stream(chunks(&["one ", "two ", "three"])))
+        });
+
+        let req = HttpRequest::new(Method::Get, Url::parse("http://localhost/").unwrap());
+        let mut res: HttpResponse = app.respond(req).await.unwrap();
+        assert_eq!(res.status(), http_types::StatusCode::Ok);
+        assert_eq!(res.len(), None);
+        assert_eq!(res.body_string().await.unwrap(), "one two three");
+    }
+
+    #[async_std::test]
+    async fn dropping_the_body_drops_the_stream() {
+        struct Guard(Arc<AtomicUsize>);
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let guard = Guard(dropped.clone());
+        let stream = stream::repeat_with(move || {
+            let _ = &guard;
+            Ok::<_, io::Error>("tick")
+        });
+
+        let mut body = Body::from_stream(stream);
+        let mut buf = [0u8; 4];
+        body.read_exact(&mut buf).await.unwrap();
+        assert_eq!(dropped.load(Ordering::SeqCst), 0);
+
+        drop(body);
+        task::yield_now().await;
+        assert_eq!(dropped.load(Ordering::SeqCst), 1);
+    }
+}
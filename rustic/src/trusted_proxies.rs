@@ -0,0 +1,203 @@
+use std::{error, fmt, net::IpAddr, str::FromStr};
+
+/// A block of IP addresses, e.g. `10.0.0.0/8` or `fd00::/8`. A bare address is a block of
+/// one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// `None` if `prefix` is longer than the address.
+    pub fn new(addr: IpAddr, prefix: u8) -> Option<Self> {
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        (prefix <= max).then_some(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // Dual stack sockets report IPv4 peers as `::ffff:a.b.c.d`
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = ParseCidrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+
+        let addr: IpAddr = addr.parse().map_err(|_| ParseCidrError)?;
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| ParseCidrError)?,
+            None if addr.is_ipv4() => 32,
+            None => 128,
+        };
+        Cidr::new(addr, prefix).ok_or(ParseCidrError)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseCidrError;
+
+impl fmt::Display for ParseCidrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid CIDR block, expected an address with an optional /prefix")
+    }
+}
+
+impl error::Error for ParseCidrError {}
+
+/// The addresses of proxies whose forwarding headers are believed. Nothing is trusted
+/// until added.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrustedProxies {
+    blocks: Vec<Cidr>,
+}
+
+impl TrustedProxies {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn trust(mut self, block: Cidr) -> Self {
+        self.blocks.push(block);
+        self
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.blocks.iter().any(|block| block.contains(ip))
+    }
+}
+
+/// The IP of an address as listeners and proxies write it, `1.2.3.4`, `1.2.3.4:80`,
+/// `::1` or `[::1]:80`, with IPv4 addresses mapped into IPv6 unwrapped.
+pub(crate) fn parse_ip(addr: &str) -> Option<IpAddr> {
+    let addr = addr.trim();
+    let ip = match addr.parse::<IpAddr>() {
+        Ok(ip) => ip,
+        Err(_) => match addr.parse::<std::net::SocketAddr>() {
+            Ok(socket_addr) => socket_addr.ip(),
+            Err(_) => addr.strip_prefix('[')?.strip_suffix(']')?.parse().ok()?,
+        },
+    };
+    Some(ip.to_canonical())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn cidr(cidr: &str) -> Cidr {
+        cidr.parse().unwrap()
+    }
+
+    #[test]
+    fn blocks_parse_with_or_without_a_prefix() {
+        assert_eq!(cidr("10.0.0.0/8"), Cidr::new(ip("10.0.0.0"), 8).unwrap());
+        assert_eq!(cidr(" 192.0.2.1 "), Cidr::new(ip("192.0.2.1"), 32).unwrap());
+        assert_eq!(cidr("fd00::/8"), Cidr::new(ip("fd00::"), 8).unwrap());
+        assert_eq!(cidr("::1"), Cidr::new(ip("::1"), 128).unwrap());
+        assert_eq!(cidr("10.0.0.0/8").to_string(), "10.0.0.0/8");
+    }
+
+    #[test]
+    fn malformed_blocks_are_refused() {
+        for block in [
+            "",
+            "10.0.0.0/",
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0/8",
+            "10.0.0.0/-1",
+            "10.0.0.0/8/8",
+            "localhost",
+            "[::1]/128",
+        ] {
+            assert_eq!(block.parse::<Cidr>(), Err(ParseCidrError), "{:?}", block);
+        }
+        assert_eq!(Cidr::new(ip("10.0.0.0"), 33), None);
+    }
+
+    #[test]
+    fn blocks_contain_addresses_under_their_prefix() {
+        let block = cidr("10.1.0.0/16");
+        assert!(block.contains(ip("10.1.0.0")));
+        assert!(block.contains(ip("10.1.255.255")));
+        assert!(!block.contains(ip("10.2.0.0")));
+
+        let block = cidr("2001:db8::/32");
+        assert!(block.contains(ip("2001:db8:ffff::1")));
+        assert!(!block.contains(ip("2001:db9::1")));
+
+        assert!(cidr("0.0.0.0/0").contains(ip("203.0.113.9")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("::1")));
+        assert!(cidr("::/0").contains(ip("::1")));
+        assert!(cidr("192.0.2.1").contains(ip("192.0.2.1")));
+        assert!(!cidr("192.0.2.1").contains(ip("192.0.2.2")));
+    }
+
+    #[test]
+    fn mapped_ipv4_addresses_match_ipv4_blocks() {
+        assert!(cidr("10.0.0.0/8").contains(ip("::ffff:10.0.0.1")));
+        assert!(!cidr("::ffff:0:0/96").contains(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn nothing_is_trusted_by_default() {
+        assert!(!TrustedProxies::new().contains(ip("127.0.0.1")));
+
+        let proxies = TrustedProxies::new()
+            .trust(cidr("127.0.0.1"))
+            .trust(cidr("10.0.0.0/8"));
+        assert!(proxies.contains(ip("127.0.0.1")));
+        assert!(proxies.contains(ip("10.9.8.7")));
+        assert!(!proxies.contains(ip("127.0.0.2")));
+    }
+
+    #[test]
+    fn addresses_parse_with_or_without_ports() {
+        assert_eq!(parse_ip("192.0.2.1"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_ip(" 192.0.2.1:8080 "), Some(ip("192.0.2.1")));
+        assert_eq!(parse_ip("::1"), Some(ip("::1")));
+        assert_eq!(parse_ip("[::1]"), Some(ip("::1")));
+        assert_eq!(parse_ip("[2001:db8::1]:4711"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_ip("[::ffff:192.0.2.1]:80"), Some(ip("192.0.2.1")));
+
+        for addr in [
+            "",
+            "unknown",
+            "_hidden",
+            "192.0.2.1:port",
+            "[::1",
+            "example.com:80",
+        ] {
+            assert_eq!(parse_ip(addr), None, "{:?}", addr);
+        }
+    }
+}
@@ -0,0 +1,376 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use async_std::{
+    channel,
+    io,
+    stream::Stream,
+    sync::Mutex,
+    task::{self, JoinHandle},
+};
+use futures_lite::io::{AsyncWriteExt, ReadHalf, WriteHalf};
+use http_types::{upgrade::Connection, StatusCode};
+
+use super::{
+    deflate::{DeflateConfig, Deflater, Inflater},
+    frame::{
+        encode_frame, read_frame, OpCode, ReadError, CLOSE_INVALID_DATA, CLOSE_PROTOCOL_ERROR,
+        CLOSE_TOO_LARGE, MAX_CONTROL_PAYLOAD,
+    },
+    message::{is_valid_close_code, CloseFrame, WebSocketMessage},
+};
+
+/// How many received messages may wait for the handler before reading from the client stops.
+pub(crate) const MESSAGE_BUFFER: usize = 16;
+
+/// What a connection accepts from the client, settled by the endpoint.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Limits {
+    pub(crate) max_frame_size: usize,
+    pub(crate) max_message_size: usize,
+}
+
+/// An upgraded WebSocket connection, a stream of the messages received from the client.
+///
+/// Pings are answered and the closing handshake is completed without the handler's help,
+/// the stream ends once the client closed the connection.
+pub struct WebSocketConnection {
+    messages: channel::Receiver<crate::Result<WebSocketMessage>>,
+    sender: WebSocketSender,
+    protocol: Option<String>,
+}
+
+impl WebSocketConnection {
+    /// Start reading from `connection`, returning the reading task along with it.
+    pub(crate) fn start(
+        connection: Connection,
+        protocol: Option<String>,
+        deflate: Option<DeflateConfig>,
+        limits: Limits,
+    ) -> (Self, JoinHandle<()>) {
+        let (source, sink) = futures_lite::io::split(connection);
+        let sender = WebSocketSender {
+            writer: Arc::new(Mutex::new(Writer {
+                sink,
+                deflater: deflate.map(Deflater::new),
+                closed: false,
+            })),
+        };
+
+        let (messages_sender, messages) = channel::bounded(MESSAGE_BUFFER);
+        let reader = MessageReader {
+            source,
+            inflater: deflate.map(Inflater::new),
+            limits,
+            fragments: None,
+        };
+        let reading = task::spawn(reader.run(sender.clone(), messages_sender));
+
+        let connection = Self {
+            messages,
+            sender,
+            protocol,
+        };
+        (connection, reading)
+    }
+
+    /// The next message, `None` once the connection is closed.
+    pub async fn recv(&mut self) -> Option<crate::Result<WebSocketMessage>> {
+        self.messages.recv().await.ok()
+    }
+
+    pub async fn send(&self, message: WebSocketMessage) -> crate::Result<()> {
+        self.sender.send(message).await
+    }
+
+    /// Start the closing handshake, the stream ends once the client answered it.
+    pub async fn close(&self, frame: Option<CloseFrame>) -> crate::Result<()> {
+        self.sender.close(frame).await
+    }
+
+    /// A handle for sending from another task while this one keeps receiving.
+    pub fn sender(&self) -> WebSocketSender {
+        self.sender.clone()
+    }
+
+    /// The subprotocol agreed on during the handshake.
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+}
+
+impl Stream for WebSocketConnection {
+    type Item = crate::Result<WebSocketMessage>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.messages).poll_next(cx)
+    }
+}
+
+/// The sending half of a `WebSocketConnection`, cheap to clone.
+#[derive(Clone)]
+pub struct WebSocketSender {
+    writer: Arc<Mutex<Writer>>,
+}
+
+struct Writer {
+    sink: WriteHalf<Connection>,
+    deflater: Option<Deflater>,
+    /// Set once a close frame went out, after which only the connection shutdown may follow.
+    closed: bool,
+}
+
+impl Writer {
+    async fn write_frame(&mut self, opcode: OpCode, payload: &[u8]) -> io::Result<()> {
+        let frame = match &mut self.deflater {
+            Some(deflater) if !opcode.is_control() => {
+                encode_frame(opcode, true, &deflater.compress(payload)?)
+            }
+            _ => encode_frame(opcode, false, payload),
+        };
+
+        self.sink.write_all(&frame).await?;
+        self.sink.flush().await
+    }
+
+    async fn write_close(&mut self, frame: Option<&CloseFrame>) -> io::Result<()> {
+        self.closed = true;
+        let payload = frame.map(CloseFrame::encode).unwrap_or_default();
+        self.write_frame(OpCode::Close, &payload).await
+    }
+}
+
+impl WebSocketSender {
+    pub async fn send(&self, message: WebSocketMessage) -> crate::Result<()> {
+        let (opcode, payload) = match message {
+            WebSocketMessage::Text(text) => (OpCode::Text, text.into_bytes()),
+            WebSocketMessage::Binary(data) => (OpCode::Binary, data),
+            WebSocketMessage::Ping(data) => (OpCode::Ping, data),
+            WebSocketMessage::Pong(data) => (OpCode::Pong, data),
+            WebSocketMessage::Close(frame) => return self.close(frame).await,
+        };
+
+        if opcode.is_control() && payload.len() > MAX_CONTROL_PAYLOAD {
+            return Err(http_types::Error::from_str(
+                StatusCode::InternalServerError,
+                "ping and pong payloads are limited to 125 bytes",
+            ));
+        }
+
+        let mut writer = self.writer.lock().await;
+        if writer.closed {
+            return Err(http_types::Error::from_str(
+                StatusCode::InternalServerError,
+                "the WebSocket connection is closing",
+            ));
+        }
+        writer.write_frame(opcode, &payload).await?;
+        Ok(())
+    }
+
+    /// Start the closing handshake, does nothing if it was started already.
+    pub async fn close(&self, frame: Option<CloseFrame>) -> crate::Result<()> {
+        if frame
+            .as_ref()
+            .is_some_and(|frame| frame.reason.len() > MAX_CONTROL_PAYLOAD - 2)
+        {
+            return Err(http_types::Error::from_str(
+                StatusCode::InternalServerError,
+                "close reasons are limited to 123 bytes",
+            ));
+        }
+
+        let mut writer = self.writer.lock().await;
+        if !writer.closed {
+            writer.write_close(frame.as_ref()).await?;
+        }
+        Ok(())
+    }
+
+    /// Answer the client's close frame if we haven't sent ours yet, then hang up.
+    async fn acknowledge_close(&self, frame: Option<&CloseFrame>) -> io::Result<()> {
+        let mut writer = self.writer.lock().await;
+        if !writer.closed {
+            let echo = frame.map(|frame| CloseFrame::new(frame.code, ""));
+            writer.write_close(echo.as_ref()).await?;
+        }
+        writer.sink.close().await
+    }
+
+    /// Fail the connection after the client broke the protocol.
+    async fn fail(&self, code: u16, reason: &str) {
+        let mut writer = self.writer.lock().await;
+        if !writer.closed {
+            let _ = writer
+                .write_close(Some(&CloseFrame::new(code, reason)))
+                .await;
+        }
+        let _ = writer.sink.close().await;
+    }
+
+    pub(crate) async fn shutdown(&self) {
+        let _ = self.writer.lock().await.sink.close().await;
+    }
+}
+
+struct MessageReader {
+    source: ReadHalf<Connection>,
+    inflater: Option<Inflater>,
+    limits: Limits,
+    /// The opcode, compression and payload of a message whose last frame is still to come.
+    fragments: Option<(OpCode, bool, Vec<u8>)>,
+}
+
+impl MessageReader {
+    async fn run(
+        mut self,
+        sender: WebSocketSender,
+        messages: channel::Sender<crate::Result<WebSocketMessage>>,
+    ) {
+        loop {
+            let message = match self.next().await {
+                Ok(Some(message)) => message,
+                Ok(None) => break,
+                Err(ReadError::Protocol(code, reason)) => {
+                    sender.fail(code, reason).await;
+                    let _ = messages
+                        .send(Err(http_types::Error::from_str(
+                            StatusCode::BadRequest,
+                            reason,
+                        )))
+                        .await;
+                    break;
+                }
+                Err(ReadError::Io(e)) => {
+                    let _ = messages.send(Err(e.into())).await;
+                    break;
+                }
+            };
+
+            match &message {
+                WebSocketMessage::Ping(payload) => {
+                    let pong = sender.send(WebSocketMessage::Pong(payload.clone())).await;
+                    // Once closing no pongs are due anymore
+                    if pong.is_err() && !sender.writer.lock().await.closed {
+                        break;
+                    }
+                }
+                WebSocketMessage::Close(frame) => {
+                    let _ = sender.acknowledge_close(frame.as_ref()).await;
+                    let _ = messages.send(Ok(message)).await;
+                    break;
+                }
+                _ => {}
+            }
+
+            // The handler dropped the connection and isn't listening anymore
+            if messages.send(Ok(message)).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// The next complete message, `None` if the client hung up without closing.
+    async fn next(&mut self) -> Result<Option<WebSocketMessage>, ReadError> {
+        loop {
+            let frame = match read_frame(&mut self.source, self.limits.max_frame_size).await? {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+
+            if frame.rsv1 && (self.inflater.is_none() || frame.opcode.is_control()) {
+                return Err(ReadError::Protocol(
+                    CLOSE_PROTOCOL_ERROR,
+                    "unexpected compression",
+                ));
+            }
+
+            match frame.opcode {
+                OpCode::Ping => return Ok(Some(WebSocketMessage::Ping(frame.payload))),
+                OpCode::Pong => return Ok(Some(WebSocketMessage::Pong(frame.payload))),
+                OpCode::Close => return parse_close(frame.payload).map(Some),
+                OpCode::Text | OpCode::Binary => {
+                    if self.fragments.is_some() {
+                        return Err(ReadError::Protocol(
+                            CLOSE_PROTOCOL_ERROR,
+                            "expected a continuation frame",
+                        ));
+                    }
+                    self.fragments = Some((frame.opcode, frame.rsv1, frame.payload));
+                }
+                OpCode::Continuation => match &mut self.fragments {
+                    Some((_, _, payload)) if !frame.rsv1 => {
+                        payload.extend_from_slice(&frame.payload)
+                    }
+                    _ => {
+                        return Err(ReadError::Protocol(
+                            CLOSE_PROTOCOL_ERROR,
+                            "unexpected continuation frame",
+                        ));
+                    }
+                },
+            }
+
+            let size = self
+                .fragments
+                .as_ref()
+                .map_or(0, |(_, _, payload)| payload.len());
+            if size > self.limits.max_message_size {
+                return Err(ReadError::Protocol(CLOSE_TOO_LARGE, "message too large"));
+            }
+
+            if frame.fin {
+                if let Some((opcode, compressed, payload)) = self.fragments.take() {
+                    return self.finish(opcode, compressed, payload).map(Some);
+                }
+            }
+        }
+    }
+
+    fn finish(
+        &mut self,
+        opcode: OpCode,
+        compressed: bool,
+        payload: Vec<u8>,
+    ) -> Result<WebSocketMessage, ReadError> {
+        let payload = match (&mut self.inflater, compressed) {
+            (Some(inflater), true) => {
+                inflater.decompress(&payload, self.limits.max_message_size)?
+            }
+            _ => payload,
+        };
+
+        if opcode == OpCode::Text {
+            let text = String::from_utf8(payload)
+                .map_err(|_| ReadError::Protocol(CLOSE_INVALID_DATA, "invalid UTF-8 in text"))?;
+            Ok(WebSocketMessage::Text(text))
+        } else {
+            Ok(WebSocketMessage::Binary(payload))
+        }
+    }
+}
+
+fn parse_close(payload: Vec<u8>) -> Result<WebSocketMessage, ReadError> {
+    match payload.len() {
+        0 => Ok(WebSocketMessage::Close(None)),
+        1 => Err(ReadError::Protocol(
+            CLOSE_PROTOCOL_ERROR,
+            "truncated close code",
+        )),
+        _ => {
+            let code = u16::from_be_bytes([payload[0], payload[1]]);
+            if !is_valid_close_code(code) {
+                return Err(ReadError::Protocol(
+                    CLOSE_PROTOCOL_ERROR,
+                    "invalid close code",
+                ));
+            }
+            let reason = String::from_utf8(payload[2..].to_vec())
+                .map_err(|_| ReadError::Protocol(CLOSE_INVALID_DATA, "invalid UTF-8 in reason"))?;
+            Ok(WebSocketMessage::Close(Some(CloseFrame::new(code, reason))))
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use std::io;
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+use super::frame::{ReadError, CLOSE_INVALID_DATA, CLOSE_TOO_LARGE};
+
+/// What every deflate block flushed with `Z_SYNC_FLUSH` ends with, left off on the wire.
+const TRAILER: [u8; 4] = [0x00, 0x00, 0xFF, 0xFF];
+
+/// The permessage-deflate parameters agreed on during the handshake (RFC 7692).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct DeflateConfig {
+    pub(crate) server_no_context_takeover: bool,
+    pub(crate) client_no_context_takeover: bool,
+}
+
+impl DeflateConfig {
+    /// Accept the first offer in `Sec-WebSocket-Extensions` we can honour. Window sizes
+    /// below the default can only be used by the client, we always compress with 15 bits.
+    pub(crate) fn negotiate<'a>(offers: impl Iterator<Item = &'a str>) -> Option<Self> {
+        offers
+            .flat_map(|offers| offers.split(','))
+            .find_map(Self::accept)
+    }
+
+    fn accept(offer: &str) -> Option<Self> {
+        let mut params = offer.split(';').map(str::trim);
+        if params.next() != Some("permessage-deflate") {
+            return None;
+        }
+
+        let mut config = Self::default();
+        for param in params {
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (param, None),
+            };
+
+            match (name, value) {
+                ("server_no_context_takeover", None) => config.server_no_context_takeover = true,
+                ("client_no_context_takeover", None) => config.client_no_context_takeover = true,
+                ("server_max_window_bits", Some("15")) => {}
+                ("client_max_window_bits", None) => {}
+                ("client_max_window_bits", Some(bits))
+                    if bits
+                        .parse::<u8>()
+                        .is_ok_and(|bits| (8..=15).contains(&bits)) => {}
+                _ => return None,
+            }
+        }
+
+        Some(config)
+    }
+
+    /// The `Sec-WebSocket-Extensions` response for this configuration.
+    pub(crate) fn response(&self) -> String {
+        let mut response = String::from("permessage-deflate");
+        if self.server_no_context_takeover {
+            response.push_str("; server_no_context_takeover");
+        }
+        if self.client_no_context_takeover {
+            response.push_str("; client_no_context_takeover");
+        }
+        response
+    }
+}
+
+pub(crate) struct Deflater {
+    compress: Compress,
+    reset: bool,
+}
+
+impl Deflater {
+    pub(crate) fn new(config: DeflateConfig) -> Self {
+        Self {
+            compress: Compress::new(Compression::default(), false),
+            reset: config.server_no_context_takeover,
+        }
+    }
+
+    pub(crate) fn compress(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let start = self.compress.total_in();
+        let mut compressed = Vec::with_capacity(data.len() / 2 + 64);
+
+        loop {
+            let consumed = (self.compress.total_in() - start) as usize;
+            self.compress
+                .compress_vec(&data[consumed..], &mut compressed, FlushCompress::Sync)
+                .map_err(io::Error::other)?;
+
+            let consumed = (self.compress.total_in() - start) as usize;
+            if consumed == data.len() && compressed.len() < compressed.capacity() {
+                break;
+            }
+            compressed.reserve(compressed.capacity().max(64));
+        }
+
+        if compressed.ends_with(&TRAILER) {
+            compressed.truncate(compressed.len() - TRAILER.len());
+        }
+        if self.reset {
+            self.compress.reset();
+        }
+        Ok(compressed)
+    }
+}
+
+pub(crate) struct Inflater {
+    decompress: Decompress,
+    reset: bool,
+}
+
+impl Inflater {
+    pub(crate) fn new(config: DeflateConfig) -> Self {
+        Self {
+            decompress: Decompress::new(false),
+            reset: config.client_no_context_takeover,
+        }
+    }
+
+    /// Decompress a message, failing as soon as it inflates to more than `max_size` bytes.
+    pub(crate) fn decompress(
+        &mut self,
+        data: &[u8],
+        max_size: usize,
+    ) -> Result<Vec<u8>, ReadError> {
+        let mut input = Vec::with_capacity(data.len() + TRAILER.len());
+        input.extend_from_slice(data);
+        input.extend_from_slice(&TRAILER);
+
+        let start = self.decompress.total_in();
+        let mut decompressed = Vec::with_capacity((data.len() * 4).min(max_size + 1).max(64));
+
+        loop {
+            let consumed = (self.decompress.total_in() - start) as usize;
+            let status = self
+                .decompress
+                .decompress_vec(&input[consumed..], &mut decompressed, FlushDecompress::Sync)
+                .map_err(|_| ReadError::Protocol(CLOSE_INVALID_DATA, "invalid compressed data"))?;
+
+            if decompressed.len() > max_size {
+                return Err(ReadError::Protocol(CLOSE_TOO_LARGE, "message too large"));
+            }
+
+            let consumed = (self.decompress.total_in() - start) as usize;
+            let has_room = decompressed.len() < decompressed.capacity();
+            if status == Status::StreamEnd
+                || (has_room && (consumed == input.len() || status == Status::BufError))
+            {
+                break;
+            }
+            if !has_room {
+                let room = decompressed
+                    .capacity()
+                    .min(max_size + 1 - decompressed.len());
+                decompressed.reserve(room.max(1));
+            }
+        }
+
+        if self.reset {
+            self.decompress.reset(false);
+        }
+        Ok(decompressed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn negotiate(offers: &[&str]) -> Option<DeflateConfig> {
+        DeflateConfig::negotiate(offers.iter().copied())
+    }
+
+    fn protocol_error<T>(result: Result<T, ReadError>) -> (u16, &'static str) {
+        match result {
+            Err(ReadError::Protocol(code, reason)) => (code, reason),
+            _ => panic!("expected a protocol error"),
+        }
+    }
+
+    #[test]
+    fn the_first_acceptable_offer_wins() {
+        assert_eq!(
+            negotiate(&["permessage-deflate"]),
+            Some(DeflateConfig::default())
+        );
+        assert_eq!(
+            negotiate(&[
+                "x-webkit-deflate-frame",
+                "permessage-deflate; server_max_window_bits=10, permessage-deflate; client_no_context_takeover"
+            ]),
+            Some(DeflateConfig {
+                server_no_context_takeover: false,
+                client_no_context_takeover: true,
+            })
+        );
+        assert_eq!(
+            negotiate(&[
+                "permessage-deflate; server_no_context_takeover; client_max_window_bits=\"12\""
+            ]),
+            Some(DeflateConfig {
+                server_no_context_takeover: true,
+                client_no_context_takeover: false,
+            })
+        );
+    }
+
+    #[test]
+    fn offers_we_cannot_honour_are_declined() {
+        assert_eq!(negotiate(&[]), None);
+        assert_eq!(negotiate(&["x-webkit-deflate-frame"]), None);
+        assert_eq!(
+            negotiate(&["permessage-deflate; server_max_window_bits=10"]),
+            None
+        );
+        assert_eq!(
+            negotiate(&["permessage-deflate; client_max_window_bits=7"]),
+            None
+        );
+        assert_eq!(
+            negotiate(&["permessage-deflate; server_no_context_takeover=1"]),
+            None
+        );
+        assert_eq!(negotiate(&["permessage-deflate; mystery"]), None);
+    }
+
+    #[test]
+    fn responses_echo_the_agreed_parameters() {
+        assert_eq!(DeflateConfig::default().response(), "permessage-deflate");
+        let config = DeflateConfig {
+            server_no_context_takeover: true,
+            client_no_context_takeover: true,
+        };
+        assert_eq!(
+            config.response(),
+            "permessage-deflate; server_no_context_takeover; client_no_context_takeover"
+        );
+    }
+
+    #[test]
+    fn messages_round_trip_without_the_trailer() {
+        let config = DeflateConfig::default();
+        let mut deflater = Deflater::new(config);
+        let mut inflater = Inflater::new(config);
+
+        let message = "Hello, hello, hello, hello!".repeat(100);
+        let compressed = deflater.compress(message.as_bytes()).unwrap();
+        assert!(!compressed.ends_with(&TRAILER));
+        assert!(compressed.len() < message.len());
+        assert_eq!(
+            inflater.decompress(&compressed, message.len()).unwrap(),
+            message.as_bytes()
+        );
+
+        // the shared window makes the repeat cheaper and still readable
+        let again = deflater.compress(message.as_bytes()).unwrap();
+        assert!(again.len() < compressed.len());
+        assert_eq!(
+            inflater.decompress(&again, message.len()).unwrap(),
+            message.as_bytes()
+        );
+    }
+
+    #[test]
+    fn no_context_takeover_compresses_messages_independently() {
+        let config = DeflateConfig {
+            server_no_context_takeover: true,
+            client_no_context_takeover: true,
+        };
+        let mut deflater = Deflater::new(config);
+
+        let first = deflater.compress(b"the same message").unwrap();
+        let second = deflater.compress(b"the same message").unwrap();
+        assert_eq!(first, second);
+
+        // a fresh inflater can read either of them
+        for compressed in [first, second] {
+            let mut inflater = Inflater::new(DeflateConfig::default());
+            assert_eq!(
+                inflater.decompress(&compressed, 1024).unwrap(),
+                b"the same message"
+            );
+        }
+    }
+
+    #[test]
+    fn empty_and_incompressible_messages_round_trip() {
+        let config = DeflateConfig::default();
+        let mut deflater = Deflater::new(config);
+        let mut inflater = Inflater::new(config);
+
+        let empty = deflater.compress(b"").unwrap();
+        assert!(inflater.decompress(&empty, 0).unwrap().is_empty());
+
+        let noise: Vec<u8> = (0..50_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        let compressed = deflater.compress(&noise).unwrap();
+        assert_eq!(
+            inflater.decompress(&compressed, noise.len()).unwrap(),
+            noise
+        );
+    }
+
+    #[test]
+    fn inflating_past_the_limit_fails() {
+        let mut deflater = Deflater::new(DeflateConfig::default());
+        let bomb = deflater.compress(&vec![0; 1 << 20]).unwrap();
+        assert!(bomb.len() < 4096);
+
+        let mut inflater = Inflater::new(DeflateConfig::default());
+        assert_eq!(
+            protocol_error(inflater.decompress(&bomb, 1000)),
+            (CLOSE_TOO_LARGE, "message too large")
+        );
+    }
+
+    #[test]
+    fn garbage_is_invalid_data() {
+        let mut inflater = Inflater::new(DeflateConfig::default());
+        assert_eq!(
+            protocol_error(inflater.decompress(&[0xFF; 16], 1024)),
+            (CLOSE_INVALID_DATA, "invalid compressed data")
+        );
+    }
+}
@@ -0,0 +1,340 @@
+use std::{sync::Arc, time::Duration};
+
+use async_std::{future, task};
+use async_trait::async_trait;
+use futures_core::Future;
+use http_types::{headers, StatusCode};
+use kv_log_macro::{error, warn};
+use sha1::{Digest, Sha1};
+
+use super::{
+    connection::Limits,
+    deflate::DeflateConfig,
+    frame::{CLOSE_INTERNAL_ERROR, CLOSE_NORMAL},
+    CloseFrame, WebSocketConnection,
+};
+use crate::{Endpoint, Request, Response};
+
+pub(crate) const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+pub(crate) const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+/// How long the client gets to answer the close frame sent after the handler returned.
+pub(crate) const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Appended to `Sec-WebSocket-Key` before hashing it into `Sec-WebSocket-Accept`.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// An endpoint accepting WebSocket connections and handing them to `handler`.
+///
+/// The handshake answer goes through the route's middleware like any response, the
+/// handler runs in its own task once the connection was upgraded.
+pub struct WebSocketEndpoint<F> {
+    handler: Arc<F>,
+    protocols: Vec<String>,
+    permessage_deflate: bool,
+    limits: Limits,
+}
+
+impl<F, Fut> WebSocketEndpoint<F>
+where
+    F: Fn(Request, WebSocketConnection) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = crate::Result<()>> + Send + 'static,
+{
+    pub fn new(handler: F) -> Self {
+        Self {
+            handler: Arc::new(handler),
+            protocols: Vec::new(),
+            permessage_deflate: true,
+            limits: Limits {
+                max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+                max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            },
+        }
+    }
+}
+
+impl<F> WebSocketEndpoint<F> {
+    /// The subprotocols spoken, in order of preference. The first one the client offers is
+    /// picked, clients offering none of them are still accepted without a subprotocol.
+    #[must_use]
+    pub fn protocols<I, S>(mut self, protocols: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.protocols = protocols.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Whether the permessage-deflate extension is accepted when the client offers it.
+    #[must_use]
+    pub fn permessage_deflate(mut self, permessage_deflate: bool) -> Self {
+        self.permessage_deflate = permessage_deflate;
+        self
+    }
+
+    /// Frames larger than this fail the connection with `1009 Message Too Big`.
+    #[must_use]
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.limits.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// Messages larger than this, once reassembled and decompressed, fail the connection
+    /// with `1009 Message Too Big`.
+    #[must_use]
+    pub fn max_message_size(mut self, max_message_size: usize) -> Self {
+        self.limits.max_message_size = max_message_size;
+        self
+    }
+
+    fn negotiate_protocol(&self, req: &Request) -> Option<String> {
+        let offered = req.header("Sec-WebSocket-Protocol")?;
+        let offered: Vec<&str> = offered
+            .iter()
+            .flat_map(|value| value.as_str().split(','))
+            .map(str::trim)
+            .collect();
+
+        self.protocols
+            .iter()
+            .find(|protocol| offered.contains(&protocol.as_str()))
+            .cloned()
+    }
+}
+
+#[async_trait]
+impl<F, Fut> Endpoint for WebSocketEndpoint<F>
+where
+    F: Fn(Request, WebSocketConnection) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = crate::Result<()>> + Send + 'static,
+{
+    async fn call(&self, req: Request) -> crate::Result {
+        if !has_token(&req, headers::UPGRADE, "websocket")
+            || !has_token(&req, headers::CONNECTION, "upgrade")
+        {
+            let mut res = Response::new(StatusCode::UpgradeRequired);
+            res.insert_header(headers::UPGRADE, "websocket");
+            res.insert_header(headers::CONNECTION, "Upgrade");
+            return Ok(res);
+        }
+
+        let version = req
+            .header("Sec-WebSocket-Version")
+            .map(|version| version.last().as_str().trim());
+        if version != Some("13") {
+            let mut res = Response::new(StatusCode::UpgradeRequired);
+            res.insert_header("Sec-WebSocket-Version", "13");
+            return Ok(res);
+        }
+
+        let key = match req.header("Sec-WebSocket-Key") {
+            Some(key) if key.iter().count() == 1 => key.last().as_str().trim().to_owned(),
+            _ => return Ok(Response::new(StatusCode::BadRequest)),
+        };
+        if base64::decode(&key).map_or(true, |nonce| nonce.len() != 16) {
+            return Ok(Response::new(StatusCode::BadRequest));
+        }
+
+        let protocol = self.negotiate_protocol(&req);
+        let deflate = if self.permessage_deflate {
+            req.header("Sec-WebSocket-Extensions")
+                .and_then(|offers| DeflateConfig::negotiate(offers.iter().map(|v| v.as_str())))
+        } else {
+            None
+        };
+
+        let mut res = Response::new(StatusCode::SwitchingProtocols);
+        res.insert_header(headers::UPGRADE, "websocket");
+        res.insert_header(headers::CONNECTION, "Upgrade");
+        res.insert_header("Sec-WebSocket-Accept", accept_key(&key));
+        if let Some(protocol) = &protocol {
+            res.insert_header("Sec-WebSocket-Protocol", protocol.as_str());
+        }
+        if let Some(deflate) = &deflate {
+            res.insert_header("Sec-WebSocket-Extensions", deflate.response());
+        }
+
+        let upgrade = res.res.recv_upgrade().await;
+        let handler = self.handler.clone();
+        let limits = self.limits;
+        let path = req.url().path().to_owned();
+
+        task::spawn(async move {
+            let connection = match upgrade.await {
+                Some(connection) => connection,
+                None => {
+                    warn!("WebSocket handshake answered without upgrading", { path: path });
+                    return;
+                }
+            };
+
+            let (connection, mut reading) =
+                WebSocketConnection::start(connection, protocol, deflate, limits);
+            let sender = connection.sender();
+
+            let close = match (handler)(req, connection).await {
+                Ok(()) => CloseFrame::new(CLOSE_NORMAL, ""),
+                Err(e) => {
+                    error!("WebSocket handler failed", {
+                        path: path,
+                        error: e.to_string(),
+                    });
+                    CloseFrame::new(CLOSE_INTERNAL_ERROR, "")
+                }
+            };
+
+            // Give the client a chance to answer before hanging up on it
+            let _ = sender.close(Some(close)).await;
+            if future::timeout(CLOSE_TIMEOUT, &mut reading).await.is_err() {
+                warn!("WebSocket client didn't answer the close frame", { path: path });
+                reading.cancel().await;
+            }
+            sender.shutdown().await;
+        });
+
+        Ok(res)
+    }
+}
+
+/// Whether a comma separated header contains `token`, ignoring case.
+fn has_token(req: &Request, name: headers::HeaderName, token: &str) -> bool {
+    req.header(name).is_some_and(|values| {
+        values.iter().any(|value| {
+            value
+                .as_str()
+                .split(',')
+                .any(|part| part.trim().eq_ignore_ascii_case(token))
+        })
+    })
+}
+
+fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(ACCEPT_GUID.as_bytes());
+    base64::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use http_types::{Method, Request as HttpRequest, Response as HttpResponse, Url};
+
+    use super::*;
+    use crate::Server;
+
+    /// The sample nonce from RFC 6455 section 1.3.
+    const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+    fn server(endpoint: impl Endpoint) -> Server {
+        let mut app = Server::new();
+        app.at("/ws").get(endpoint);
+        app
+    }
+
+    async fn handler(_: Request, _: WebSocketConnection) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn handshake(headers: &[(&str, &str)]) -> HttpRequest {
+        let mut req = HttpRequest::new(Method::Get, Url::parse("http://localhost/ws").unwrap());
+        req.insert_header("Upgrade", "websocket");
+        req.insert_header("Connection", "keep-alive, Upgrade");
+        req.insert_header("Sec-WebSocket-Version", "13");
+        req.insert_header("Sec-WebSocket-Key", KEY);
+        for (name, value) in headers {
+            req.insert_header(*name, *value);
+        }
+        req
+    }
+
+    #[test]
+    fn accept_keys_follow_the_rfc() {
+        assert_eq!(accept_key(KEY), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[async_std::test]
+    async fn valid_handshakes_switch_protocols() {
+        let app = server(WebSocketEndpoint::new(handler));
+        let res: HttpResponse = app.respond(handshake(&[])).await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::SwitchingProtocols);
+        assert_eq!(res.header(headers::UPGRADE).unwrap(), "websocket");
+        assert_eq!(res.header(headers::CONNECTION).unwrap(), "Upgrade");
+        assert_eq!(
+            res.header("Sec-WebSocket-Accept").unwrap(),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert!(res.header("Sec-WebSocket-Protocol").is_none());
+        assert!(res.header("Sec-WebSocket-Extensions").is_none());
+    }
+
+    #[async_std::test]
+    async fn plain_requests_are_told_to_upgrade() {
+        let app = server(WebSocketEndpoint::new(handler));
+        let req = HttpRequest::new(Method::Get, Url::parse("http://localhost/ws").unwrap());
+        let res: HttpResponse = app.respond(req).await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::UpgradeRequired);
+        assert_eq!(res.header(headers::UPGRADE).unwrap(), "websocket");
+    }
+
+    #[async_std::test]
+    async fn other_versions_are_told_which_one_we_speak() {
+        let app = server(WebSocketEndpoint::new(handler));
+        let res: HttpResponse = app
+            .respond(handshake(&[("Sec-WebSocket-Version", "8")]))
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::UpgradeRequired);
+        assert_eq!(res.header("Sec-WebSocket-Version").unwrap(), "13");
+    }
+
+    #[async_std::test]
+    async fn malformed_keys_are_bad_requests() {
+        for key in ["", "not base64!", "c2hvcnQ="] {
+            let app = server(WebSocketEndpoint::new(handler));
+            let res: HttpResponse = app
+                .respond(handshake(&[("Sec-WebSocket-Key", key)]))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::BadRequest, "{:?}", key);
+        }
+    }
+
+    #[async_std::test]
+    async fn the_preferred_offered_protocol_is_picked() {
+        let app = server(WebSocketEndpoint::new(handler).protocols(["v2.chat", "v1.chat"]));
+        let res: HttpResponse = app
+            .respond(handshake(&[("Sec-WebSocket-Protocol", "v1.chat, v2.chat")]))
+            .await
+            .unwrap();
+        assert_eq!(res.header("Sec-WebSocket-Protocol").unwrap(), "v2.chat");
+
+        let res: HttpResponse = app
+            .respond(handshake(&[("Sec-WebSocket-Protocol", "v3.chat")]))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::SwitchingProtocols);
+        assert!(res.header("Sec-WebSocket-Protocol").is_none());
+    }
+
+    #[async_std::test]
+    async fn permessage_deflate_is_negotiated_unless_disabled() {
+        let offer = [(
+            "Sec-WebSocket-Extensions",
+            "permessage-deflate; client_max_window_bits",
+        )];
+
+        let app = server(WebSocketEndpoint::new(handler));
+        let res: HttpResponse = app.respond(handshake(&offer)).await.unwrap();
+        assert_eq!(
+            res.header("Sec-WebSocket-Extensions").unwrap(),
+            "permessage-deflate"
+        );
+
+        let app = server(WebSocketEndpoint::new(handler).permessage_deflate(false));
+        let res: HttpResponse = app.respond(handshake(&offer)).await.unwrap();
+        assert!(res.header("Sec-WebSocket-Extensions").is_none());
+    }
+}
@@ -0,0 +1,342 @@
+use async_std::io::{self, Read, ReadExt};
+
+/// Why reading from the connection stopped, other than the client hanging up.
+#[derive(Debug)]
+pub(crate) enum ReadError {
+    Io(io::Error),
+    /// The client broke the protocol, the connection is failed with this close code.
+    Protocol(u16, &'static str),
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+pub(crate) const CLOSE_NORMAL: u16 = 1000;
+pub(crate) const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub(crate) const CLOSE_INVALID_DATA: u16 = 1007;
+pub(crate) const CLOSE_TOO_LARGE: u16 = 1009;
+pub(crate) const CLOSE_INTERNAL_ERROR: u16 = 1011;
+
+/// Control frames can't carry more than this, and can't be fragmented.
+pub(crate) const MAX_CONTROL_PAYLOAD: usize = 125;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OpCode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl OpCode {
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0x0 => Some(OpCode::Continuation),
+            0x1 => Some(OpCode::Text),
+            0x2 => Some(OpCode::Binary),
+            0x8 => Some(OpCode::Close),
+            0x9 => Some(OpCode::Ping),
+            0xA => Some(OpCode::Pong),
+            _ => None,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            OpCode::Continuation => 0x0,
+            OpCode::Text => 0x1,
+            OpCode::Binary => 0x2,
+            OpCode::Close => 0x8,
+            OpCode::Ping => 0x9,
+            OpCode::Pong => 0xA,
+        }
+    }
+
+    pub(crate) fn is_control(self) -> bool {
+        matches!(self, OpCode::Close | OpCode::Ping | OpCode::Pong)
+    }
+}
+
+pub(crate) struct Frame {
+    pub(crate) fin: bool,
+    /// Set on the first frame of a message compressed with permessage-deflate.
+    pub(crate) rsv1: bool,
+    pub(crate) opcode: OpCode,
+    pub(crate) payload: Vec<u8>,
+}
+
+/// Read one client frame and unmask it, `None` if the client hung up between frames.
+pub(crate) async fn read_frame<R>(
+    reader: &mut R,
+    max_size: usize,
+) -> Result<Option<Frame>, ReadError>
+where
+    R: Read + Unpin,
+{
+    let mut head = [0u8; 2];
+    if reader.read(&mut head[..1]).await? == 0 {
+        return Ok(None);
+    }
+    reader.read_exact(&mut head[1..]).await?;
+
+    let fin = head[0] & 0x80 != 0;
+    let rsv1 = head[0] & 0x40 != 0;
+    if head[0] & 0x30 != 0 {
+        return Err(ReadError::Protocol(
+            CLOSE_PROTOCOL_ERROR,
+            "reserved bits set",
+        ));
+    }
+    let opcode = OpCode::from_bits(head[0] & 0x0F)
+        .ok_or(ReadError::Protocol(CLOSE_PROTOCOL_ERROR, "unknown opcode"))?;
+
+    // RFC 6455 5.1, clients always mask so that proxies can't be fed forged requests
+    if head[1] & 0x80 == 0 {
+        return Err(ReadError::Protocol(
+            CLOSE_PROTOCOL_ERROR,
+            "unmasked client frame",
+        ));
+    }
+
+    let len = match head[1] & 0x7F {
+        126 => {
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len).await?;
+            u16::from_be_bytes(len) as u64
+        }
+        127 => {
+            let mut len = [0u8; 8];
+            reader.read_exact(&mut len).await?;
+            u64::from_be_bytes(len)
+        }
+        len => len as u64,
+    };
+
+    if opcode.is_control() && (!fin || len > MAX_CONTROL_PAYLOAD as u64) {
+        return Err(ReadError::Protocol(
+            CLOSE_PROTOCOL_ERROR,
+            "invalid control frame",
+        ));
+    }
+    if len > max_size as u64 {
+        return Err(ReadError::Protocol(CLOSE_TOO_LARGE, "frame too large"));
+    }
+
+    let mut mask = [0u8; 4];
+    reader.read_exact(&mut mask).await?;
+
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).await?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+
+    Ok(Some(Frame {
+        fin,
+        rsv1,
+        opcode,
+        payload,
+    }))
+}
+
+/// A complete, unmasked server frame.
+pub(crate) fn encode_frame(opcode: OpCode, rsv1: bool, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | if rsv1 { 0x40 } else { 0 } | opcode.bits());
+
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::io::Cursor;
+
+    use super::*;
+
+    const MASK: [u8; 4] = [0x37, 0xFA, 0x21, 0x3D];
+
+    /// A client frame as it appears on the wire, masked with `MASK`.
+    fn client_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![first];
+        match payload.len() {
+            len if len < 126 => frame.push(0x80 | len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&MASK);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ MASK[i % 4]));
+        frame
+    }
+
+    async fn read(bytes: Vec<u8>, max_size: usize) -> Result<Option<Frame>, ReadError> {
+        read_frame(&mut Cursor::new(bytes), max_size).await
+    }
+
+    fn protocol_error(result: Result<Option<Frame>, ReadError>) -> (u16, &'static str) {
+        match result {
+            Err(ReadError::Protocol(code, reason)) => (code, reason),
+            Err(ReadError::Io(e)) => panic!("expected a protocol error, got {}", e),
+            Ok(_) => panic!("expected a protocol error"),
+        }
+    }
+
+    #[async_std::test]
+    async fn masked_frames_are_unmasked() {
+        let frame = read(client_frame(0x81, b"Hello"), 1024)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(frame.fin);
+        assert!(!frame.rsv1);
+        assert_eq!(frame.opcode, OpCode::Text);
+        assert_eq!(frame.payload, b"Hello");
+
+        let frame = read(client_frame(0x42, b"part"), 1024)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!frame.fin);
+        assert!(frame.rsv1);
+        assert_eq!(frame.opcode, OpCode::Binary);
+    }
+
+    #[async_std::test]
+    async fn extended_lengths_are_read() {
+        for len in [125, 126, u16::MAX as usize, u16::MAX as usize + 1] {
+            let payload = vec![b'x'; len];
+            let frame = read(client_frame(0x82, &payload), 1 << 20)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(frame.payload, payload, "length {}", len);
+        }
+    }
+
+    #[async_std::test]
+    async fn hanging_up_between_frames_is_not_an_error() {
+        assert!(read(Vec::new(), 1024).await.unwrap().is_none());
+    }
+
+    #[async_std::test]
+    async fn truncated_frames_are_io_errors() {
+        let frame = client_frame(0x81, b"Hello");
+        for len in 1..frame.len() {
+            match read(frame[..len].to_vec(), 1024).await {
+                Err(ReadError::Io(e)) => {
+                    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof, "length {}", len)
+                }
+                _ => panic!("expected an unexpected eof at length {}", len),
+            }
+        }
+    }
+
+    #[async_std::test]
+    async fn protocol_violations_fail_the_connection() {
+        assert_eq!(
+            protocol_error(read(client_frame(0x91, b""), 1024).await),
+            (CLOSE_PROTOCOL_ERROR, "reserved bits set")
+        );
+        assert_eq!(
+            protocol_error(read(client_frame(0x83, b""), 1024).await),
+            (CLOSE_PROTOCOL_ERROR, "unknown opcode")
+        );
+
+        let mut unmasked = client_frame(0x81, b"");
+        unmasked[1] &= 0x7F;
+        assert_eq!(
+            protocol_error(read(unmasked, 1024).await),
+            (CLOSE_PROTOCOL_ERROR, "unmasked client frame")
+        );
+    }
+
+    #[async_std::test]
+    async fn control_frames_are_short_and_unfragmented() {
+        let ping = read(client_frame(0x89, &[0; MAX_CONTROL_PAYLOAD]), 1024)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(ping.opcode, OpCode::Ping);
+
+        assert_eq!(
+            protocol_error(read(client_frame(0x89, &[0; MAX_CONTROL_PAYLOAD + 1]), 1024).await),
+            (CLOSE_PROTOCOL_ERROR, "invalid control frame")
+        );
+        assert_eq!(
+            protocol_error(read(client_frame(0x08, b""), 1024).await),
+            (CLOSE_PROTOCOL_ERROR, "invalid control frame")
+        );
+    }
+
+    #[async_std::test]
+    async fn oversized_frames_are_refused_before_reading_them() {
+        let mut frame = client_frame(0x82, &[0; 17]);
+        frame.truncate(2 + MASK.len());
+        assert_eq!(
+            protocol_error(read(frame, 16).await),
+            (CLOSE_TOO_LARGE, "frame too large")
+        );
+
+        let mut huge = vec![0x82, 0x80 | 127];
+        huge.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert_eq!(
+            protocol_error(read(huge, 16).await),
+            (CLOSE_TOO_LARGE, "frame too large")
+        );
+    }
+
+    #[test]
+    fn server_frames_are_unmasked_with_minimal_lengths() {
+        assert_eq!(
+            encode_frame(OpCode::Text, false, b"Hi"),
+            [0x81, 0x02, b'H', b'i']
+        );
+        assert_eq!(encode_frame(OpCode::Pong, false, b""), [0x8A, 0x00]);
+        assert_eq!(encode_frame(OpCode::Binary, true, b"")[0], 0xC2);
+
+        let frame = encode_frame(OpCode::Binary, false, &[0; 126]);
+        assert_eq!(frame[..4], [0x82, 126, 0x00, 126]);
+        assert_eq!(frame.len(), 4 + 126);
+
+        let len = u16::MAX as usize + 1;
+        let frame = encode_frame(OpCode::Binary, false, &vec![0; len]);
+        assert_eq!(frame[..2], [0x82, 127]);
+        assert_eq!(frame[2..10], (len as u64).to_be_bytes());
+        assert_eq!(frame.len(), 10 + len);
+    }
+
+    #[async_std::test]
+    async fn encoded_frames_read_back_once_masked() {
+        let encoded = encode_frame(OpCode::Close, false, &[0x03, 0xE8]);
+        let mut masked = vec![encoded[0], encoded[1] | 0x80];
+        masked.extend_from_slice(&[0; 4]);
+        masked.extend_from_slice(&encoded[2..]);
+
+        let frame = read(masked, 1024).await.unwrap().unwrap();
+        assert_eq!(frame.opcode, OpCode::Close);
+        assert_eq!(frame.payload, CLOSE_NORMAL.to_be_bytes());
+    }
+}
@@ -0,0 +1,62 @@
+/// A message received from or sent to a WebSocket client.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WebSocketMessage {
+    Text(String),
+    Binary(Vec<u8>),
+    /// Answered with a pong automatically, passed on for information.
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The client started or answered the closing handshake, nothing follows it.
+    Close(Option<CloseFrame>),
+}
+
+/// The status code and reason carried by a close frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+impl CloseFrame {
+    pub fn new(code: u16, reason: impl Into<String>) -> Self {
+        Self {
+            code,
+            reason: reason.into(),
+        }
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut payload = self.code.to_be_bytes().to_vec();
+        payload.extend_from_slice(self.reason.as_bytes());
+        payload
+    }
+}
+
+/// Codes a client may send, the rest are reserved or meant for local use only.
+pub(crate) fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn close_frames_encode_code_then_reason() {
+        assert_eq!(CloseFrame::new(1000, "").encode(), [0x03, 0xE8]);
+        assert_eq!(
+            CloseFrame::new(4001, "bye").encode(),
+            [0x0F, 0xA1, b'b', b'y', b'e']
+        );
+    }
+
+    #[test]
+    fn reserved_close_codes_are_invalid() {
+        for code in [1000, 1001, 1003, 1007, 1011, 1014, 3000, 4999] {
+            assert!(is_valid_close_code(code), "{}", code);
+        }
+        for code in [0, 999, 1004, 1005, 1006, 1015, 2999, 5000] {
+            assert!(!is_valid_close_code(code), "{}", code);
+        }
+    }
+}
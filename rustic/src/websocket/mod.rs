@@ -0,0 +1,9 @@
+mod connection;
+mod deflate;
+mod endpoint;
+mod frame;
+mod message;
+
+pub use connection::{WebSocketConnection, WebSocketSender};
+pub use endpoint::WebSocketEndpoint;
+pub use message::{CloseFrame, WebSocketMessage};